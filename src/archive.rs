@@ -0,0 +1,303 @@
+//! Several files in one archive, extracted into a directory.
+//!
+//! An archive is:
+//!
+//! ```text
+//! magic    4 bytes  "RFAR"
+//! version  u8
+//! entries  until the end, each:
+//!          path length  varint
+//!          path         UTF-8, relative, components separated by `/`
+//!          data length  varint
+//!          data         a container member holding the file
+//! ```
+//!
+//! Its magic differs from a member's, so [`is_archive`] tells the two apart
+//! from the first bytes. A path may only name a file under the directory
+//! being extracted into: absolute paths and `..` components are rejected
+//! with [`RuffmanError::UnsafePath`] when writing and when extracting.
+
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{container, container::Metadata, files, varint, Huffman, RuffmanError};
+
+pub const MAGIC: [u8; 4] = *b"RFAR";
+const VERSION: u8 = 1;
+
+/// One file of an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Relative to the directory the archive is extracted into, with `/`
+    /// between components.
+    pub path: String,
+    pub data: Vec<u8>,
+    /// Unix permission bits to restore, if recorded.
+    pub mode: Option<u32>,
+}
+
+/// Whether `bytes` starts like an archive rather than a single compressed
+/// file.
+pub fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Writes `entries` as an archive, each compressed on its own, in text mode
+/// if it is UTF-8 and byte mode otherwise. Returns the number of bytes
+/// written.
+pub fn write<W: Write>(entries: &[Entry], out: &mut W) -> io::Result<u64> {
+    write_with_progress(entries, |_, _, _| {}, out)
+}
+
+/// Like [`write()`], calling `progress` with the entry's number, counting from
+/// 1, the number of entries and the entry's path before compressing each, so
+/// a caller can report `[3/10] compressing src/foo.rs`.
+pub fn write_with_progress<W: Write>(
+    entries: &[Entry],
+    mut progress: impl FnMut(usize, usize, &str),
+    out: &mut W,
+) -> io::Result<u64> {
+    out.write_all(&MAGIC)?;
+    out.write_all(&[VERSION])?;
+    let mut written = MAGIC.len() as u64 + 1;
+
+    for (i, entry) in entries.iter().enumerate() {
+        safe_path(&entry.path)?;
+        progress(i + 1, entries.len(), &entry.path);
+
+        let mut huffman = match std::str::from_utf8(&entry.data) {
+            Ok(text) => Huffman::from_input(text.to_owned()),
+            Err(_) => Huffman::from_bytes(&entry.data),
+        };
+        huffman.compress();
+        let mut metadata = Metadata::default();
+        metadata.mode = entry.mode;
+        let mut member = Vec::new();
+        let bits = huffman.get_compressed();
+        container::write_member_or_stored(&huffman, &bits, &metadata, 1, &mut member)?;
+
+        let mut bytes = Vec::new();
+        varint::write(entry.path.len() as u64, &mut bytes);
+        bytes.extend(entry.path.as_bytes());
+        varint::write(member.len() as u64, &mut bytes);
+        bytes.extend(member);
+        out.write_all(&bytes)?;
+        written += bytes.len() as u64;
+    }
+
+    Ok(written)
+}
+
+/// Decodes every entry of an archive, checking each path is safe to extract.
+pub fn read(bytes: &[u8]) -> Result<Vec<Entry>, RuffmanError> {
+    if !is_archive(bytes) {
+        return Err(RuffmanError::InvalidMagic);
+    }
+    let version = *bytes.get(MAGIC.len()).ok_or(RuffmanError::Truncated)?;
+    if version != VERSION {
+        return Err(RuffmanError::UnsupportedVersion(version));
+    }
+
+    let mut entries = Vec::new();
+    let mut pos = MAGIC.len() + 1;
+    let field = |pos: &mut usize| {
+        let len = varint::read(bytes, pos)? as usize;
+        let end = pos.checked_add(len).ok_or(RuffmanError::Truncated)?;
+        let field = bytes.get(*pos..end).ok_or(RuffmanError::Truncated)?;
+        *pos = end;
+        Ok::<_, RuffmanError>(field)
+    };
+    while pos < bytes.len() {
+        let path = String::from_utf8(field(&mut pos)?.to_vec())
+            .map_err(|_| RuffmanError::CorruptMetadata)?;
+        safe_path(&path)?;
+
+        let (data, metadata) = container::decompress_with_metadata(field(&mut pos)?)?;
+        entries.push(Entry {
+            path,
+            data,
+            mode: metadata.mode,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Extracts an archive under `dir`, creating it and any subdirectories, and
+/// returns the paths written. Every entry is decoded and every target path
+/// checked before anything is written: a path that exists already, or that
+/// two entries share, fails the whole extraction.
+pub fn extract(bytes: &[u8], dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let entries = read(bytes)?;
+
+    let mut paths = Vec::with_capacity(entries.len());
+    let mut seen = HashSet::new();
+    for entry in &entries {
+        let path = dir.join(safe_path(&entry.path)?);
+        if !seen.insert(path.clone()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} appears twice in the archive", entry.path),
+            ));
+        }
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        paths.push(path);
+    }
+
+    for (entry, path) in entries.iter().zip(&paths) {
+        std::fs::create_dir_all(files::parent_dir(path))?;
+        files::write_atomic(path, entry.mode, false, |file| file.write_all(&entry.data))?;
+    }
+
+    Ok(paths)
+}
+
+/// Collects every file under `dir`, recursively, as entries with paths
+/// relative to `dir` and their permissions recorded. Sorted by path, so the
+/// same tree always archives the same way.
+pub fn entries_from_dir(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(next) = pending.pop() {
+        for item in std::fs::read_dir(&next)? {
+            let item = item?;
+            let path = item.path();
+            if item.file_type()?.is_dir() {
+                pending.push(path);
+                continue;
+            }
+
+            let relative = path.strip_prefix(dir).unwrap();
+            let parts = relative
+                .components()
+                .map(|part| part.as_os_str().to_str())
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} is not a UTF-8 path", path.display()),
+                    )
+                })?;
+            entries.push(Entry {
+                path: parts.join("/"),
+                data: std::fs::read(&path)?,
+                mode: files::file_mode(&path)?,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(entries)
+}
+
+/// `path` as a relative path that stays under the directory it's joined to.
+fn safe_path(path: &str) -> Result<PathBuf, RuffmanError> {
+    let mut safe = PathBuf::new();
+    for part in path.split('/') {
+        match Path::new(part).components().collect::<Vec<_>>()[..] {
+            [Component::Normal(part)] => safe.push(part),
+            _ => return Err(RuffmanError::UnsafePath(path.to_owned())),
+        }
+    }
+
+    Ok(safe)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn entry(path: &str, data: &[u8]) -> Entry {
+        Entry {
+            path: path.to_owned(),
+            data: data.to_vec(),
+            mode: None,
+        }
+    }
+
+    #[test]
+    fn extracts_two_members() {
+        let dir = std::env::temp_dir().join(format!("ruffman-archive-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let entries = [
+            entry("notes.txt", b"the rain in spain stays mainly in the plain"),
+            entry("sub/dir/data.bin", &[0xFF, 0x00, 0xFE, 0x80]),
+        ];
+        let mut bytes = Vec::new();
+        let written = write(&entries, &mut bytes).unwrap();
+        assert_eq!(written, bytes.len() as u64);
+        assert!(is_archive(&bytes));
+
+        let paths = extract(&bytes, &dir).unwrap();
+        assert_eq!(paths, [dir.join("notes.txt"), dir.join("sub/dir/data.bin")]);
+        for entry in &entries {
+            assert_eq!(fs::read(dir.join(&entry.path)).unwrap(), entry.data);
+        }
+        assert!(extract(&bytes, &dir).is_err());
+
+        // Nothing is written when a later entry clashes, whether with an
+        // existing file or with an earlier entry.
+        for clash in ["notes.txt", "fresh.txt"] {
+            let mut bytes = Vec::new();
+            let clashing = [entry("fresh.txt", b"new"), entry(clash, b"again")];
+            write(&clashing, &mut bytes).unwrap();
+            assert!(extract(&bytes, &dir).is_err());
+            assert!(!dir.join("fresh.txt").exists());
+        }
+
+        let mut calls = Vec::new();
+        let mut with_progress = Vec::new();
+        let progress = |i, total, path: &str| calls.push((i, total, path.to_owned()));
+        write_with_progress(&entries, progress, &mut with_progress).unwrap();
+        assert_eq!(with_progress, bytes);
+        assert_eq!(
+            calls,
+            [
+                (1, 2, "notes.txt".to_owned()),
+                (2, 2, "sub/dir/data.bin".to_owned())
+            ]
+        );
+
+        let found = entries_from_dir(&dir).unwrap();
+        let found = found.iter().map(|e| (&e.path, &e.data)).collect::<Vec<_>>();
+        let expected = entries
+            .iter()
+            .map(|e| (&e.path, &e.data))
+            .collect::<Vec<_>>();
+        assert_eq!(found, expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_path_traversal() {
+        for path in ["../evil", "a/../../evil", "/etc/evil", "", "a//b", "./a"] {
+            assert!(matches!(
+                write(&[entry(path, b"x")], &mut Vec::new()),
+                Err(e) if e.to_string().contains("escapes")
+            ));
+
+            // Written by hand, as `write` refuses to.
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(VERSION);
+            varint::write(path.len() as u64, &mut bytes);
+            bytes.extend(path.as_bytes());
+            assert!(matches!(read(&bytes), Err(RuffmanError::UnsafePath(p)) if p == path));
+        }
+
+        let mut single = Vec::new();
+        container::compress("not an archive".to_owned(), &mut single).unwrap();
+        assert!(!is_archive(&single));
+        assert!(matches!(read(&single), Err(RuffmanError::InvalidMagic)));
+    }
+}
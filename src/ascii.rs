@@ -0,0 +1,106 @@
+//! Structural trees over ASCII symbols, one byte each.
+//!
+//! When every symbol is below 0x80 it fits in a byte, and the byte's high
+//! bit is free to tell leaves from edges:
+//!
+//! ```text
+//! leaf count  u8
+//! walk        as the structural tree, except that each edge below the root
+//!             is a 0x80 byte and each leaf is its symbol as one byte, then
+//!             its weight as a LEB128 varint
+//! ```
+
+use crate::{calc_huff, check_weights, varint, Node, RuffmanError};
+
+const EDGE: u8 = 0x80;
+
+/// Whether every leaf of `tree` is an ASCII symbol, so [`write_tree`] can
+/// store it.
+pub(crate) fn fits(tree: &Node) -> bool {
+    tree.leaves().all(|leaf| leaf.symb.is_ascii())
+}
+
+/// Serializes a tree for which [`fits`] holds.
+pub(crate) fn write_tree(tree: &Node) -> Vec<u8> {
+    // At most 128 distinct symbols, so the count fits too.
+    let mut bytes = vec![tree.leaf_count() as u8];
+
+    for (node, _, bit) in tree.preorder() {
+        if bit.is_some() {
+            bytes.push(EDGE);
+        }
+        if let Node::Leaf(leaf) = node {
+            bytes.push(leaf.symb as u8);
+            varint::write(leaf.weight as u64, &mut bytes);
+        }
+    }
+
+    bytes
+}
+
+/// Parses a tree written by [`write_tree`], rebuilding it from the leaf
+/// weights as the structural tree does.
+pub(crate) fn read_tree(bytes: &[u8]) -> Result<Node, RuffmanError> {
+    let count = *bytes.first().ok_or(RuffmanError::Truncated)? as usize;
+    let mut pos = 1;
+
+    let mut leaves = Vec::new();
+    while let Some(&byte) = bytes.get(pos) {
+        pos += 1;
+        match byte {
+            EDGE => {}
+            0..EDGE => {
+                let weight = varint::read(bytes, &mut pos)?;
+                let weight = u32::try_from(weight).map_err(|_| RuffmanError::CorruptTree)?;
+                leaves.push((char::from(byte), weight));
+            }
+            _ => return Err(RuffmanError::CorruptTree),
+        }
+    }
+
+    if leaves.len() != count {
+        return Err(RuffmanError::TreeSymbolMismatch {
+            expected: count,
+            found: leaves.len(),
+        });
+    }
+    check_weights(&leaves)?;
+
+    Ok(calc_huff(leaves))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{remap, Huffman};
+
+    #[test]
+    fn round_trip_smaller_than_remapped() {
+        let all_ascii = (0..128u8).map(char::from).collect::<String>();
+
+        for input in ["", "a", "abracadabra", all_ascii.as_str()] {
+            let tree = Huffman::from_input(input.to_owned()).tree;
+            assert!(fits(&tree));
+
+            let bytes = write_tree(&tree);
+            assert_eq!(read_tree(&bytes).unwrap(), tree);
+            assert!(bytes.len() < remap::write_tree(&tree).len());
+        }
+        assert!(!fits(&Huffman::from_input("naïve".to_owned()).tree));
+    }
+
+    #[test]
+    fn rejects_weights_past_u32() {
+        let mut tree = Huffman::from_input("ab".to_owned()).tree;
+        if let Node::Internal(internal) = &mut tree {
+            for child in [&mut internal.left, &mut internal.right] {
+                if let Some(Node::Leaf(leaf)) = child.as_deref_mut() {
+                    leaf.weight = u32::MAX;
+                }
+            }
+        }
+
+        let bytes = write_tree(&tree);
+        assert!(matches!(read_tree(&bytes), Err(RuffmanError::CorruptTree)));
+    }
+}
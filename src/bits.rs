@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+
+/// Packs bits MSB-first into bytes on top of any writer.
+pub struct BitWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    current: u8,
+    filled: u8,
+    bits: u64,
+    bytes: u64,
+}
+
+impl<W: Write> BitWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(8192),
+            current: 0,
+            filled: 0,
+            bits: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        self.current = (self.current << 1) | bit as u8;
+        self.filled += 1;
+        self.bits = self
+            .bits
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("bit count overflow"))?;
+
+        if self.filled == 8 {
+            self.push_byte()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a code in the `Vec<u8>` form used by [`crate::Huffman`], one
+    /// bit per element.
+    pub fn write_bits(&mut self, bits: &[u8]) -> io::Result<()> {
+        for bit in bits {
+            self.write_bit(*bit == 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of bits written so far, not counting final padding.
+    pub fn bits_written(&self) -> u64 {
+        self.bits
+    }
+
+    /// Zero-pads the last byte, flushes, and returns the total number of
+    /// bytes written.
+    pub fn finish(mut self) -> io::Result<u64> {
+        if self.filled > 0 {
+            self.current <<= 8 - self.filled;
+            self.push_byte()?;
+        }
+
+        self.inner.write_all(&self.buf)?;
+        self.inner.flush()?;
+
+        Ok(self.bytes)
+    }
+
+    fn push_byte(&mut self) -> io::Result<()> {
+        self.buf.push(self.current);
+        self.current = 0;
+        self.filled = 0;
+        self.bytes += 1;
+
+        if self.buf.len() == self.buf.capacity() {
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calc_freq, Huffman};
+
+    #[test]
+    fn packs_msb_first() {
+        let mut out = Vec::new();
+        let mut writer = BitWriter::new(&mut out);
+        writer.write_bits(&[1, 0, 1, 1, 0, 0, 0, 0, 1]).unwrap();
+
+        assert_eq!(writer.bits_written(), 9);
+        assert_eq!(writer.finish().unwrap(), 2);
+        assert_eq!(out, vec![0b1011_0000, 0b1000_0000]);
+    }
+
+    #[test]
+    fn bit_count_matches_code_lengths() {
+        let input = "it was the best of times, it was the worst of times";
+        let mut huffman = Huffman::from_input(input.to_owned());
+        huffman.compress();
+
+        let expected: u64 = calc_freq(input.to_owned())
+            .into_iter()
+            .map(|(c, freq)| freq as u64 * huffman.char_codes[&c].len() as u64)
+            .sum();
+
+        let mut out = Vec::new();
+        let mut writer = BitWriter::new(&mut out);
+        writer.write_bits(&huffman.get_compressed()).unwrap();
+
+        assert_eq!(writer.bits_written(), expected);
+        assert_eq!(writer.finish().unwrap(), expected.div_ceil(8));
+    }
+}
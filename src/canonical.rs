@@ -0,0 +1,273 @@
+//! Canonical Huffman codes and the compact (RLE) code-length table used to
+//! store them.
+//!
+//! The table is written as:
+//!
+//! ```text
+//! range count  u32
+//! ranges       (first symbol u32, length u32) per run of consecutive symbols
+//! run count    u32
+//! runs         (code length u8, repeat u8) over the symbols in order
+//! ```
+//!
+//! Symbols are listed in code point order rather than tree order. Canonical
+//! codes depend only on each symbol's length, so the order is free to choose,
+//! and this one keeps runs of consecutive symbols in a single range and lets
+//! neighbours with equal lengths share a run.
+
+use crate::{LeafNode, Node, RuffmanError};
+
+/// Collects `(symbol, weight, code length)` for every leaf under `node`, in
+/// tree order.
+pub(crate) fn leaf_lengths(node: &Node) -> Vec<(char, u32, usize)> {
+    node.preorder()
+        .filter_map(|(node, depth, _)| match node {
+            Node::Leaf(leaf) => Some((leaf.symb, leaf.weight, depth)),
+            Node::Internal(_) => None,
+        })
+        .collect()
+}
+
+/// Assigns canonical codes to `(symbol, weight, length)` triples: shorter codes
+/// first, ties broken by symbol, each code one greater than the last.
+pub(crate) fn canonical_codes(mut lengths: Vec<(char, u32, usize)>) -> Vec<(char, u32, Vec<u8>)> {
+    lengths.sort_by_key(|(symb, _, len)| (*len, *symb));
+
+    let mut codes = Vec::with_capacity(lengths.len());
+    let mut code: Vec<u8> = Vec::new();
+
+    for (i, (symb, weight, len)) in lengths.into_iter().enumerate() {
+        if i > 0 {
+            // Increment, then pad with zeros up to the new length.
+            while let Some(bit) = code.pop() {
+                if bit == 0 {
+                    code.push(1);
+                    break;
+                }
+            }
+        }
+        code.resize(len, 0);
+
+        codes.push((symb, weight, code.clone()));
+    }
+
+    codes
+}
+
+/// Rebuilds the tree whose root-to-leaf paths are `codes`, or `None` if the
+/// codes are not prefix-free.
+pub(crate) fn tree_from_codes(codes: &[(char, u32, Vec<u8>)]) -> Option<Node> {
+    match codes {
+        [] => return None,
+        [(symb, weight, code)] if code.is_empty() => {
+            return Some(Node::Leaf(LeafNode {
+                weight: *weight,
+                symb: *symb,
+            }));
+        }
+        _ => {}
+    }
+
+    // Each code is walked down from the root, adding the internal nodes on
+    // its path, so a deep tree needs no recursion.
+    let mut root = Node::empty();
+    for (symb, weight, code) in codes {
+        let (last, path) = code.split_last()?;
+        let mut node = &mut root;
+        for &bit in path {
+            let Node::Internal(internal) = node else {
+                return None;
+            };
+            internal.weight += weight;
+            let child = match bit {
+                0 => &mut internal.left,
+                _ => &mut internal.right,
+            };
+            node = child.get_or_insert_with(|| Box::new(Node::empty()));
+        }
+
+        let Node::Internal(internal) = node else {
+            return None;
+        };
+        internal.weight += weight;
+        let slot = match last {
+            0 => &mut internal.left,
+            _ => &mut internal.right,
+        };
+        if slot.is_some() {
+            return None;
+        }
+        *slot = Some(Box::new(Node::Leaf(LeafNode {
+            weight: *weight,
+            symb: *symb,
+        })));
+    }
+
+    Some(root)
+}
+
+/// Serializes the code lengths of `tree` as an RLE table ordered by symbol.
+/// Lengths are stored in a byte, so a longer code fails with
+/// [`RuffmanError::CodeTooLong`].
+pub(crate) fn write_table(tree: &Node) -> Result<Vec<u8>, RuffmanError> {
+    let mut lengths = leaf_lengths(tree);
+    lengths.sort_by_key(|(symb, _, _)| *symb);
+
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    let mut runs: Vec<(u8, u8)> = Vec::new();
+    for (symb, _, len) in lengths {
+        let len = u8::try_from(len).map_err(|_| RuffmanError::CodeTooLong {
+            length: len,
+            max: u8::MAX as usize,
+        })?;
+        match ranges.last_mut() {
+            Some((first, count)) if *first + *count == symb as u32 => *count += 1,
+            _ => ranges.push((symb as u32, 1)),
+        }
+
+        match runs.last_mut() {
+            Some((run_len, repeat)) if *run_len == len && *repeat < u8::MAX => *repeat += 1,
+            _ => runs.push((len, 1)),
+        }
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend((ranges.len() as u32).to_be_bytes());
+    for (first, count) in ranges {
+        bytes.extend(first.to_be_bytes());
+        bytes.extend(count.to_be_bytes());
+    }
+    bytes.extend((runs.len() as u32).to_be_bytes());
+    for (len, repeat) in runs {
+        bytes.extend([len, repeat]);
+    }
+
+    Ok(bytes)
+}
+
+/// Parses a table written by [`write_table`] back into the canonical tree.
+/// Leaf weights are not stored, so they come back as zero.
+pub(crate) fn read_table(bytes: &[u8]) -> Result<Node, RuffmanError> {
+    let mut pos = 0;
+    let mut take = |n: usize| {
+        let slice = bytes.get(pos..pos + n).ok_or(RuffmanError::Truncated)?;
+        pos += n;
+        Ok::<_, RuffmanError>(slice)
+    };
+    let read_u32 = |slice: &[u8]| u32::from_be_bytes(slice.try_into().unwrap());
+
+    let range_count = read_u32(take(4)?);
+    let mut ranges = Vec::new();
+    for _ in 0..range_count {
+        let first = read_u32(take(4)?);
+        let count = read_u32(take(4)?);
+        ranges.push((first, count));
+    }
+
+    // Checked before expanding the ranges, so a small, malformed table can't
+    // demand a huge allocation: there are only so many chars, and each run
+    // of the 2-byte entries left covers at most 255 of them.
+    let total = ranges.iter().map(|(_, count)| *count as u64).sum::<u64>();
+    let remaining = (bytes.len() - 4 - 8 * ranges.len()).saturating_sub(4);
+    if total > char::MAX as u64 + 1 || total > (remaining / 2) as u64 * u8::MAX as u64 {
+        return Err(RuffmanError::CorruptTree);
+    }
+
+    let mut symbols = Vec::with_capacity(total as usize);
+    for (first, count) in ranges {
+        for offset in 0..count {
+            let value = first.checked_add(offset).ok_or(RuffmanError::CorruptTree)?;
+            let symb = char::from_u32(value).ok_or(RuffmanError::InvalidSymbol(value))?;
+            symbols.push(symb);
+        }
+    }
+
+    let mut lengths = Vec::new();
+    let run_count = read_u32(take(4)?);
+    for _ in 0..run_count {
+        let run = take(2)?;
+        lengths.extend(std::iter::repeat_n(run[0], run[1] as usize));
+    }
+
+    if symbols.len() != lengths.len() {
+        return Err(RuffmanError::TreeSymbolMismatch {
+            expected: symbols.len(),
+            found: lengths.len(),
+        });
+    }
+
+    if symbols.is_empty() {
+        return Ok(Node::empty());
+    }
+
+    let lengths = symbols
+        .into_iter()
+        .zip(lengths)
+        .map(|(symb, len)| (symb, 0, len as usize))
+        .collect();
+
+    tree_from_codes(&canonical_codes(lengths)).ok_or(RuffmanError::CorruptTree)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Huffman;
+
+    fn codes(mut lengths: Vec<(char, u32, usize)>) -> Vec<(char, Vec<u8>)> {
+        // Weights aren't stored in the table.
+        lengths.iter_mut().for_each(|(_, weight, _)| *weight = 0);
+        let mut codes = canonical_codes(lengths)
+            .into_iter()
+            .map(|(symb, _, code)| (symb, code))
+            .collect::<Vec<_>>();
+        codes.sort();
+        codes
+    }
+
+    #[test]
+    fn sorted_table_gives_same_codes() {
+        let tree = Huffman::from_input("abracadabra, alakazam!".to_owned())
+            .tree()
+            .clone();
+        let lengths = leaf_lengths(&tree);
+
+        let mut sorted = lengths.clone();
+        sorted.sort_by_key(|(symb, _, _)| *symb);
+        assert_ne!(sorted, lengths);
+        let mut reversed = lengths.clone();
+        reversed.reverse();
+        assert_eq!(codes(sorted), codes(lengths.clone()));
+        assert_eq!(codes(reversed), codes(lengths.clone()));
+
+        let rebuilt = leaf_lengths(&read_table(&write_table(&tree).unwrap()).unwrap());
+        assert_eq!(codes(rebuilt), codes(lengths));
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_table() {
+        let table = |ranges: &[(u32, u32)], runs: &[[u8; 2]]| {
+            let mut bytes = (ranges.len() as u32).to_be_bytes().to_vec();
+            for (first, count) in ranges {
+                bytes.extend(first.to_be_bytes());
+                bytes.extend(count.to_be_bytes());
+            }
+            bytes.extend((runs.len() as u32).to_be_bytes());
+            bytes.extend(runs.concat());
+            bytes
+        };
+
+        // Two symbols fit the one run left; a million don't, nor do more
+        // symbols than there are chars, however many runs follow.
+        assert!(read_table(&table(&[(0x61, 2)], &[[1, 2]])).is_ok());
+        for (ranges, runs) in [
+            (&[(0x1_0000, 0xF_0000)][..], &[[1, 2]][..]),
+            (&[(0, 0xF_0000); 2], &[[21, u8::MAX]; 0x2000]),
+        ] {
+            assert!(matches!(
+                read_table(&table(ranges, runs)),
+                Err(RuffmanError::CorruptTree)
+            ));
+        }
+    }
+}
@@ -0,0 +1,2916 @@
+//! The on-disk format.
+//!
+//! A ruffman file is one or more members back to back. Each member is:
+//!
+//! ```text
+//! magic        4 bytes  "RUFF"
+//! version      u8
+//! flags        u8       see the `FLAG_*` constants
+//! symbol count varint   chars in the decoded payload
+//! bit count    varint   payload length in bits
+//! crc          u32      CRC-32 of the decoded payload's UTF-8 bytes, or of
+//!                       the bytes themselves when `FLAG_BYTES` is set; zero
+//!                       and unchecked when the metadata says there is none
+//! tree length  varint   length of the serialized tree in bytes
+//! metadata     only when `FLAG_METADATA` is set: a u32 length, then
+//!              (tag u8, length u8, value) entries; unknown tags are skipped
+//!              and repeated prefix entries are concatenated
+//! tree         tree length bytes; structural, structural with a symbol
+//!              table when `FLAG_REMAPPED_TREE` is set, an RLE code-length
+//!              table when `FLAG_CANONICAL_TREE` is set, or structural with
+//!              one-byte ASCII symbols when both are; empty when
+//!              `FLAG_EXTERNAL_TREE` is set
+//! payload      ceil(bit count / 8) bytes, MSB first; when `FLAG_RLE` is set
+//!              it decodes to run-length encoded text, and when the metadata
+//!              names an escape symbol its code is followed by a 21-bit
+//!              literal code point; in a stored member, marked by a metadata
+//!              entry, the data itself with no tree
+//! padding      zero bytes, as many as the metadata's padding entry says
+//! trailer      only when `FLAG_TRAILER` is set: CRC-32 and length modulo
+//!              2^32 of the member's decoded data, as in gzip's trailer
+//! ```
+//!
+//! Varints are LEB128, so a small file's counts take a byte each. All other
+//! integers are big-endian, except the trailer's, which are little-endian to
+//! match gzip.
+//!
+//! Output is a pure function of the input and options: the tree doesn't
+//! depend on the order symbols are counted in, since ties between equal
+//! weights are broken by symbol, and nothing like a timestamp is recorded.
+//! The same input always compresses to the same bytes.
+//!
+//! Text is stored exactly as given. A leading byte order mark is an ordinary
+//! U+FEFF symbol, so it survives decompression, and none is ever added unless
+//! asked for with [`include_bom`].
+//!
+//! In block mode ([`write_blocks`]) the input is split into blocks, each
+//! written as its own member with `FLAG_BLOCK` set, so every block has its own
+//! tree and CRC and can be checked on its own with [`verify`].
+//! [`StreamEncoder`] writes the same blocks as text arrives, one per flush,
+//! and [`write_blocks_from_reader`] as it reads input of unknown length.
+
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+    },
+    time::Instant,
+};
+
+use bitvec::prelude::*;
+
+use crate::{
+    ascii, bits::BitWriter, canonical, crc::crc32, decode::DecodeTable, eol, eol::LineEnding,
+    indent, lz, remap, rle, structural_bytes, trained, varint, CompressionStats, Huffman,
+    HuffmanBuilder, Node, NodeBytes, RuffmanError, Serialization,
+};
+
+/// Starts every member.
+pub const MAGIC: [u8; 4] = *b"RUFF";
+/// The format version written, and the only one read; members with any
+/// other version are rejected with [`RuffmanError::UnsupportedVersion`].
+pub const FORMAT_VERSION: u8 = 2;
+
+/// The tree is stored as a canonical code-length table.
+const FLAG_CANONICAL_TREE: u8 = 1 << 0;
+/// The flags that together say how the tree is stored.
+const TREE_FORMAT: u8 = FLAG_CANONICAL_TREE | FLAG_REMAPPED_TREE;
+/// The structural tree's symbols are all ASCII, one byte each, see `ascii`.
+const FLAG_ASCII_TREE: u8 = FLAG_CANONICAL_TREE | FLAG_REMAPPED_TREE;
+/// A metadata block follows the fixed header.
+const FLAG_METADATA: u8 = 1 << 1;
+/// The member is one block of a larger input, written by [`write_blocks`];
+/// its CRC covers only this block.
+const FLAG_BLOCK: u8 = 1 << 2;
+/// Runs were collapsed before coding and are expanded after decoding.
+const FLAG_RLE: u8 = 1 << 3;
+/// The structural tree refers to symbols through a table, see `remap`.
+const FLAG_REMAPPED_TREE: u8 = 1 << 4;
+/// The symbols are bytes, U+0000 to U+00FF standing for the byte of the same
+/// value, and the payload decodes to binary data rather than text.
+const FLAG_BYTES: u8 = 1 << 5;
+/// The member ends with a gzip-style CRC-32 and size trailer.
+const FLAG_TRAILER: u8 = 1 << 6;
+/// The tree isn't stored; the member decodes only against the tree it was
+/// written with, supplied to [`decompress_with_tree`].
+const FLAG_EXTERNAL_TREE: u8 = 1 << 7;
+
+/// Length of the trailer written with [`write_member_with_trailer`].
+pub const TRAILER_LEN: usize = 8;
+
+const TAG_MODE: u8 = 1;
+const TAG_PADDING: u8 = 2;
+const TAG_PREFIX: u8 = 3;
+const TAG_ENCODING: u8 = 4;
+const TAG_ESCAPE: u8 = 5;
+const TAG_STORED: u8 = 6;
+const TAG_NORMALIZATION: u8 = 7;
+const TAG_LINE_ENDING: u8 = 8;
+const TAG_NO_CHECKSUM: u8 = 9;
+const TAG_VALUES: u8 = 10;
+const TAG_LZ: u8 = 11;
+const TAG_TREE_HASH: u8 = 12;
+const TAG_INDENT: u8 = 13;
+
+/// The fixed part of a member header, up to the metadata block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub flags: u8,
+    pub symbol_count: u64,
+    pub bit_count: u64,
+    pub crc: u32,
+    pub tree_len: u32,
+}
+
+impl Header {
+    /// Serializes the header, magic and version included, with the counts
+    /// as varints and the CRC big-endian regardless of the host.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32);
+        bytes.extend(MAGIC);
+        bytes.extend([FORMAT_VERSION, self.flags]);
+        varint::write(self.symbol_count, &mut bytes);
+        varint::write(self.bit_count, &mut bytes);
+        bytes.extend(self.crc.to_be_bytes());
+        varint::write(self.tree_len as u64, &mut bytes);
+
+        bytes
+    }
+
+    /// Serialized size in bytes, which grows with the counts.
+    pub fn encoded_len(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.to_bytes())
+    }
+
+    /// Bytes a member with this header and `metadata` holds ahead of its
+    /// payload: the header itself, the metadata block and the tree. Exact
+    /// for the metadata as written, which for an aligned member includes the
+    /// padding entry the writer adds.
+    pub fn estimated_size(&self, metadata: &Metadata) -> usize {
+        let metadata_len = match metadata.is_empty() {
+            true => 0,
+            false => 4 + metadata.to_bytes().len(),
+        };
+
+        self.encoded_len() + metadata_len + self.tree_len as usize
+    }
+
+    /// Parses a header written by [`Header::write`] from the start of
+    /// `bytes`, returning it and its length in bytes.
+    pub fn read(bytes: &[u8]) -> Result<(Self, usize), RuffmanError> {
+        let magic = bytes.get(0..4).ok_or(RuffmanError::Truncated)?;
+        if magic != MAGIC {
+            return Err(RuffmanError::InvalidMagic);
+        }
+
+        let version = *bytes.get(4).ok_or(RuffmanError::Truncated)?;
+        if version != FORMAT_VERSION {
+            return Err(RuffmanError::UnsupportedVersion(version));
+        }
+
+        let flags = *bytes.get(5).ok_or(RuffmanError::Truncated)?;
+        let mut pos = 6;
+        let symbol_count = varint::read(bytes, &mut pos)?;
+        let bit_count = varint::read(bytes, &mut pos)?;
+        let crc = bytes.get(pos..pos + 4).ok_or(RuffmanError::Truncated)?;
+        let crc = u32::from_be_bytes(crc.try_into().unwrap());
+        pos += 4;
+        let tree_len = varint::read(bytes, &mut pos)?;
+        let tree_len = u32::try_from(tree_len).map_err(|_| RuffmanError::CorruptVarint)?;
+
+        let header = Self {
+            flags,
+            symbol_count,
+            bit_count,
+            crc,
+            tree_len,
+        };
+        Ok((header, pos))
+    }
+}
+
+/// Optional facts about the original file, stored alongside the data.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Unix permission bits.
+    pub mode: Option<u32>,
+    /// Text stored uncompressed ahead of the payload, such as a shebang line.
+    pub prefix: Option<String>,
+    /// Name of the encoding the original text was in, when not UTF-8.
+    pub encoding: Option<String>,
+    /// The Unicode normalization form, such as `NFC`, the text was put in
+    /// before compression. The original text may have differed, so it
+    /// decompresses to the normalized text rather than the original bytes.
+    pub normalization: Option<String>,
+    /// The line ending the original text used throughout, turned into `\n`
+    /// before compression and restored on decompression. See [`crate::eol`].
+    pub line_ending: Option<LineEnding>,
+    /// The CRC-32 was never computed, to save the time on data that is
+    /// thrown away or checked some other way, so decoding can't check it.
+    pub no_checksum: bool,
+    /// Zero bytes after the payload, set by [`write_member_aligned`].
+    padding: Option<u32>,
+    /// The tree's escape symbol, set when the codec was built with
+    /// [`HuffmanBuilder::coded_symbols`] and had symbols left over.
+    escape: Option<char>,
+    /// The payload is the data as is, set by [`write_stored`].
+    stored: bool,
+    /// The 4-byte values a [`crate::numeric`] member's symbols stand for,
+    /// each symbol coding its value's index.
+    pub(crate) values: Option<Vec<u32>>,
+    /// The symbols went through the LZ pre-pass, set from the codec by
+    /// [`write_member`]. Every header flag is taken, so it is recorded here.
+    lz: bool,
+    /// The [`crate::trained::tree_hash`] of the tree a member written
+    /// without one was coded with, set by [`write_member_without_tree`].
+    tree_hash: Option<u32>,
+    /// The symbols went through the indentation pre-pass, set from the codec
+    /// by [`write_member`] like `lz`.
+    indent: bool,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none()
+            && self.prefix.is_none()
+            && self.encoding.is_none()
+            && self.normalization.is_none()
+            && self.line_ending.is_none()
+            && !self.no_checksum
+            && self.padding.is_none()
+            && self.escape.is_none()
+            && !self.stored
+            && self.values.is_none()
+            && !self.lz
+            && self.tree_hash.is_none()
+            && !self.indent
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if let Some(mode) = self.mode {
+            bytes.extend([TAG_MODE, 4]);
+            bytes.extend(mode.to_be_bytes());
+        }
+        if let Some(prefix) = &self.prefix {
+            // Entries hold at most 255 bytes, so long prefixes are split.
+            for chunk in prefix.as_bytes().chunks(u8::MAX as usize) {
+                bytes.extend([TAG_PREFIX, chunk.len() as u8]);
+                bytes.extend(chunk);
+            }
+        }
+        if let Some(encoding) = &self.encoding {
+            bytes.extend([TAG_ENCODING, encoding.len() as u8]);
+            bytes.extend(encoding.as_bytes());
+        }
+        if let Some(normalization) = &self.normalization {
+            bytes.extend([TAG_NORMALIZATION, normalization.len() as u8]);
+            bytes.extend(normalization.as_bytes());
+        }
+        if let Some(ending) = self.line_ending {
+            bytes.extend([TAG_LINE_ENDING, 1, ending.to_byte()]);
+        }
+        if self.no_checksum {
+            bytes.extend([TAG_NO_CHECKSUM, 0]);
+        }
+        if let Some(padding) = self.padding {
+            bytes.extend([TAG_PADDING, 4]);
+            bytes.extend(padding.to_be_bytes());
+        }
+        if let Some(escape) = self.escape {
+            bytes.extend([TAG_ESCAPE, 4]);
+            bytes.extend((escape as u32).to_be_bytes());
+        }
+        if self.stored {
+            bytes.extend([TAG_STORED, 0]);
+        }
+        if self.lz {
+            bytes.extend([TAG_LZ, 0]);
+        }
+        if self.indent {
+            bytes.extend([TAG_INDENT, 0]);
+        }
+        if let Some(hash) = self.tree_hash {
+            bytes.extend([TAG_TREE_HASH, 4]);
+            bytes.extend(hash.to_be_bytes());
+        }
+        if let Some(values) = &self.values {
+            // Split like the prefix, with an empty entry for an empty table.
+            if values.is_empty() {
+                bytes.extend([TAG_VALUES, 0]);
+            }
+            for chunk in values.chunks(u8::MAX as usize / 4) {
+                bytes.extend([TAG_VALUES, chunk.len() as u8 * 4]);
+                bytes.extend(chunk.iter().flat_map(|value| value.to_be_bytes()));
+            }
+        }
+
+        bytes
+    }
+
+    fn from_bytes(mut bytes: &[u8]) -> Result<Self, RuffmanError> {
+        let mut metadata = Self::default();
+        let mut prefix = None::<Vec<u8>>;
+
+        while let [tag, len, rest @ ..] = bytes {
+            let len = *len as usize;
+            let value = rest.get(..len).ok_or(RuffmanError::Truncated)?;
+            match (*tag, len) {
+                (TAG_MODE, 4) => {
+                    metadata.mode = Some(u32::from_be_bytes(value.try_into().unwrap()))
+                }
+                (TAG_PADDING, 4) => {
+                    metadata.padding = Some(u32::from_be_bytes(value.try_into().unwrap()))
+                }
+                (TAG_ESCAPE, 4) => {
+                    let escape = u32::from_be_bytes(value.try_into().unwrap());
+                    metadata.escape =
+                        Some(char::from_u32(escape).ok_or(RuffmanError::CorruptMetadata)?)
+                }
+                (TAG_STORED, 0) => metadata.stored = true,
+                (TAG_LZ, 0) => metadata.lz = true,
+                (TAG_INDENT, 0) => metadata.indent = true,
+                (TAG_TREE_HASH, 4) => {
+                    metadata.tree_hash = Some(u32::from_be_bytes(value.try_into().unwrap()))
+                }
+                (TAG_NO_CHECKSUM, 0) => metadata.no_checksum = true,
+                (TAG_VALUES, _) if len.is_multiple_of(4) => {
+                    let values = metadata.values.get_or_insert_with(Vec::new);
+                    values.extend(
+                        value
+                            .chunks(4)
+                            .map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap())),
+                    );
+                }
+                (TAG_VALUES, _) => return Err(RuffmanError::CorruptMetadata),
+                (TAG_LINE_ENDING, 1) => {
+                    metadata.line_ending = Some(LineEnding::from_byte(value[0])?)
+                }
+                (TAG_NORMALIZATION, _) => {
+                    metadata.normalization = Some(
+                        String::from_utf8(value.to_vec())
+                            .map_err(|_| RuffmanError::CorruptMetadata)?,
+                    )
+                }
+                (TAG_PREFIX, _) => prefix.get_or_insert_with(Vec::new).extend(value),
+                (TAG_ENCODING, _) => {
+                    metadata.encoding = Some(
+                        String::from_utf8(value.to_vec())
+                            .map_err(|_| RuffmanError::CorruptMetadata)?,
+                    )
+                }
+                _ => {}
+            }
+            bytes = &rest[len..];
+        }
+
+        if !bytes.is_empty() {
+            return Err(RuffmanError::Truncated);
+        }
+
+        if let Some(prefix) = prefix {
+            metadata.prefix =
+                Some(String::from_utf8(prefix).map_err(|_| RuffmanError::CorruptMetadata)?);
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// A decoded member.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    /// The decoded bytes: UTF-8 text, unless the member is in byte mode.
+    pub data: Vec<u8>,
+    pub metadata: Metadata,
+    /// Number of bytes the member occupied in the input.
+    pub len: usize,
+}
+
+/// The header fields of one member, as far as they could be parsed.
+#[derive(Debug, Default)]
+pub struct Layout {
+    pub magic: Option<[u8; 4]>,
+    pub version: Option<u8>,
+    pub flags: Option<u8>,
+    pub symbol_count: Option<u64>,
+    pub bit_count: Option<u64>,
+    pub crc: Option<u32>,
+    pub tree_len: Option<u32>,
+    pub metadata: Option<Metadata>,
+    pub payload_len: Option<u64>,
+    /// The trailer's CRC-32 and size, if the member has one.
+    pub trailer: Option<(u32, u32)>,
+    /// Why parsing stopped, if it did.
+    pub error: Option<RuffmanError>,
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(magic) = self.magic {
+            writeln!(f, "magic:        {:?}", String::from_utf8_lossy(&magic))?;
+        }
+        if let Some(version) = self.version {
+            writeln!(f, "version:      {}", version)?;
+        }
+        if let Some(flags) = self.flags {
+            let mut names = Vec::new();
+            match flags & TREE_FORMAT {
+                FLAG_ASCII_TREE => names.push("ascii tree"),
+                FLAG_CANONICAL_TREE => names.push("canonical tree"),
+                FLAG_REMAPPED_TREE => names.push("remapped tree"),
+                _ => {}
+            }
+            if flags & FLAG_METADATA != 0 {
+                names.push("metadata");
+            }
+            if flags & FLAG_BLOCK != 0 {
+                names.push("block");
+            }
+            if flags & FLAG_RLE != 0 {
+                names.push("rle");
+            }
+            if flags & FLAG_BYTES != 0 {
+                names.push("bytes");
+            }
+            if flags & FLAG_TRAILER != 0 {
+                names.push("trailer");
+            }
+            if flags & FLAG_EXTERNAL_TREE != 0 {
+                names.push("external tree");
+            }
+            writeln!(f, "flags:        {:#04x} ({})", flags, names.join(", "))?;
+        }
+        if let Some(symbol_count) = self.symbol_count {
+            writeln!(f, "symbols:      {}", symbol_count)?;
+        }
+        if let Some(bit_count) = self.bit_count {
+            writeln!(f, "bits:         {}", bit_count)?;
+        }
+        if let Some(crc) = self.crc {
+            writeln!(f, "crc:          {:08x}", crc)?;
+        }
+        if let Some(metadata) = &self.metadata {
+            if let Some(mode) = metadata.mode {
+                writeln!(f, "mode:         {:o}", mode)?;
+            }
+            if let Some(prefix) = &metadata.prefix {
+                writeln!(f, "prefix:       {:?}", prefix)?;
+            }
+            if let Some(encoding) = &metadata.encoding {
+                writeln!(f, "encoding:     {}", encoding)?;
+            }
+            if let Some(normalization) = &metadata.normalization {
+                writeln!(f, "normalized:   {}", normalization)?;
+            }
+            if let Some(ending) = metadata.line_ending {
+                writeln!(f, "line endings: {}", ending.name())?;
+            }
+            if metadata.no_checksum {
+                writeln!(f, "checksum:     none")?;
+            }
+            if let Some(padding) = metadata.padding {
+                writeln!(f, "padding:      {} bytes", padding)?;
+            }
+            if let Some(escape) = metadata.escape {
+                writeln!(f, "escape:       {:?}", escape)?;
+            }
+            if metadata.stored {
+                writeln!(f, "stored:       yes")?;
+            }
+            if metadata.lz {
+                writeln!(f, "lz:           yes")?;
+            }
+            if metadata.indent {
+                writeln!(f, "indent:       yes")?;
+            }
+            if let Some(hash) = metadata.tree_hash {
+                writeln!(f, "tree hash:    {:08x}", hash)?;
+            }
+            if let Some(values) = &metadata.values {
+                writeln!(f, "values:       {} distinct, 4 bytes each", values.len())?;
+            }
+        }
+        if let Some(tree_len) = self.tree_len {
+            writeln!(f, "tree:         {} bytes", tree_len)?;
+        }
+        if let Some(payload_len) = self.payload_len {
+            writeln!(f, "payload:      {} bytes", payload_len)?;
+        }
+        if let Some((crc, size)) = self.trailer {
+            writeln!(f, "trailer:      crc {:08x}, size {}", crc, size)?;
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "error:        {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the headers of every member in `bytes` without decoding them,
+/// stopping at the first error, which is recorded in the last [`Layout`].
+pub fn describe(mut bytes: &[u8]) -> Vec<Layout> {
+    let mut layouts = Vec::new();
+
+    while !bytes.is_empty() {
+        let mut layout = Layout::default();
+        let result = describe_member(bytes, &mut layout);
+        layouts.push(layout);
+
+        match result {
+            Ok(len) => bytes = &bytes[len..],
+            Err(error) => {
+                layouts.last_mut().unwrap().error = Some(error);
+                break;
+            }
+        }
+    }
+
+    layouts
+}
+
+/// Fills in `layout` field by field, returning the member's length.
+fn describe_member(bytes: &[u8], layout: &mut Layout) -> Result<usize, RuffmanError> {
+    let field = |range: std::ops::Range<usize>| bytes.get(range).ok_or(RuffmanError::Truncated);
+
+    let magic: [u8; 4] = field(0..4)?.try_into().unwrap();
+    layout.magic = Some(magic);
+    if magic != MAGIC {
+        return Err(RuffmanError::InvalidMagic);
+    }
+
+    let version = field(4..5)?[0];
+    layout.version = Some(version);
+    if version != FORMAT_VERSION {
+        return Err(RuffmanError::UnsupportedVersion(version));
+    }
+
+    let flags = field(5..6)?[0];
+    layout.flags = Some(flags);
+    let mut pos = 6;
+    layout.symbol_count = Some(varint::read(bytes, &mut pos)?);
+    let bit_count = varint::read(bytes, &mut pos)?;
+    layout.bit_count = Some(bit_count);
+    layout.crc = Some(u32::from_be_bytes(field(pos..pos + 4)?.try_into().unwrap()));
+    pos += 4;
+    let tree_len = varint::read(bytes, &mut pos)?;
+    let tree_len = u32::try_from(tree_len).map_err(|_| RuffmanError::CorruptVarint)?;
+    layout.tree_len = Some(tree_len);
+
+    let mut tree_start = pos;
+    let mut padding = 0;
+    if flags & FLAG_METADATA != 0 {
+        let len = field(tree_start..tree_start + 4)?;
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        let metadata = Metadata::from_bytes(field(tree_start + 4..tree_start + 4 + len)?)?;
+        padding = metadata.padding.unwrap_or(0) as usize;
+        layout.metadata = Some(metadata);
+        tree_start += 4 + len;
+    }
+
+    let payload_len = bit_count.div_ceil(8);
+    layout.payload_len = Some(payload_len);
+
+    let mut member_end = tree_start + tree_len as usize + payload_len as usize + padding;
+    if flags & FLAG_TRAILER != 0 {
+        layout.trailer = Some(read_trailer(field(member_end..member_end + TRAILER_LEN)?));
+        member_end += TRAILER_LEN;
+    }
+    if bytes.len() < member_end {
+        return Err(RuffmanError::Truncated);
+    }
+
+    Ok(member_end)
+}
+
+/// Splits `input` after its first newline, or at the end if there is none.
+/// With `--preserve-first-line` the first part is stored as the
+/// [`Metadata::prefix`] and only the rest is compressed.
+pub fn split_first_line(input: &str) -> (&str, &str) {
+    match input.find('\n') {
+        Some(pos) => input.split_at(pos + 1),
+        None => (input, ""),
+    }
+}
+
+/// The UTF-8 byte order mark.
+pub const BOM: char = '\u{FEFF}';
+
+/// Prefixes decompressed text with a [`BOM`] unless it already starts with
+/// one.
+pub fn include_bom(text: &mut Vec<u8>) {
+    let mut bom = [0; 3];
+    let bom = BOM.encode_utf8(&mut bom).as_bytes();
+    if !text.starts_with(bom) {
+        text.splice(0..0, bom.iter().copied());
+    }
+}
+
+/// Whether `input` starts with the magic of a member or an archive, judged
+/// from its first 4 bytes. Compressing such input again wastes the effort
+/// and usually grows it.
+pub fn is_compressed(input: &[u8]) -> bool {
+    input.starts_with(&MAGIC) || crate::archive::is_archive(input)
+}
+
+/// Checks `input` before compressing it: if it [`is_compressed`] already,
+/// fails with [`RuffmanError::AlreadyCompressed`] when `refuse` is set and
+/// otherwise returns a warning to show.
+pub fn check_recompress(input: &[u8], refuse: bool) -> Result<Option<String>, RuffmanError> {
+    match (is_compressed(input), refuse) {
+        (false, _) => Ok(None),
+        (true, true) => Err(RuffmanError::AlreadyCompressed),
+        (true, false) => Ok(Some(RuffmanError::AlreadyCompressed.to_string())),
+    }
+}
+
+/// Compresses `input` into a single member, returning the codec built for it
+/// alongside the stats. The codec's codes are assigned, so it can
+/// [`Huffman::encode`] further inputs over the same tree.
+pub fn compress<W: Write>(input: String, out: &mut W) -> io::Result<(Huffman, CompressionStats)> {
+    let start = Instant::now();
+    let input_len = input.len() as u64;
+
+    let mut huffman = Huffman::from_input(input);
+    huffman.compress();
+    let written = write_member(
+        &huffman,
+        &huffman.get_compressed(),
+        &Metadata::default(),
+        out,
+    )?;
+
+    let stats = CompressionStats::new(input_len, written, huffman.num_symbols(), start.elapsed());
+
+    Ok((huffman, stats))
+}
+
+/// Writes `huffman`'s tree followed by `bits`, the encoding of its input, as
+/// a single member. Returns the number of bytes written.
+///
+/// A canonicalized `huffman` (see [`Huffman::canonicalize`]) is stored as a
+/// compact code-length table rather than the structural tree.
+pub fn write_member<W: Write>(
+    huffman: &Huffman,
+    bits: &[u8],
+    metadata: &Metadata,
+    out: &mut W,
+) -> io::Result<u64> {
+    write_member_aligned(huffman, bits, metadata, 1, out)
+}
+
+/// Like [`write_member`], zero-padding the member to a multiple of `align`
+/// bytes. The padding is recorded in the header and skipped when reading.
+pub fn write_member_aligned<W: Write>(
+    huffman: &Huffman,
+    bits: &[u8],
+    metadata: &Metadata,
+    align: u64,
+    out: &mut W,
+) -> io::Result<u64> {
+    write(huffman, bits, metadata, align, 0, out)
+}
+
+/// Like [`write_member_aligned`], ending the member with a trailer holding
+/// the CRC-32 and the length modulo 2^32 of its decoded data, little-endian,
+/// in the layout of gzip's CRC32 and ISIZE fields. The trailer stays the
+/// last [`TRAILER_LEN`] bytes of the member even when it is aligned.
+pub fn write_member_with_trailer<W: Write>(
+    huffman: &Huffman,
+    bits: &[u8],
+    metadata: &Metadata,
+    align: u64,
+    out: &mut W,
+) -> io::Result<u64> {
+    write(huffman, bits, metadata, align, FLAG_TRAILER, out)
+}
+
+/// Like [`write_member_aligned`], leaving out the tree, for a `huffman`
+/// built on a shared tree such as one from [`crate::trained::train`]. The
+/// member keeps its header and metadata, but can only be decoded by
+/// [`decompress_with_tree`] with the same tree.
+pub fn write_member_without_tree<W: Write>(
+    huffman: &Huffman,
+    bits: &[u8],
+    metadata: &Metadata,
+    align: u64,
+    out: &mut W,
+) -> io::Result<u64> {
+    write(huffman, bits, metadata, align, FLAG_EXTERNAL_TREE, out)
+}
+
+/// Like [`write_member_aligned`], but writes `huffman`'s input as a stored
+/// member (see [`write_stored`]) instead if that is smaller, so input that
+/// doesn't compress grows by no more than a header.
+pub fn write_member_or_stored<W: Write>(
+    huffman: &Huffman,
+    bits: &[u8],
+    metadata: &Metadata,
+    align: u64,
+    out: &mut W,
+) -> io::Result<u64> {
+    let data = original(huffman)?;
+    let coded = write(huffman, bits, metadata, align, 0, &mut io::sink())?;
+    if write_stored(&data, metadata, align, &mut io::sink())? < coded {
+        return write_stored(&data, metadata, align, out);
+    }
+
+    write(huffman, bits, metadata, align, 0, out)
+}
+
+/// Rewrites a file of `current_len` bytes that decoded to `data` and
+/// `metadata` as a single member whose tree is built from `data` itself,
+/// returning the new file only if it is smaller. It decodes to exactly
+/// `data` again: a prefix and line endings, already restored into `data`,
+/// are coded along with the rest rather than kept in the metadata.
+pub fn optimize(
+    data: &[u8],
+    metadata: &Metadata,
+    current_len: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let metadata = Metadata {
+        mode: metadata.mode,
+        encoding: metadata.encoding.clone(),
+        normalization: metadata.normalization.clone(),
+        values: metadata.values.clone(),
+        ..Default::default()
+    };
+
+    let mut huffman = match std::str::from_utf8(data) {
+        Ok(text) => Huffman::from_input(text.to_owned()),
+        Err(_) => Huffman::from_bytes(data),
+    };
+    huffman.compress();
+
+    let mut out = Vec::new();
+    write_member_or_stored(&huffman, &huffman.get_compressed(), &metadata, 1, &mut out)?;
+    if out.len() >= current_len {
+        return Ok(None);
+    }
+
+    Ok(Some(out))
+}
+
+/// Writes `data` as a stored member, copied into the payload as is with no
+/// tree, for input that Huffman coding would only grow. It decodes back to
+/// exactly `data`, text or not. Returns the number of bytes written.
+pub fn write_stored<W: Write>(
+    data: &[u8],
+    metadata: &Metadata,
+    align: u64,
+    out: &mut W,
+) -> io::Result<u64> {
+    let mut flags = FLAG_METADATA;
+    let symbol_count = match std::str::from_utf8(data) {
+        Ok(text) => text.chars().count(),
+        Err(_) => {
+            flags |= FLAG_BYTES;
+            data.len()
+        }
+    };
+
+    let header = Header {
+        flags,
+        symbol_count: symbol_count as u64,
+        bit_count: data.len() as u64 * 8,
+        crc: if metadata.no_checksum { 0 } else { crc32(data) },
+        tree_len: 0,
+    };
+    let header_len = header.encoded_len();
+
+    let mut metadata = metadata.clone();
+    metadata.stored = true;
+    let metadata_block = metadata_block(&mut metadata, (header_len + data.len()) as u64, align);
+
+    header.write(out)?;
+    out.write_all(&metadata_block)?;
+    out.write_all(data)?;
+
+    let padding = metadata.padding.unwrap_or(0) as usize;
+    out.write_all(&vec![0; padding])?;
+
+    Ok((header_len + metadata_block.len() + data.len() + padding) as u64)
+}
+
+/// Splits `input` into blocks of about `block_size` bytes, each compressed
+/// with its own tree from `builder` and checksummed separately, so that
+/// damage to one block leaves the others readable. `metadata` is stored with
+/// the first block and every block is aligned as by
+/// [`write_member_aligned`]. Returns the number of bytes written.
+pub fn write_blocks<W: Write>(
+    input: &str,
+    block_size: usize,
+    builder: &HuffmanBuilder,
+    metadata: &Metadata,
+    align: u64,
+    out: &mut W,
+) -> io::Result<u64> {
+    let never = AtomicBool::new(false);
+    write_blocks_cancellable(input, block_size, builder, metadata, align, &never, out)
+}
+
+/// Like [`write_blocks`], checking `cancel` before each block and failing
+/// with [`RuffmanError::Cancelled`] once it is set. Blocks already written
+/// are left in `out`; write through [`crate::files::write_atomic`] to have
+/// them cleaned up.
+pub fn write_blocks_cancellable<W: Write>(
+    input: &str,
+    block_size: usize,
+    builder: &HuffmanBuilder,
+    metadata: &Metadata,
+    align: u64,
+    cancel: &AtomicBool,
+    out: &mut W,
+) -> io::Result<u64> {
+    let mut written = 0;
+    let mut start = 0;
+
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(RuffmanError::Cancelled.into());
+        }
+
+        let end = block_end(input.as_bytes(), start + block_size.max(1));
+        written += write_block(
+            &input[start..end],
+            builder,
+            metadata,
+            align,
+            start == 0,
+            out,
+        )?;
+
+        start = end;
+        if start == input.len() {
+            return Ok(written);
+        }
+    }
+}
+
+/// Compresses everything `input` yields into blocks as [`write_blocks`] does,
+/// reading only a block at a time, for input whose length isn't known up
+/// front such as a pipe. The output is the same as [`write_blocks`] gives for
+/// the whole input with no metadata or alignment.
+pub fn write_blocks_from_reader<R: Read, W: Write>(
+    input: &mut R,
+    block_size: usize,
+    builder: &HuffmanBuilder,
+    out: &mut W,
+) -> io::Result<CompressionStats> {
+    let start = Instant::now();
+    let block_size = block_size.max(1);
+    let metadata = Metadata::default();
+
+    let mut pending = Vec::new();
+    let mut symbols = HashSet::new();
+    let mut read = 0;
+    let mut written = 0;
+    let mut first = true;
+    let mut eof = false;
+    loop {
+        // A block may run up to 3 bytes past its size to finish a char.
+        while !eof && pending.len() < block_size + 3 {
+            let len = pending.len();
+            pending.resize(len + block_size.max(8192), 0);
+            let n = input.read(&mut pending[len..])?;
+            pending.truncate(len + n);
+            eof = n == 0;
+        }
+
+        let end = block_end(&pending, block_size);
+        let block = std::str::from_utf8(&pending[..end])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        symbols.extend(block.chars());
+        read += end as u64;
+        written += write_block(block, builder, &metadata, 1, first, out)?;
+        first = false;
+
+        pending.drain(..end);
+        if eof && pending.is_empty() {
+            return Ok(CompressionStats::new(
+                read,
+                written,
+                symbols.len(),
+                start.elapsed(),
+            ));
+        }
+    }
+}
+
+/// Where a block reaching to `end` actually ends in `input`: no further than
+/// its end, and never partway through a UTF-8 char.
+fn block_end(input: &[u8], end: usize) -> usize {
+    let mut end = end.min(input.len());
+    while input.get(end).is_some_and(|byte| byte & 0xC0 == 0x80) {
+        end += 1;
+    }
+
+    end
+}
+
+/// Writes `block` as one block member, built with its own tree. Only the
+/// first block carries `metadata`.
+fn write_block<W: Write>(
+    block: &str,
+    builder: &HuffmanBuilder,
+    metadata: &Metadata,
+    align: u64,
+    first: bool,
+    out: &mut W,
+) -> io::Result<u64> {
+    let mut huffman = builder.build(block.to_owned()).map_err(io::Error::from)?;
+    huffman.compress();
+
+    let block_metadata = match first {
+        true => metadata.clone(),
+        // Every block needs to say it has no checksum.
+        false => Metadata {
+            no_checksum: metadata.no_checksum,
+            ..Default::default()
+        },
+    };
+    let bits = huffman.get_compressed();
+    write(&huffman, &bits, &block_metadata, align, FLAG_BLOCK, out)
+}
+
+/// Compresses text as it arrives, such as log records, writing a block
+/// member (as [`write_blocks`] does) for everything buffered at each
+/// [`StreamEncoder::flush`]. Members end on a byte boundary, so a reader can
+/// decode all the flushed blocks so far while more are still to come, and
+/// the whole stream decompresses to everything written.
+pub struct StreamEncoder<W: Write> {
+    out: W,
+    builder: HuffmanBuilder,
+    buffer: String,
+    written: u64,
+}
+
+impl<W: Write> StreamEncoder<W> {
+    pub fn new(builder: HuffmanBuilder, out: W) -> Self {
+        Self {
+            out,
+            builder,
+            buffer: String::new(),
+            written: 0,
+        }
+    }
+
+    /// Buffers `text` until the next flush.
+    pub fn write_str(&mut self, text: &str) {
+        self.buffer.push_str(text);
+    }
+
+    /// Writes the buffered text as one block, built with its own tree, and
+    /// flushes the writer. Does nothing if no text is buffered. Returns the
+    /// number of bytes written.
+    pub fn flush(&mut self) -> io::Result<u64> {
+        if self.buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let input = std::mem::take(&mut self.buffer);
+        let mut huffman = self.builder.build(input).map_err(io::Error::from)?;
+        huffman.compress();
+
+        let bits = huffman.get_compressed();
+        let metadata = Metadata::default();
+        let written = write(&huffman, &bits, &metadata, 1, FLAG_BLOCK, &mut self.out)?;
+        self.out.flush()?;
+        self.written += written;
+
+        Ok(written)
+    }
+
+    /// Flushes what is left and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.out)
+    }
+
+    /// Number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+}
+
+fn write<W: Write>(
+    huffman: &Huffman,
+    bits: &[u8],
+    metadata: &Metadata,
+    align: u64,
+    mut flags: u8,
+    out: &mut W,
+) -> io::Result<u64> {
+    if huffman.rle {
+        flags |= FLAG_RLE;
+    }
+    if huffman.bytes {
+        flags |= FLAG_BYTES;
+    }
+    let crc = if metadata.no_checksum {
+        0
+    } else if huffman.bytes {
+        crc32(&huffman.input.chars().map(|c| c as u8).collect::<Vec<_>>())
+    } else {
+        crc32(huffman.input.as_bytes())
+    };
+
+    let tree = match flags & FLAG_EXTERNAL_TREE {
+        0 => {
+            let (tree, tree_flags) = tree_bytes(huffman)?;
+            flags |= tree_flags;
+            tree
+        }
+        _ => Vec::new(),
+    };
+
+    let bit_count = bits.len() as u64;
+    let trailer_len = match flags & FLAG_TRAILER {
+        0 => 0,
+        _ => TRAILER_LEN,
+    };
+
+    let mut header = Header {
+        flags,
+        symbol_count: huffman.input.chars().count() as u64,
+        bit_count,
+        crc,
+        tree_len: tree.len() as u32,
+    };
+    // Flags are one byte whatever they hold, so the metadata flag can be set
+    // after measuring.
+    let header_len = header.encoded_len();
+
+    let mut metadata = metadata.clone();
+    metadata.escape = huffman.escape;
+    metadata.lz = huffman.lz;
+    metadata.indent = huffman.indent;
+    if flags & FLAG_EXTERNAL_TREE != 0 {
+        metadata.tree_hash = Some(trained::tree_hash(&huffman.tree));
+    }
+    let len = (header_len + tree.len() + trailer_len) as u64 + bit_count.div_ceil(8);
+    let metadata_block = metadata_block(&mut metadata, len, align);
+    if !metadata_block.is_empty() {
+        header.flags |= FLAG_METADATA;
+    }
+
+    header.write(out)?;
+    out.write_all(&metadata_block)?;
+    out.write_all(&tree)?;
+
+    let mut payload = BitWriter::new(&mut *out);
+    payload.write_bits(bits)?;
+    debug_assert_eq!(payload.bits_written(), bit_count);
+    let payload_len = payload.finish()?;
+
+    let padding = metadata.padding.unwrap_or(0) as usize;
+    out.write_all(&vec![0; padding])?;
+
+    if trailer_len > 0 {
+        out.write_all(&trailer(huffman, &metadata)?)?;
+    }
+
+    Ok(
+        (header_len + metadata_block.len() + tree.len() + padding + trailer_len) as u64
+            + payload_len,
+    )
+}
+
+/// Serializes `huffman`'s tree as [`write`] stores it, with the flag saying
+/// how.
+///
+/// Fails with [`RuffmanError::CodeTooLong`] on a canonical tree with a code
+/// longer than its table can hold, which only a hand-built tree can have.
+fn tree_bytes(huffman: &Huffman) -> Result<(Vec<u8>, u8), RuffmanError> {
+    Ok(match huffman.serialization() {
+        Serialization::Canonical => (canonical::write_table(&huffman.tree)?, FLAG_CANONICAL_TREE),
+        Serialization::Structural if ascii::fits(&huffman.tree) => {
+            (ascii::write_tree(&huffman.tree), FLAG_ASCII_TREE)
+        }
+        Serialization::Structural => {
+            let plain = structural_bytes(&huffman.tree);
+
+            // The symbol table pays for itself on all but the smallest trees.
+            let remapped = remap::write_tree(&huffman.tree);
+            if remapped.len() < plain.len() {
+                (remapped, FLAG_REMAPPED_TREE)
+            } else {
+                (plain, 0)
+            }
+        }
+    })
+}
+
+/// Size of a member holding `huffman`'s tree and a payload of `bit_count`
+/// bits encoding `symbol_count` symbols, with no metadata but the escape, or
+/// `None` if the tree can't be stored.
+pub(crate) fn estimate_member_len(
+    huffman: &Huffman,
+    symbol_count: u64,
+    bit_count: u64,
+) -> Option<u64> {
+    let (tree, flags) = tree_bytes(huffman).ok()?;
+    let header = Header {
+        flags,
+        symbol_count,
+        bit_count,
+        crc: 0,
+        tree_len: tree.len() as u32,
+    };
+
+    let metadata = Metadata {
+        escape: huffman.escape,
+        ..Default::default()
+    };
+
+    Some(header.estimated_size(&metadata) as u64 + bit_count.div_ceil(8))
+}
+
+/// Serializes `metadata` as a member's metadata block, or nothing if it's
+/// empty, first setting its padding so that a member of `len` bytes plus the
+/// block ends on a multiple of `align`.
+fn metadata_block(metadata: &mut Metadata, len: u64, align: u64) -> Vec<u8> {
+    if align > 1 {
+        // The padding entry's own size counts towards the length it pads.
+        metadata.padding = Some(0);
+        let len = len + 4 + metadata.to_bytes().len() as u64;
+        metadata.padding = Some(((align - len % align) % align) as u32);
+    }
+
+    let mut block = Vec::new();
+    if !metadata.is_empty() {
+        let entries = metadata.to_bytes();
+        block.extend((entries.len() as u32).to_be_bytes());
+        block.extend(entries);
+    }
+
+    block
+}
+
+/// The trailer for a member holding `huffman`'s input, covering exactly
+/// what the member decodes to, line endings restored.
+fn trailer(huffman: &Huffman, metadata: &Metadata) -> io::Result<[u8; TRAILER_LEN]> {
+    let mut data = metadata.prefix.clone().unwrap_or_default().into_bytes();
+    data.extend(original(huffman)?);
+    if let Some(ending) = metadata.line_ending {
+        data = eol::restore(&data, ending);
+    }
+
+    let mut trailer = [0; TRAILER_LEN];
+    trailer[0..4].copy_from_slice(&crc32(&data).to_le_bytes());
+    trailer[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(trailer)
+}
+
+/// The data `huffman` was built from, before any of its pre-passes.
+fn original(huffman: &Huffman) -> io::Result<Vec<u8>> {
+    if huffman.bytes {
+        return Ok(huffman.input.chars().map(|c| c as u8).collect());
+    }
+
+    let mut text = huffman.input.clone();
+    if huffman.rle {
+        text = rle::decode(&text).map_err(io::Error::from)?;
+    }
+    if huffman.lz {
+        text = lz::decode(&text).map_err(io::Error::from)?;
+    }
+    if huffman.indent {
+        text = indent::decode(&text).map_err(io::Error::from)?;
+    }
+
+    Ok(text.into_bytes())
+}
+
+/// Parses a trailer's CRC-32 and size.
+fn read_trailer(bytes: &[u8]) -> (u32, u32) {
+    (
+        u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+        u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+    )
+}
+
+/// Where the parts of a member lie, parsed from its header.
+struct Bounds {
+    header: Header,
+    metadata: Metadata,
+    tree_start: usize,
+    tree_end: usize,
+    payload_end: usize,
+    /// Where the trailer starts, if the member has one.
+    trailer: Option<usize>,
+    /// Including any padding and trailer.
+    len: usize,
+}
+
+impl Bounds {
+    /// Whether decoding checks the member's data against a checksum, in its
+    /// header or its trailer.
+    fn checksum(&self) -> bool {
+        !self.metadata.no_checksum || self.trailer.is_some()
+    }
+}
+
+fn read_bounds(bytes: &[u8]) -> Result<Bounds, RuffmanError> {
+    let bounds = measure_bounds(bytes)?;
+    if bytes.len() < bounds.len {
+        return Err(RuffmanError::Truncated);
+    }
+
+    Ok(bounds)
+}
+
+/// Like [`read_bounds`], but doesn't check that `bytes` holds the whole
+/// member, only its header and metadata.
+fn measure_bounds(bytes: &[u8]) -> Result<Bounds, RuffmanError> {
+    let (header, mut tree_start) = Header::read(bytes)?;
+
+    let mut metadata = Metadata::default();
+    if header.flags & FLAG_METADATA != 0 {
+        let len = bytes
+            .get(tree_start..tree_start + 4)
+            .ok_or(RuffmanError::Truncated)?;
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        let entries = bytes
+            .get(tree_start + 4..tree_start + 4 + len)
+            .ok_or(RuffmanError::Truncated)?;
+        metadata = Metadata::from_bytes(entries)?;
+        tree_start += 4 + len;
+    }
+
+    let tree_end = tree_start + header.tree_len as usize;
+    let payload_end = tree_end + (header.bit_count as usize).div_ceil(8);
+    let mut len = payload_end + metadata.padding.unwrap_or(0) as usize;
+    let mut trailer = None;
+    if header.flags & FLAG_TRAILER != 0 {
+        trailer = Some(len);
+        len += TRAILER_LEN;
+    }
+
+    Ok(Bounds {
+        header,
+        metadata,
+        tree_start,
+        tree_end,
+        payload_end,
+        trailer,
+        len,
+    })
+}
+
+/// Decodes the member `bounds` describes, appending its data to `out`. A
+/// member written without its tree is decoded against `external`. Fails with
+/// [`RuffmanError::OutputTooLarge`] rather than let `out` grow past `limit`
+/// bytes. On error, `out` may hold part of the member.
+fn decode_member_into(
+    bytes: &[u8],
+    bounds: &Bounds,
+    external: Option<&Node>,
+    limit: usize,
+    out: &mut Vec<u8>,
+) -> Result<(), RuffmanError> {
+    let header = bounds.header;
+    let payload = &bytes[bounds.tree_end..bounds.payload_end];
+    let bits = payload.view_bits::<Msb0>();
+    // Shorter only when `recover` has cut the payload off at a truncation.
+    let bits = &bits[..bits.len().min(header.bit_count as usize)];
+
+    let member_start = out.len();
+    // Every symbol decodes to at least a byte, so the header's count can
+    // rule the member out before any decoding.
+    let prefix_len = bounds.metadata.prefix.as_ref().map_or(0, String::len);
+    let declared = (member_start + prefix_len) as u64;
+    if declared.saturating_add(header.symbol_count) > limit as u64 {
+        return Err(RuffmanError::OutputTooLarge);
+    }
+
+    if let Some(prefix) = &bounds.metadata.prefix {
+        out.extend_from_slice(prefix.as_bytes());
+    }
+    let payload_start = out.len();
+
+    if bounds.metadata.stored {
+        out.extend_from_slice(&payload[..bits.len() / 8]);
+    } else {
+        let tree_bytes = &bytes[bounds.tree_start..bounds.tree_end];
+        let own;
+        let tree = if header.flags & FLAG_EXTERNAL_TREE != 0 {
+            let tree = external.ok_or(RuffmanError::MissingTree)?;
+            // Members from before the hash was recorded can't be checked.
+            match bounds.metadata.tree_hash {
+                Some(hash) if hash != trained::tree_hash(tree) => {
+                    return Err(RuffmanError::TreeMismatch)
+                }
+                _ => tree,
+            }
+        } else {
+            own = match header.flags & TREE_FORMAT {
+                FLAG_ASCII_TREE => ascii::read_tree(tree_bytes)?,
+                FLAG_CANONICAL_TREE => canonical::read_table(tree_bytes)?,
+                FLAG_REMAPPED_TREE => remap::read_tree(tree_bytes)?,
+                _ => NodeBytes::try_from(tree_bytes.to_vec())?.node,
+            };
+            own.validate()?;
+            &own
+        };
+        let table = DecodeTable::new(tree)
+            .escape(bounds.metadata.escape)
+            .limit((limit - out.len()).saturating_add(1));
+
+        // An ASCII tree's symbols are their own UTF-8 bytes, so there's
+        // nothing to encode unless literals may follow the escape.
+        let ascii =
+            header.flags & TREE_FORMAT == FLAG_ASCII_TREE && bounds.metadata.escape.is_none();
+        if header.flags & FLAG_BYTES != 0 || ascii {
+            table.decode_bytes_into(bits, out)?;
+        } else {
+            table.decode_into(bits, out);
+        }
+    }
+    // The count may understate the payload if the header is damaged.
+    if out.len() > limit {
+        return Err(RuffmanError::OutputTooLarge);
+    }
+
+    if !bounds.metadata.no_checksum {
+        let found = crc32(&out[payload_start..]);
+        if found != header.crc {
+            return Err(RuffmanError::ChecksumMismatch {
+                expected: header.crc,
+                found,
+            });
+        }
+    }
+
+    // Decoded chars are always valid UTF-8, but a stored member's bytes
+    // needn't be, and neither are a damaged header's flags to be trusted.
+    // A byte-mode member makes no such promise.
+    if header.flags & FLAG_BYTES == 0 && std::str::from_utf8(&out[payload_start..]).is_err() {
+        return Err(RuffmanError::InvalidUtf8);
+    }
+
+    if header.flags & FLAG_RLE != 0 {
+        // Only text is ever run-length encoded, and was checked just above.
+        let encoded = String::from_utf8(out.split_off(payload_start)).unwrap();
+        out.extend(rle::decode_limited(&encoded, limit - payload_start)?.into_bytes());
+    }
+    if bounds.metadata.lz {
+        // Checked to be UTF-8 like run-length text, and the LZ pass comes
+        // before the run-length one when both were applied, so is undone
+        // after it.
+        let encoded = String::from_utf8(out.split_off(payload_start)).unwrap();
+        out.extend(lz::decode_limited(&encoded, limit - payload_start)?.into_bytes());
+    }
+    if bounds.metadata.indent {
+        // The indentation pass comes before the others, so is undone last.
+        let encoded = String::from_utf8(out.split_off(payload_start)).unwrap();
+        out.extend(indent::decode_limited(&encoded, limit - payload_start)?.into_bytes());
+    }
+
+    if let Some(ending) = bounds.metadata.line_ending {
+        let member = out.split_off(member_start);
+        out.extend(eol::restore(&member, ending));
+        if out.len() > limit {
+            return Err(RuffmanError::OutputTooLarge);
+        }
+    }
+
+    // A truncated member's trailer may be gone; `recover` reports that. It
+    // covers the line endings as restored, like the data the user gets.
+    let trailer = bounds
+        .trailer
+        .and_then(|start| bytes.get(start..start + TRAILER_LEN));
+    if let Some(trailer) = trailer {
+        let (expected, size) = read_trailer(trailer);
+        let data = &out[member_start..];
+        let found = crc32(data);
+        if found != expected {
+            return Err(RuffmanError::ChecksumMismatch { expected, found });
+        }
+        if data.len() as u32 != size {
+            return Err(RuffmanError::SizeMismatch {
+                expected: size,
+                found: data.len() as u32,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes the member at the start of `bytes`.
+pub fn read_member(bytes: &[u8]) -> Result<Member, RuffmanError> {
+    read_member_with(bytes, None, usize::MAX)
+}
+
+fn read_member_with(
+    bytes: &[u8],
+    external: Option<&Node>,
+    limit: usize,
+) -> Result<Member, RuffmanError> {
+    let bounds = read_bounds(bytes)?;
+    let mut data = Vec::new();
+    decode_member_into(bytes, &bounds, external, limit, &mut data)?;
+
+    Ok(Member {
+        data,
+        metadata: bounds.metadata,
+        len: bounds.len,
+    })
+}
+
+/// The outcome of checking one member with [`verify`].
+#[derive(Debug)]
+pub struct BlockReport {
+    /// Offset of the member in the input.
+    pub offset: usize,
+    pub result: Result<(), RuffmanError>,
+    /// Whether the member had a checksum to check its data against. Without
+    /// one, a member that decodes is reported ok even if it was damaged.
+    pub checksum: bool,
+}
+
+/// Checks that every member in `bytes` sticks to the minimal, well-understood
+/// subset of the format, for pipelines handling untrusted files: no
+/// metadata other than the marker of a stored member, and so no missing
+/// checksum or 4-byte symbols, and no tree left out. Fails with
+/// [`RuffmanError::NonstandardFeature`] naming the first feature found.
+/// Nothing is decoded; decompress as usual after.
+pub fn check_strict(mut bytes: &[u8]) -> Result<(), RuffmanError> {
+    while !bytes.is_empty() {
+        let bounds = read_bounds(bytes)?;
+        let metadata = &bounds.metadata;
+        let unstored = Metadata {
+            stored: false,
+            ..metadata.clone()
+        };
+
+        let feature = if metadata.no_checksum {
+            Some("a missing checksum")
+        } else if metadata.values.is_some() {
+            Some("4-byte symbols")
+        } else if bounds.header.flags & FLAG_EXTERNAL_TREE != 0 {
+            Some("an external tree")
+        } else if !unstored.is_empty() {
+            Some("a metadata block")
+        } else {
+            None
+        };
+        if let Some(feature) = feature {
+            return Err(RuffmanError::NonstandardFeature(feature));
+        }
+
+        bytes = &bytes[bounds.len..];
+    }
+
+    Ok(())
+}
+
+/// Decodes and checks every member in `bytes`, reporting each separately so
+/// that damage is localized to the blocks it hit. A member whose header is
+/// damaged hides where the next one starts, so checking stops there.
+pub fn verify(mut bytes: &[u8]) -> Vec<BlockReport> {
+    let mut reports = Vec::new();
+    let mut offset = 0;
+    let mut scratch = Vec::new();
+
+    while !bytes.is_empty() {
+        let bounds = match read_bounds(bytes) {
+            Ok(bounds) => bounds,
+            Err(error) => {
+                reports.push(BlockReport {
+                    offset,
+                    result: Err(error),
+                    checksum: false,
+                });
+                break;
+            }
+        };
+
+        scratch.clear();
+        reports.push(BlockReport {
+            offset,
+            result: decode_member_into(bytes, &bounds, None, usize::MAX, &mut scratch),
+            checksum: bounds.checksum(),
+        });
+        let len = bounds.len;
+        bytes = &bytes[len..];
+        offset += len;
+    }
+
+    reports
+}
+
+/// Whether every member in `bytes` has a checksum that decoding checks its
+/// data against, as [`verify`] reports for each. Only the headers are read;
+/// one too damaged to parse counts as having none.
+pub fn all_checksummed(mut bytes: &[u8]) -> bool {
+    while !bytes.is_empty() {
+        match read_bounds(bytes) {
+            Ok(bounds) if bounds.checksum() => bytes = &bytes[bounds.len..],
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// What [`recover`] salvaged from a damaged file.
+#[derive(Debug)]
+pub struct Recovery {
+    /// Everything that could be decoded. Data from a damaged member may be
+    /// wrong in places, and a run-length encoded member is left unexpanded.
+    pub data: Vec<u8>,
+    /// The first member's metadata, if its header survived.
+    pub metadata: Metadata,
+    /// The first problem found, or `None` if nothing was wrong and `data` is
+    /// the complete output.
+    pub error: Option<RuffmanError>,
+}
+
+/// Decodes as much of `bytes` as possible instead of failing outright: a
+/// member that fails its checksum still contributes what it decoded, and a
+/// truncated one whatever its surviving payload holds.
+pub fn recover(mut bytes: &[u8]) -> Recovery {
+    let mut data = Vec::new();
+    let mut metadata = None;
+    let mut error = None;
+
+    while !bytes.is_empty() {
+        let mut bounds = match measure_bounds(bytes) {
+            Ok(bounds) => bounds,
+            Err(e) => {
+                error.get_or_insert(e);
+                break;
+            }
+        };
+        metadata.get_or_insert_with(|| bounds.metadata.clone());
+
+        let truncated = bytes.len() < bounds.len;
+        if truncated {
+            if bytes.len() < bounds.tree_end {
+                error.get_or_insert(RuffmanError::Truncated);
+                break;
+            }
+            bounds.payload_end = bounds.payload_end.min(bytes.len());
+        }
+
+        let result = decode_member_into(bytes, &bounds, None, usize::MAX, &mut data);
+        if truncated {
+            error.get_or_insert(RuffmanError::Truncated);
+            break;
+        }
+        if let Err(e) = result {
+            error.get_or_insert(e);
+        }
+
+        bytes = &bytes[bounds.len..];
+    }
+
+    Recovery {
+        data,
+        metadata: metadata.unwrap_or_default(),
+        error,
+    }
+}
+
+/// Decodes every member in `bytes` and returns their concatenation.
+pub fn decompress(bytes: &[u8]) -> Result<String, RuffmanError> {
+    let mut output = String::new();
+    decompress_into(bytes, &mut output)?;
+    Ok(output)
+}
+
+/// Like [`decompress`], but appends to `out` so one buffer can be reused
+/// across many decodes. On error, `out` is left as it was.
+pub fn decompress_into(bytes: &[u8], out: &mut String) -> Result<(), RuffmanError> {
+    let start = out.len();
+    let mut buffer = std::mem::take(out).into_bytes();
+    let result = decompress_bytes_into(bytes, &mut buffer);
+
+    match String::from_utf8(buffer) {
+        Ok(text) => {
+            *out = text;
+            result
+        }
+        Err(error) => {
+            // Only the appended bytes can be at fault.
+            let mut buffer = error.into_bytes();
+            buffer.truncate(start);
+            *out = String::from_utf8(buffer).unwrap();
+            Err(RuffmanError::InvalidUtf8)
+        }
+    }
+}
+
+/// Like [`decompress`], but returns the raw bytes, so it also handles
+/// members written in byte mode.
+pub fn decompress_bytes(bytes: &[u8]) -> Result<Vec<u8>, RuffmanError> {
+    let mut output = Vec::new();
+    decompress_bytes_into(bytes, &mut output)?;
+    Ok(output)
+}
+
+/// Like [`decompress_bytes`], but appends to `out`. On error, `out` is left
+/// as it was.
+pub fn decompress_bytes_into(bytes: &[u8], out: &mut Vec<u8>) -> Result<(), RuffmanError> {
+    fn decode_all(mut bytes: &[u8], out: &mut Vec<u8>) -> Result<(), RuffmanError> {
+        while !bytes.is_empty() {
+            let bounds = read_bounds(bytes)?;
+            decode_member_into(bytes, &bounds, None, usize::MAX, out)?;
+            bytes = &bytes[bounds.len..];
+        }
+
+        Ok(())
+    }
+
+    let start = out.len();
+    let result = decode_all(bytes, out);
+    if result.is_err() {
+        out.truncate(start);
+    }
+
+    result
+}
+
+/// Like [`decompress_bytes`], also returning the first member's metadata.
+pub fn decompress_with_metadata(bytes: &[u8]) -> Result<(Vec<u8>, Metadata), RuffmanError> {
+    decompress_members(bytes, None, usize::MAX, &AtomicBool::new(false))
+}
+
+/// Like [`decompress_with_metadata`], failing with
+/// [`RuffmanError::OutputTooLarge`] if the output would be more than
+/// `max_output_bytes` long, for input that can't be trusted. The limit is
+/// checked against each member's declared symbol count before decoding it,
+/// and again as it decodes, so a small crafted file can't make the output
+/// grow much past the limit.
+pub fn decompress_limited(
+    bytes: &[u8],
+    max_output_bytes: u64,
+) -> Result<(Vec<u8>, Metadata), RuffmanError> {
+    let limit = usize::try_from(max_output_bytes).unwrap_or(usize::MAX);
+    decompress_members(bytes, None, limit, &AtomicBool::new(false))
+}
+
+/// Like [`decompress_with_metadata`], checking `cancel` before each member
+/// and failing with [`RuffmanError::Cancelled`] once it is set.
+pub fn decompress_cancellable(
+    bytes: &[u8],
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, Metadata), RuffmanError> {
+    decompress_members(bytes, None, usize::MAX, cancel)
+}
+
+/// Like [`decompress_with_metadata`], decoding members written by
+/// [`write_member_without_tree`] against `tree`.
+pub fn decompress_with_tree(
+    bytes: &[u8],
+    tree: &Node,
+) -> Result<(Vec<u8>, Metadata), RuffmanError> {
+    decompress_members(bytes, Some(tree), usize::MAX, &AtomicBool::new(false))
+}
+
+/// Reads members from `reader` and sends each one's decoded data through
+/// `tx` as soon as the member is complete, so another thread can process the
+/// output while the rest is still being read and decoded. The channel is
+/// closed by dropping `tx` once the input ends. On error, the error is the
+/// last thing sent. Decoding stops early if the receiver hangs up.
+pub fn decode_to_sender<R: Read>(mut reader: R, tx: Sender<io::Result<Vec<u8>>>) {
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    let mut eof = false;
+
+    loop {
+        while !buffer.is_empty() {
+            let bounds = match read_bounds(&buffer) {
+                Ok(bounds) => bounds,
+                // The rest of the member may not have been read yet.
+                Err(RuffmanError::Truncated) if !eof => break,
+                Err(error) => {
+                    let _ = tx.send(Err(error.into()));
+                    return;
+                }
+            };
+
+            let mut data = Vec::new();
+            let result = decode_member_into(&buffer, &bounds, None, usize::MAX, &mut data);
+            let failed = result.is_err();
+            if tx
+                .send(result.map(|()| data).map_err(io::Error::from))
+                .is_err()
+                || failed
+            {
+                return;
+            }
+            buffer.drain(..bounds.len);
+        }
+        if eof {
+            return;
+        }
+
+        match reader.read(&mut chunk) {
+            Ok(0) => eof = true,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => {
+                let _ = tx.send(Err(error));
+                return;
+            }
+        }
+    }
+}
+
+fn decompress_members(
+    mut bytes: &[u8],
+    external: Option<&Node>,
+    limit: usize,
+    cancel: &AtomicBool,
+) -> Result<(Vec<u8>, Metadata), RuffmanError> {
+    let mut output = Vec::new();
+    let mut metadata = None;
+
+    while !bytes.is_empty() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(RuffmanError::Cancelled);
+        }
+
+        let member = read_member_with(bytes, external, limit - output.len())?;
+        output.extend(member.data);
+        metadata.get_or_insert(member.metadata);
+        bytes = &bytes[member.len..];
+    }
+
+    Ok((output, metadata.unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_roundtrip;
+
+    fn compress(input: &str) -> Vec<u8> {
+        compress_with(input, false)
+    }
+
+    fn compress_with(input: &str, compact_tree: bool) -> Vec<u8> {
+        let mut huffman = Huffman::from_input(input.to_owned());
+        if compact_tree {
+            huffman.canonicalize();
+        }
+        huffman.compress();
+
+        let mut out = Vec::new();
+        write_member(
+            &huffman,
+            &huffman.get_compressed(),
+            &Metadata::default(),
+            &mut out,
+        )
+        .unwrap();
+
+        out
+    }
+
+    #[test]
+    fn header_counts_are_varints() {
+        let header = Header {
+            flags: FLAG_CANONICAL_TREE,
+            symbol_count: 127,
+            bit_count: 128,
+            crc: 0xDEAD_BEEF,
+            tree_len: 0,
+        };
+
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(bytes, [
+            b'R', b'U', b'F', b'F', FORMAT_VERSION, FLAG_CANONICAL_TREE,
+            0x7F,
+            0x80, 0x01,
+            0xDE, 0xAD, 0xBE, 0xEF,
+            0x00,
+        ]);
+        assert_eq!(Header::read(&bytes).unwrap(), (header, bytes.len()));
+        assert!(matches!(
+            Header::read(&bytes[..bytes.len() - 1]),
+            Err(RuffmanError::Truncated)
+        ));
+
+        for (count, len) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (16_383, 2),
+            (16_384, 3),
+            (u64::MAX, 10),
+        ] {
+            let header = Header {
+                symbol_count: count,
+                bit_count: count,
+                ..header
+            };
+            let bytes = header.to_bytes();
+            assert_eq!(bytes.len(), 4 + 1 + 1 + 2 * len + 4 + 1);
+            assert_eq!(Header::read(&bytes).unwrap(), (header, bytes.len()));
+        }
+
+        let header = Header {
+            tree_len: u32::MAX,
+            ..header
+        };
+        assert_eq!(Header::read(&header.to_bytes()).unwrap().0, header);
+    }
+
+    #[test]
+    fn estimated_size_matches_written_header() {
+        let metadata = Metadata {
+            mode: Some(0o644),
+            prefix: Some("#!/bin/sh\n".to_owned()),
+            ..Default::default()
+        };
+
+        let input = "the quick brown fox jumps over the lazy dog ".repeat(4);
+        for (builder, metadata) in [
+            (HuffmanBuilder::new(), Metadata::default()),
+            (HuffmanBuilder::new(), metadata.clone()),
+            (HuffmanBuilder::new().canonical(true), metadata),
+            (HuffmanBuilder::new().coded_symbols(4), Metadata::default()),
+        ] {
+            let mut huffman = builder.build(input.clone()).unwrap();
+            huffman.compress();
+            let bits = huffman.get_compressed();
+            let mut bytes = Vec::new();
+            write_member_aligned(&huffman, &bits, &metadata, 16, &mut bytes).unwrap();
+
+            let bounds = read_bounds(&bytes).unwrap();
+            let size = bounds.header.estimated_size(&bounds.metadata);
+            assert_eq!(size, bounds.tree_end);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_roundtrip("Hello, wörld! ünïcode ✓");
+    }
+
+    #[test]
+    fn reuse_returned_codec() {
+        let mut out = Vec::new();
+        let (codec, stats) = super::compress("abracadabra".to_owned(), &mut out).unwrap();
+
+        assert_eq!(stats.output_bytes, out.len() as u64);
+        assert_eq!(decompress(&out).unwrap(), "abracadabra");
+
+        for input in ["cab", "radar"] {
+            let bits = codec.encode(input).unwrap();
+            assert_eq!(codec.decompress(bits), input);
+        }
+        assert!(matches!(
+            codec.encode("abc!"),
+            Err(RuffmanError::UnknownSymbol('!'))
+        ));
+    }
+
+    #[test]
+    fn empty_and_single_symbol_inputs() {
+        for input in ["", "a", "aaaaaaaaaaa"] {
+            assert_roundtrip(input);
+
+            let bytes = compress_with(input, true);
+            assert_eq!(decompress(&bytes).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn multi_member() {
+        let mut bytes = compress("Hello");
+        bytes.extend(compress("abracadabra"));
+
+        assert_eq!(decompress(&bytes).unwrap(), "Helloabracadabra");
+    }
+
+    #[test]
+    fn compact_tree_shrinks_header() {
+        let input = (0..200u32)
+            .flat_map(|i| {
+                let symb = char::from_u32(0x100 + i).unwrap();
+                std::iter::repeat_n(symb, 1 + (i as usize % 7))
+            })
+            .collect::<String>();
+
+        let structural = compress_with(&input, false);
+        let compact = compress_with(&input, true);
+
+        assert_eq!(compact[5] & TREE_FORMAT, FLAG_CANONICAL_TREE);
+        assert!(compact.len() < structural.len());
+        assert_eq!(decompress(&compact).unwrap(), input);
+    }
+
+    #[test]
+    fn remapped_tree_shrinks_header() {
+        let input = (0..60u32)
+            .flat_map(|i| {
+                let symb = char::from_u32(0x2_0000 + i * 37).unwrap();
+                std::iter::repeat_n(symb, 1 + i as usize % 5)
+            })
+            .collect::<String>();
+
+        let huffman = Huffman::from_input(input.clone());
+        let mut plain = NodeBytes::from(huffman.tree.clone());
+        plain.as_bytes();
+
+        let bytes = compress(&input);
+        let (header, _) = Header::read(&bytes).unwrap();
+        assert_eq!(header.flags & FLAG_REMAPPED_TREE, FLAG_REMAPPED_TREE);
+        assert!((header.tree_len as usize) < plain.bytes.len());
+        assert_eq!(decompress(&bytes).unwrap(), input);
+    }
+
+    #[test]
+    fn each_serialization_round_trips() {
+        let input = "serialize me one way or the other";
+        for serialization in [Serialization::Structural, Serialization::Canonical] {
+            let mut huffman = HuffmanBuilder::new()
+                .serialization(serialization)
+                .build(input.to_owned())
+                .unwrap();
+            huffman.compress();
+            assert_eq!(huffman.serialization(), serialization);
+
+            let mut bytes = Vec::new();
+            write_member(
+                &huffman,
+                &huffman.get_compressed(),
+                &Metadata::default(),
+                &mut bytes,
+            )
+            .unwrap();
+
+            let canonical = bytes[5] & TREE_FORMAT == FLAG_CANONICAL_TREE;
+            assert_eq!(canonical, serialization == Serialization::Canonical);
+            assert_eq!(decompress(&bytes).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn rejects_incomplete_canonical_tree() {
+        // Two symbols with 2-bit codes leave half the code space unused, so
+        // the rebuilt tree has an internal node with a single child.
+        let mut table = Vec::new();
+        table.extend(1u32.to_be_bytes());
+        table.extend(u32::from('a').to_be_bytes());
+        table.extend(2u32.to_be_bytes());
+        table.extend(1u32.to_be_bytes());
+        table.extend([2, 2]);
+
+        let mut bytes = Vec::new();
+        bytes.extend(MAGIC);
+        bytes.extend([FORMAT_VERSION, FLAG_CANONICAL_TREE, 0, 0]);
+        bytes.extend(crc32(b"").to_be_bytes());
+        varint::write(table.len() as u64, &mut bytes);
+        bytes.extend(table);
+
+        assert!(matches!(
+            decompress(&bytes),
+            Err(RuffmanError::MissingChild)
+        ));
+    }
+
+    #[test]
+    fn written_length_matches() {
+        let mut huffman = Huffman::from_input("abracadabra".to_owned());
+        huffman.compress();
+
+        let mut out = Vec::new();
+        let metadata = Metadata {
+            mode: Some(0o644),
+            ..Default::default()
+        };
+        let written =
+            write_member(&huffman, &huffman.get_compressed(), &metadata, &mut out).unwrap();
+
+        assert_eq!(written, out.len() as u64);
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let mut huffman = Huffman::from_input("abracadabra".to_owned());
+        huffman.compress();
+
+        let mut out = Vec::new();
+        let metadata = Metadata {
+            mode: Some(0o755),
+            encoding: Some("windows-1252".to_owned()),
+            ..Default::default()
+        };
+        write_member(&huffman, &huffman.get_compressed(), &metadata, &mut out).unwrap();
+
+        assert_eq!(out[5] & FLAG_METADATA, FLAG_METADATA);
+        assert_eq!(
+            decompress_with_metadata(&out).unwrap(),
+            (b"abracadabra".to_vec(), metadata)
+        );
+    }
+
+    #[test]
+    fn align_pads_to_boundary() {
+        let mut bytes = Vec::new();
+        for input in ["Hello", "abracadabra"] {
+            let mut huffman = Huffman::from_input(input.to_owned());
+            huffman.compress();
+
+            let written = write_member_aligned(
+                &huffman,
+                &huffman.get_compressed(),
+                &Metadata::default(),
+                512,
+                &mut bytes,
+            )
+            .unwrap();
+
+            assert_eq!(written, 512);
+            assert_eq!(bytes.len() % 512, 0);
+        }
+
+        assert_eq!(decompress(&bytes).unwrap(), "Helloabracadabra");
+    }
+
+    #[test]
+    fn ascii_input_gets_compact_tree() {
+        let input = "The quick brown fox jumps over the lazy dog. 0123456789!";
+        let bytes = compress(input);
+        let (header, _) = Header::read(&bytes).unwrap();
+
+        assert_eq!(header.flags & TREE_FORMAT, FLAG_ASCII_TREE);
+        let tree = Huffman::from_input(input.to_owned()).tree;
+        assert!((header.tree_len as usize) < remap::write_tree(&tree).len());
+        assert_eq!(decompress(&bytes).unwrap(), input);
+
+        // One symbol outside ASCII takes the general path.
+        let bytes = compress("naïve");
+        let (header, _) = Header::read(&bytes).unwrap();
+        assert_ne!(header.flags & TREE_FORMAT, FLAG_ASCII_TREE);
+        assert_eq!(decompress(&bytes).unwrap(), "naïve");
+    }
+
+    #[test]
+    fn describe_known_member() {
+        let mut bytes = compress("Hello");
+        let layouts = describe(&bytes);
+
+        assert_eq!(layouts.len(), 1);
+        let layout = &layouts[0];
+        assert_eq!(layout.magic, Some(MAGIC));
+        assert_eq!(layout.version, Some(FORMAT_VERSION));
+        assert_eq!(layout.flags, Some(FLAG_ASCII_TREE));
+        assert_eq!(layout.symbol_count, Some(5));
+        assert_eq!(layout.bit_count, Some(10));
+        assert_eq!(layout.crc, Some(crc32(b"Hello")));
+        // A u8 leaf count, four leaves of a symbol and a one-byte weight,
+        // and six edge markers.
+        assert_eq!(layout.tree_len, Some(1 + 4 * 2 + 6));
+        assert_eq!(layout.payload_len, Some(2));
+        assert!(layout.error.is_none());
+
+        // A truncated second member still reports its fixed header.
+        bytes.extend(compress("Hello"));
+        bytes.pop();
+        let layouts = describe(&bytes);
+        assert_eq!(layouts.len(), 2);
+        assert_eq!(layouts[1].payload_len, Some(2));
+        assert!(matches!(layouts[1].error, Some(RuffmanError::Truncated)));
+    }
+
+    #[test]
+    fn output_is_reproducible() {
+        let input = "the quick brown fox jumps over the lazy dog, again and again";
+        let write = |builder: &HuffmanBuilder| {
+            let mut out = Vec::new();
+            write_blocks(input, 16, builder, &Metadata::default(), 1, &mut out).unwrap();
+            out
+        };
+
+        assert_eq!(compress(input), compress(input));
+        for builder in [
+            HuffmanBuilder::new(),
+            HuffmanBuilder::new().canonical(true),
+            HuffmanBuilder::new().rle(true),
+        ] {
+            assert_eq!(write(&builder), write(&builder));
+        }
+    }
+
+    #[test]
+    fn flushed_records_decode_as_they_arrive() {
+        let records = [
+            "GET /index.html 200\n",
+            "GET /missing 404\n",
+            "",
+            "POST /login 302 ✓\n",
+        ];
+        let mut encoder = StreamEncoder::new(HuffmanBuilder::new(), Vec::new());
+
+        let mut sent = String::new();
+        for record in records {
+            encoder.write_str(record);
+            let written = encoder.flush().unwrap();
+            assert_eq!(written == 0, record.is_empty());
+
+            // Everything flushed so far is already readable.
+            sent.push_str(record);
+            assert_eq!(decompress(encoder.out.as_slice()).unwrap(), sent);
+        }
+        assert_eq!(encoder.flush().unwrap(), 0);
+
+        let written = encoder.bytes_written();
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(bytes.len() as u64, written);
+        assert_eq!(verify(&bytes).len(), 3);
+        assert_eq!(decompress(&bytes).unwrap(), records.concat());
+    }
+
+    #[test]
+    fn verify_flags_only_the_damaged_block() {
+        let input = "the quick brown fox jumps over the lazy dog ".repeat(10);
+        let mut bytes = Vec::new();
+        let builder = HuffmanBuilder::new();
+        write_blocks(&input, 100, &builder, &Metadata::default(), 1, &mut bytes).unwrap();
+
+        let reports = verify(&bytes);
+        assert_eq!(reports.len(), 5);
+        assert!(reports.iter().all(|report| report.result.is_ok()));
+        assert_eq!(bytes[5] & FLAG_BLOCK, FLAG_BLOCK);
+        assert_eq!(decompress(&bytes).unwrap(), input);
+
+        // Flip a payload bit in the last byte of the third block.
+        let third_end = reports[3].offset;
+        bytes[third_end - 1] ^= 0x80;
+
+        let reports = verify(&bytes);
+        assert_eq!(reports.len(), 5);
+        for (i, report) in reports.iter().enumerate() {
+            match i {
+                2 => assert!(matches!(
+                    report.result,
+                    Err(RuffmanError::ChecksumMismatch { .. })
+                )),
+                _ => assert!(report.result.is_ok()),
+            }
+        }
+    }
+
+    #[test]
+    fn reader_blocks_match_whole_input() {
+        /// Hands out a few bytes per read, splitting chars, as a pipe may.
+        struct Trickle<'a>(&'a [u8]);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(5);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let input = "snow ☃ falls, ❄ drifts; ".repeat(20);
+        let builder = HuffmanBuilder::new();
+        for (input, block_size) in [(input.as_str(), 64), (&input, 10_000), ("", 64)] {
+            let mut streamed = Vec::new();
+            let stats = write_blocks_from_reader(
+                &mut Trickle(input.as_bytes()),
+                block_size,
+                &builder,
+                &mut streamed,
+            )
+            .unwrap();
+
+            let mut whole = Vec::new();
+            write_blocks(
+                input,
+                block_size,
+                &builder,
+                &Metadata::default(),
+                1,
+                &mut whole,
+            )
+            .unwrap();
+            assert_eq!(streamed, whole);
+            assert_eq!(decompress(&streamed).unwrap(), input);
+            assert_eq!(stats.input_bytes, input.len() as u64);
+            assert_eq!(stats.output_bytes, streamed.len() as u64);
+        }
+
+        let mut cut = Trickle(&[b'a', 0xE2, 0x98]);
+        assert!(write_blocks_from_reader(&mut cut, 64, &builder, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn unchecksummed_blocks_decode_unverified() {
+        let input = "the quick brown fox jumps over the lazy dog ".repeat(10);
+        let metadata = Metadata {
+            no_checksum: true,
+            ..Default::default()
+        };
+        let mut bytes = Vec::new();
+        let builder = HuffmanBuilder::new();
+        write_blocks(&input, 100, &builder, &metadata, 1, &mut bytes).unwrap();
+
+        assert_eq!(decompress(&bytes).unwrap(), input);
+        let reports = verify(&bytes);
+        assert_eq!(reports.len(), 5);
+        for (i, report) in reports.iter().enumerate() {
+            assert!(report.result.is_ok());
+            assert!(!report.checksum);
+            let (header, _) = Header::read(&bytes[report.offset..]).unwrap();
+            assert_eq!(header.crc, 0, "block {}", i);
+        }
+
+        let mut stored = Vec::new();
+        write_stored(b"\xFFraw", &metadata, 1, &mut stored).unwrap();
+        assert_eq!(read_member(&stored).unwrap().data, b"\xFFraw");
+        assert!(!verify(&stored)[0].checksum);
+
+        let mut checked = Vec::new();
+        write_blocks(&input, 100, &builder, &Metadata::default(), 1, &mut checked).unwrap();
+        assert!(verify(&checked).iter().all(|report| report.checksum));
+    }
+
+    #[test]
+    fn preserved_first_line_stays_readable() {
+        let script = "#!/usr/bin/env bash\nset -eu\necho \"hello, world\"\n";
+        let (first_line, rest) = split_first_line(script);
+        assert_eq!(first_line, "#!/usr/bin/env bash\n");
+
+        let mut huffman = Huffman::from_input(rest.to_owned());
+        huffman.compress();
+        let metadata = Metadata {
+            prefix: Some(first_line.to_owned()),
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        write_member(&huffman, &huffman.get_compressed(), &metadata, &mut out).unwrap();
+
+        assert!(out
+            .windows(first_line.len())
+            .any(|w| w == first_line.as_bytes()));
+        assert_eq!(decompress(&out).unwrap(), script);
+    }
+
+    #[test]
+    fn bom_survives_round_trip() {
+        let input = format!("{}#!/bin/sh\necho hi\n", BOM);
+
+        assert_eq!(decompress(&compress(&input)).unwrap(), input);
+
+        // The BOM ends up in the stored first line.
+        let (first_line, rest) = split_first_line(&input);
+        let mut huffman = Huffman::from_input(rest.to_owned());
+        huffman.compress();
+        let metadata = Metadata {
+            prefix: Some(first_line.to_owned()),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_member(&huffman, &huffman.get_compressed(), &metadata, &mut out).unwrap();
+        assert_eq!(decompress(&out).unwrap(), input);
+
+        let mut with_bom = input.clone().into_bytes();
+        include_bom(&mut with_bom);
+        assert_eq!(with_bom, input.as_bytes());
+        let mut plain = b"echo hi\n".to_vec();
+        include_bom(&mut plain);
+        assert_eq!(plain, "\u{FEFF}echo hi\n".as_bytes());
+    }
+
+    #[test]
+    fn trailer_matches_gzip_fields() {
+        let input = "The quick brown fox jumps over the lazy dog";
+        let mut huffman = Huffman::from_input(input.to_owned());
+        huffman.compress();
+
+        let mut out = Vec::new();
+        let written = write_member_with_trailer(
+            &huffman,
+            &huffman.get_compressed(),
+            &Metadata::default(),
+            16,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(written as usize, out.len());
+        assert_eq!(out.len() % 16, 0);
+
+        // CRC-32 of the pangram, as computed by gzip.
+        let trailer = &out[out.len() - TRAILER_LEN..];
+        assert_eq!(trailer[..4], 0x414F_A339u32.to_le_bytes());
+        assert_eq!(trailer[4..], (input.len() as u32).to_le_bytes());
+        assert_eq!(describe(&out)[0].trailer, Some((0x414F_A339, 43)));
+
+        let mut twice = out.clone();
+        twice.extend(&out);
+        assert_eq!(decompress(&twice).unwrap(), input.repeat(2));
+
+        let last = out.len() - 1;
+        out[last] ^= 1;
+        assert!(matches!(
+            decompress(&out),
+            Err(RuffmanError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn trailer_covers_restored_line_endings() {
+        let (input, ending) = eol::record("#!/bin/sh\r\necho hi\r\n");
+        let mut huffman = Huffman::from_input(input[10..].to_owned());
+        huffman.compress();
+        let metadata = Metadata {
+            prefix: Some(input[..10].to_owned()),
+            line_ending: ending,
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        write_member_with_trailer(&huffman, &huffman.get_compressed(), &metadata, 1, &mut out)
+            .unwrap();
+
+        let original = "#!/bin/sh\r\necho hi\r\n";
+        let trailer = &out[out.len() - TRAILER_LEN..];
+        assert_eq!(trailer[..4], crc32(original.as_bytes()).to_le_bytes());
+        assert_eq!(trailer[4..], (original.len() as u32).to_le_bytes());
+        assert_eq!(decompress(&out).unwrap(), original);
+    }
+
+    #[test]
+    fn escaped_symbols_round_trip() {
+        let mut input = "GET /index.html 200\n".repeat(50);
+        input.push_str("POST /ünïcødé?q=🦀 500 ☃\n");
+        input.push('\0');
+        input.push_str(&"GET /index.html 200\n".repeat(50));
+
+        let builder = HuffmanBuilder::new().coded_symbols(8);
+        let mut huffman = builder.build(input.clone()).unwrap();
+        huffman.compress();
+        assert_eq!(huffman.num_symbols(), 9);
+        // '\0' is in the input, so the escape is the next free code point.
+        assert_eq!(huffman.escape, Some('\u{1}'));
+
+        let bits = huffman.get_compressed();
+        assert_eq!(huffman.decompress(bits.clone()), input);
+
+        let mut out = Vec::new();
+        write_member(&huffman, &bits, &Metadata::default(), &mut out).unwrap();
+        assert_eq!(decompress(&out).unwrap(), input);
+        assert_eq!(read_member(&out).unwrap().metadata.escape, Some('\u{1}'));
+        // The escape itself is written as a literal.
+        assert_eq!(
+            huffman.decompress(huffman.encode("\u{1}a").unwrap()),
+            "\u{1}a"
+        );
+
+        // Every symbol escaped, and none.
+        for coded in [0, 100] {
+            let mut huffman = HuffmanBuilder::new()
+                .coded_symbols(coded)
+                .canonical(true)
+                .build(input.clone())
+                .unwrap();
+            huffman.compress();
+            let mut out = Vec::new();
+            write_member(
+                &huffman,
+                &huffman.get_compressed(),
+                &Metadata::default(),
+                &mut out,
+            )
+            .unwrap();
+            assert_eq!(decompress(&out).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn separate_tree_file_must_match() {
+        let input = "separate trees, separate files";
+        let mut huffman = Huffman::from_input(input.to_owned());
+        huffman.compress();
+        let mut out = Vec::new();
+        write_member_without_tree(
+            &huffman,
+            &huffman.get_compressed(),
+            &Metadata::default(),
+            1,
+            &mut out,
+        )
+        .unwrap();
+
+        let mut tree_file = Vec::new();
+        trained::write_tree(huffman.tree(), &mut tree_file).unwrap();
+        let tree = trained::read_tree(&tree_file).unwrap();
+        let (output, _) = decompress_with_tree(&out, &tree).unwrap();
+        assert_eq!(output, input.as_bytes());
+
+        // Covers the same symbols, so would decode to something.
+        let other = trained::train([input.chars().rev().collect::<String>() + "eeee"]).unwrap();
+        assert!(matches!(
+            decompress_with_tree(&out, &other),
+            Err(RuffmanError::TreeMismatch)
+        ));
+    }
+
+    #[test]
+    fn no_tree_round_trip() {
+        let tree = crate::trained::train(["the cat sat on the mat".to_owned()]).unwrap();
+        let input = "the mat sat on the cat";
+        let mut huffman = Huffman::with_tree(tree.clone(), input.to_owned()).unwrap();
+        huffman.compress();
+        let bits = huffman.get_compressed();
+        let metadata = Metadata {
+            mode: Some(0o644),
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        let written = write_member_without_tree(&huffman, &bits, &metadata, 1, &mut out).unwrap();
+        assert_eq!(written as usize, out.len());
+        let mut embedded = Vec::new();
+        write_member(&huffman, &bits, &metadata, &mut embedded).unwrap();
+        assert!(out.len() < embedded.len());
+        assert_eq!(describe(&out)[0].tree_len, Some(0));
+
+        assert!(matches!(decompress(&out), Err(RuffmanError::MissingTree)));
+        let (output, read_metadata) = decompress_with_tree(&out, &tree).unwrap();
+        assert_eq!(output, input.as_bytes());
+        let metadata = Metadata {
+            tree_hash: Some(trained::tree_hash(&tree)),
+            ..metadata
+        };
+        assert_eq!(read_metadata, metadata);
+
+        // Members that carry their own tree ignore the one supplied.
+        let other = crate::trained::train(["xyz".to_owned()]).unwrap();
+        assert_eq!(
+            decompress_with_tree(&embedded, &other).unwrap().0,
+            input.as_bytes()
+        );
+    }
+
+    #[test]
+    fn text_members_must_decode_to_utf8() {
+        // A structural tree whose first leaf is a surrogate, which no char
+        // can hold.
+        let mut tree = 2u32.to_be_bytes().to_vec();
+        for symb in [0xD800, u32::from('a')] {
+            tree.extend([0, 1]);
+            tree.extend(symb.to_be_bytes());
+            tree.extend(1u32.to_be_bytes());
+        }
+        let mut bytes = Header {
+            flags: 0,
+            symbol_count: 2,
+            bit_count: 2,
+            crc: 0,
+            tree_len: tree.len() as u32,
+        }
+        .to_bytes();
+        bytes.extend(tree);
+        bytes.push(0b0100_0000);
+        assert!(matches!(
+            decompress_bytes(&bytes),
+            Err(RuffmanError::InvalidSymbol(0xD800))
+        ));
+
+        // A stored byte-mode member with its bytes flag cleared claims to be
+        // text it isn't. Its CRC covers only the data, so still matches.
+        let mut stored = Vec::new();
+        write_stored(&[0xFF, 0xFE], &Metadata::default(), 1, &mut stored).unwrap();
+        assert_eq!(decompress_bytes(&stored).unwrap(), [0xFF, 0xFE]);
+        stored[5] &= !FLAG_BYTES;
+        assert!(matches!(
+            decompress_with_metadata(&stored),
+            Err(RuffmanError::InvalidUtf8)
+        ));
+    }
+
+    #[test]
+    fn stored_member_decodes_verbatim() {
+        let text = "abcdefgh";
+        let mut out = Vec::new();
+        let written = write_stored(text.as_bytes(), &Metadata::default(), 8, &mut out).unwrap();
+        assert_eq!(written as usize, out.len());
+        assert_eq!(out.len() % 8, 0);
+        assert!(out.len() < compress(text).len());
+        assert!(describe(&out)[0].metadata.as_ref().unwrap().stored);
+        assert_eq!(decompress(&out).unwrap(), text);
+
+        let binary = [0xFF, 0x00, 0xFE, 0x80, 0x7F];
+        let mut stored = Vec::new();
+        write_stored(&binary, &Metadata::default(), 1, &mut stored).unwrap();
+        assert_eq!(decompress_bytes(&stored).unwrap(), binary);
+        assert!(matches!(
+            decompress(&stored),
+            Err(RuffmanError::InvalidUtf8)
+        ));
+
+        let mut huffman = Huffman::from_input(text.to_owned());
+        huffman.compress();
+        let mut chosen = Vec::new();
+        let bits = huffman.get_compressed();
+        write_member_or_stored(&huffman, &bits, &Metadata::default(), 8, &mut chosen).unwrap();
+        assert_eq!(chosen, out);
+
+        // Stored and coded members mix freely.
+        out.extend(compress("hello"));
+        assert_eq!(decompress(&out).unwrap(), "abcdefghhello");
+        let last = out.len() - 1;
+        out[last] ^= 0xFF;
+        assert_eq!(verify(&out).iter().filter(|r| r.result.is_err()).count(), 1);
+    }
+
+    #[test]
+    fn long_prefix_spans_entries() {
+        let metadata = Metadata {
+            prefix: Some("#".repeat(600)),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Metadata::from_bytes(&metadata.to_bytes()).unwrap(),
+            metadata
+        );
+    }
+
+    #[test]
+    fn rle_helps_run_heavy_input() {
+        let input = format!(
+            "{}{}{}\n{}",
+            "-".repeat(400),
+            "ab".repeat(30),
+            rle::ESCAPE.to_string().repeat(3),
+            " ".repeat(700)
+        );
+
+        let mut huffman = HuffmanBuilder::new()
+            .rle(true)
+            .build(input.clone())
+            .unwrap();
+        huffman.compress();
+        let mut with_rle = Vec::new();
+        write_member(
+            &huffman,
+            &huffman.get_compressed(),
+            &Metadata::default(),
+            &mut with_rle,
+        )
+        .unwrap();
+
+        assert_eq!(with_rle[5] & FLAG_RLE, FLAG_RLE);
+        assert!(with_rle.len() * 2 < compress(&input).len());
+        assert_eq!(decompress(&with_rle).unwrap(), input);
+    }
+
+    #[test]
+    fn lz_beats_plain_huffman_on_repeats() {
+        let input = (0..40)
+            .map(|i| {
+                format!(
+                    "<item id=\"{}\"><name>widget</name><price>9.99</price></item>\n",
+                    i
+                )
+            })
+            .collect::<String>();
+
+        for rle in [false, true] {
+            let mut huffman = HuffmanBuilder::new()
+                .lz(true)
+                .rle(rle)
+                .build(input.clone())
+                .unwrap();
+            huffman.compress();
+            let mut with_lz = Vec::new();
+            write_member(
+                &huffman,
+                &huffman.get_compressed(),
+                &Metadata::default(),
+                &mut with_lz,
+            )
+            .unwrap();
+
+            assert!(with_lz.len() * 2 < compress(&input).len());
+            assert!(read_bounds(&with_lz).unwrap().metadata.lz);
+            assert_eq!(decompress(&with_lz).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn indent_shrinks_indented_code() {
+        let input = (0..60)
+            .map(|i| {
+                let indent = "    ".repeat(i % 6);
+                format!(
+                    "{0}if x{1} {{\n{0}    call({2});\n{0}}}\n",
+                    indent,
+                    i % 7,
+                    i % 3
+                )
+            })
+            .collect::<String>();
+
+        let mut huffman = HuffmanBuilder::new()
+            .indent(true)
+            .build(input.clone())
+            .unwrap();
+        huffman.compress();
+        let mut with_indent = Vec::new();
+        write_member(
+            &huffman,
+            &huffman.get_compressed(),
+            &Metadata::default(),
+            &mut with_indent,
+        )
+        .unwrap();
+
+        assert!(with_indent.len() < compress(&input).len());
+        assert!(read_bounds(&with_indent).unwrap().metadata.indent);
+        assert_eq!(decompress(&with_indent).unwrap(), input);
+    }
+
+    #[test]
+    fn byte_mode_round_trips_binary() {
+        let input = [0, 0xFF, 0, b'a', 0xFF, 0x80, 0x80, 0];
+        let mut huffman = Huffman::from_bytes(&input);
+        huffman.compress();
+
+        let mut bytes = Vec::new();
+        write_member(
+            &huffman,
+            &huffman.get_compressed(),
+            &Metadata::default(),
+            &mut bytes,
+        )
+        .unwrap();
+
+        assert_eq!(bytes[5] & FLAG_BYTES, FLAG_BYTES);
+        assert_eq!(decompress_bytes(&bytes).unwrap(), input);
+        assert!(matches!(decompress(&bytes), Err(RuffmanError::InvalidUtf8)));
+    }
+
+    #[test]
+    fn decompress_into_reuses_buffer() {
+        let mut out = String::from("> ");
+        for input in ["Hello", "", "abracadabra"] {
+            decompress_into(&compress(input), &mut out).unwrap();
+        }
+        assert_eq!(out, "> Helloabracadabra");
+
+        let mut damaged = compress("Hello");
+        damaged[0] = b'X';
+        assert!(decompress_into(&damaged, &mut out).is_err());
+        assert_eq!(out, "> Helloabracadabra");
+
+        let mut bytes = vec![0xFF];
+        decompress_bytes_into(&compress("radar"), &mut bytes).unwrap();
+        assert_eq!(bytes, b"\xFFradar");
+    }
+
+    #[test]
+    fn recover_keeps_decoded_prefix() {
+        let input = "the quick brown fox jumps over the lazy dog ".repeat(10);
+        let bytes = compress(&input);
+
+        let intact = recover(&bytes);
+        assert!(intact.error.is_none());
+        assert_eq!(intact.data, input.as_bytes());
+
+        let recovery = recover(&bytes[..bytes.len() - 100]);
+        assert!(matches!(recovery.error, Some(RuffmanError::Truncated)));
+        assert!(!recovery.data.is_empty());
+        assert!(recovery.data.len() < input.len());
+        assert!(input.as_bytes().starts_with(&recovery.data));
+    }
+
+    #[test]
+    fn metadata_skips_unknown_tags() {
+        let bytes = [0xEE, 2, 0xAB, 0xCD, TAG_MODE, 4, 0, 0, 0x01, 0xA4];
+
+        assert_eq!(
+            Metadata::from_bytes(&bytes).unwrap(),
+            Metadata {
+                mode: Some(0o644),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn writes_current_format_version() {
+        let bytes = compress("Hello");
+
+        assert_eq!(bytes[..4], MAGIC);
+        assert_eq!(bytes[4], FORMAT_VERSION);
+
+        let mut newer = bytes.clone();
+        newer[4] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            decompress(&newer),
+            Err(RuffmanError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn recompressing_warns_or_refuses() {
+        let bytes = compress("Hello");
+
+        assert_eq!(
+            check_recompress(&bytes, false).unwrap().as_deref(),
+            Some("input appears already compressed")
+        );
+        assert!(matches!(
+            check_recompress(&bytes, true),
+            Err(RuffmanError::AlreadyCompressed)
+        ));
+        assert!(check_recompress(b"Hello", true).unwrap().is_none());
+        assert!(check_recompress(b"RUF", true).unwrap().is_none());
+        assert!(is_compressed(&crate::archive::MAGIC));
+    }
+
+    #[test]
+    fn optimizing_preset_file_shrinks_it() {
+        use crate::preset;
+
+        let preset_file = |input: &str| {
+            let mut huffman = Huffman::with_tree(preset::english(), input.to_owned()).unwrap();
+            huffman.compress();
+            let mut out = Vec::new();
+            trained::write_payload(&huffman, &huffman.get_compressed(), &mut out).unwrap();
+            out
+        };
+
+        // Far from English frequencies, so its own tree pays for itself.
+        let input = "zzzz qqqq xxxx zzzz\n".repeat(50);
+        let bytes = preset_file(&input);
+        let data = trained::read_payload(&preset::english(), &bytes).unwrap();
+        let optimized = optimize(data.as_bytes(), &Metadata::default(), bytes.len())
+            .unwrap()
+            .unwrap();
+        assert!(optimized.len() < bytes.len());
+        assert_eq!(decompress(&optimized).unwrap(), input);
+
+        // Too short for a stored tree to be worth it.
+        let input = "The quick brown fox.";
+        let bytes = preset_file(input);
+        assert!(
+            optimize(input.as_bytes(), &Metadata::default(), bytes.len())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn decoded_members_arrive_through_channel() {
+        use std::sync::mpsc;
+
+        let mut bytes = compress("first member, ");
+        bytes.extend(compress("second member"));
+
+        let (tx, rx) = mpsc::channel();
+        let reader = io::Cursor::new(bytes.clone());
+        let decoder = std::thread::spawn(move || decode_to_sender(reader, tx));
+        let chunks = rx.iter().collect::<io::Result<Vec<_>>>().unwrap();
+        decoder.join().unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks.concat(), b"first member, second member");
+
+        // A cut-off member is reported after the ones before it.
+        let (tx, rx) = mpsc::channel();
+        decode_to_sender(&bytes[..bytes.len() - 1], tx);
+        let results = rx.iter().collect::<Vec<_>>();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap(), b"first member, ");
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn limit_checks_declared_size() {
+        let input = "the rain in spain stays mainly in the plain";
+        let bytes = compress(input);
+
+        let (output, _) = decompress_limited(&bytes, input.len() as u64).unwrap();
+        assert_eq!(output, input.as_bytes());
+        assert!(matches!(
+            decompress_limited(&bytes, 10),
+            Err(RuffmanError::OutputTooLarge)
+        ));
+
+        // Two members: the limit covers them together.
+        let twice = [bytes.clone(), bytes].concat();
+        assert!(decompress_limited(&twice, 2 * input.len() as u64).is_ok());
+        assert!(matches!(
+            decompress_limited(&twice, 2 * input.len() as u64 - 1),
+            Err(RuffmanError::OutputTooLarge)
+        ));
+    }
+
+    #[test]
+    fn limit_checks_decoded_size() {
+        // A header understating its symbol count gets no further than the
+        // limit, long before its checksum would catch it.
+        let mut bytes = compress(&"ab".repeat(50));
+        assert_eq!(bytes[6], 100);
+        bytes[6] = 1;
+        assert!(matches!(
+            decompress_limited(&bytes, 10),
+            Err(RuffmanError::OutputTooLarge)
+        ));
+
+        // A few run-length tokens expand to far more than they declare.
+        let input = "z".repeat(100_000);
+        let mut huffman = HuffmanBuilder::new().rle(true).build(input).unwrap();
+        huffman.compress();
+        let mut bomb = Vec::new();
+        let bits = huffman.get_compressed();
+        write_member(&huffman, &bits, &Metadata::default(), &mut bomb).unwrap();
+        assert!(bomb.len() < 100);
+        assert!(matches!(
+            decompress_limited(&bomb, 1000),
+            Err(RuffmanError::OutputTooLarge)
+        ));
+        assert_eq!(decompress_limited(&bomb, 100_000).unwrap().0.len(), 100_000);
+    }
+
+    #[test]
+    fn strict_rejects_optional_features() {
+        let input = "#!/bin/sh\necho hello\n";
+        let mut huffman = Huffman::from_input(input.to_owned());
+        huffman.compress();
+        let bits = huffman.get_compressed();
+        let mut metadata = Metadata {
+            prefix: Some("#!/bin/sh\n".to_owned()),
+            ..Default::default()
+        };
+
+        let mut with_metadata = Vec::new();
+        write_member(&huffman, &bits, &metadata, &mut with_metadata).unwrap();
+        assert!(decompress(&with_metadata).is_ok());
+        assert!(matches!(
+            check_strict(&with_metadata),
+            Err(RuffmanError::NonstandardFeature("a metadata block"))
+        ));
+
+        metadata.no_checksum = true;
+        let mut unchecked = Vec::new();
+        write_member(&huffman, &bits, &metadata, &mut unchecked).unwrap();
+        assert!(matches!(
+            check_strict(&unchecked),
+            Err(RuffmanError::NonstandardFeature("a missing checksum"))
+        ));
+
+        let mut without_tree = Vec::new();
+        write_member_without_tree(&huffman, &bits, &Metadata::default(), 1, &mut without_tree)
+            .unwrap();
+        assert!(matches!(
+            check_strict(&without_tree),
+            Err(RuffmanError::NonstandardFeature("an external tree"))
+        ));
+
+        let mut stored = Vec::new();
+        write_stored(b"ok", &Metadata::default(), 1, &mut stored).unwrap();
+        assert!(check_strict(&stored).is_ok());
+
+        // Only the second member of these is nonstandard.
+        let plain = compress(input);
+        assert!(check_strict(&plain).is_ok());
+        assert!(check_strict(&[plain, with_metadata].concat()).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = compress("Hello");
+        bytes[0] = b'X';
+
+        assert!(matches!(
+            decompress(&bytes),
+            Err(RuffmanError::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_member() {
+        let bytes = compress("Hello");
+
+        assert!(matches!(
+            decompress(&bytes[..bytes.len() - 1]),
+            Err(RuffmanError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,294 @@
+//! Table-driven decoding.
+//!
+//! Walking the tree costs a branch per bit. Instead, the next
+//! [`TABLE_BITS`] bits index a table giving the symbol they start with and
+//! how many bits its code uses, so short codes decode in one lookup. Codes
+//! longer than the table width fall back to walking the tree.
+//!
+//! A tree with an escape symbol (see [`crate::literals`]) has the escaped
+//! symbol's literal read straight from the bits after the escape's code.
+
+use bitvec::prelude::*;
+
+use crate::{literals, Node, RuffmanError};
+
+/// Width of the lookup index in bits.
+pub const TABLE_BITS: u8 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Entry {
+    /// A code of `len` bits for `symb` is a prefix of the index.
+    Symbol { symb: char, len: u8 },
+    /// The index is a prefix of a code longer than the table width.
+    Long,
+}
+
+pub struct DecodeTable<'a> {
+    tree: &'a Node,
+    entries: Vec<Entry>,
+    escape: Option<char>,
+    limit: usize,
+}
+
+impl<'a> DecodeTable<'a> {
+    pub fn new(tree: &'a Node) -> Self {
+        let mut entries = vec![Entry::Long; 1 << TABLE_BITS];
+
+        fn fill(node: &Node, code: usize, len: u8, entries: &mut [Entry]) {
+            match node {
+                Node::Leaf(leaf) => {
+                    let spare = TABLE_BITS - len;
+                    let first = code << spare;
+                    for entry in &mut entries[first..first + (1 << spare)] {
+                        *entry = Entry::Symbol {
+                            symb: leaf.symb,
+                            len,
+                        };
+                    }
+                }
+                Node::Internal(internal) => {
+                    if len == TABLE_BITS {
+                        return;
+                    }
+
+                    if let Some(left) = &internal.left {
+                        fill(left, code << 1, len + 1, entries);
+                    }
+
+                    if let Some(right) = &internal.right {
+                        fill(right, (code << 1) | 1, len + 1, entries);
+                    }
+                }
+            }
+        }
+
+        match tree {
+            // A lone leaf is encoded as one bit per symbol.
+            Node::Leaf(leaf) => entries.fill(Entry::Symbol {
+                symb: leaf.symb,
+                len: 1,
+            }),
+            _ => fill(tree, 0, 0, &mut entries),
+        }
+
+        Self {
+            tree,
+            entries,
+            escape: None,
+            limit: usize::MAX,
+        }
+    }
+
+    /// Treats `escape`, if given, as the tree's escape symbol.
+    pub fn escape(mut self, escape: Option<char>) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Stops decoding after `limit` symbols, however many more the bits
+    /// hold, so a caller bounding its output never decodes much past it.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Decodes every complete code in `bits`. Produces exactly what
+    /// [`crate::Huffman::decompress`] does for the same bits.
+    pub fn decode(&self, bits: &BitSlice<u8, Msb0>) -> String {
+        let mut result = String::new();
+        self.decode_with(bits, |symb| result.push(symb));
+        result
+    }
+
+    /// Like [`DecodeTable::decode`], but appends the text's UTF-8 bytes to
+    /// `out`.
+    pub fn decode_into(&self, bits: &BitSlice<u8, Msb0>, out: &mut Vec<u8>) {
+        self.decode_with(bits, |symb| {
+            out.extend_from_slice(symb.encode_utf8(&mut [0; 4]).as_bytes())
+        });
+    }
+
+    /// Like [`DecodeTable::decode_into`] for a byte-mode tree, whose symbols
+    /// stand for the bytes of the same value. Fails if the tree has other
+    /// symbols.
+    pub fn decode_bytes_into(
+        &self,
+        bits: &BitSlice<u8, Msb0>,
+        out: &mut Vec<u8>,
+    ) -> Result<(), RuffmanError> {
+        let mut fits = true;
+        self.decode_with(bits, |symb| match u8::try_from(symb) {
+            Ok(byte) => out.push(byte),
+            Err(_) => fits = false,
+        });
+
+        if !fits {
+            return Err(RuffmanError::CorruptTree);
+        }
+
+        Ok(())
+    }
+
+    /// Stops early at an incomplete code or an invalid literal, which the
+    /// caller's checksum catches, or at the limit.
+    fn decode_with(&self, bits: &BitSlice<u8, Msb0>, mut push: impl FnMut(char)) {
+        let mut pos = 0;
+        let mut decoded = 0;
+
+        while pos < bits.len() && decoded < self.limit {
+            let remaining = bits.len() - pos;
+            let take = remaining.min(TABLE_BITS as usize);
+            let index = bits[pos..pos + take].load_be::<usize>() << (TABLE_BITS as usize - take);
+
+            let (symb, next) = match self.entries[index] {
+                Entry::Symbol { symb, len } if len as usize <= remaining => {
+                    (Some(symb), pos + len as usize)
+                }
+                _ => self.walk(bits, pos),
+            };
+            pos = next;
+
+            let Some(mut symb) = symb else {
+                break;
+            };
+            if Some(symb) == self.escape {
+                let Some(literal) = literals::read(&bits[pos..]) else {
+                    break;
+                };
+                symb = literal;
+                pos += literals::LITERAL_BITS;
+            }
+            push(symb);
+            decoded += 1;
+        }
+    }
+
+    /// Decodes one code bit by bit starting at `pos`, returning its symbol,
+    /// or `None` if the bits run out first, and the position after it.
+    fn walk(&self, bits: &BitSlice<u8, Msb0>, mut pos: usize) -> (Option<char>, usize) {
+        let mut node = self.tree;
+
+        while pos < bits.len() {
+            if let Node::Internal(internal) = node {
+                let child = match bits[pos] {
+                    false => &internal.left,
+                    true => &internal.right,
+                };
+                if let Some(child) = child {
+                    node = child;
+                }
+            }
+            pos += 1;
+
+            if let Node::Leaf(leaf) = node {
+                return (Some(leaf.symb), pos);
+            }
+        }
+
+        (None, pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::Huffman;
+
+    /// xorshift64, so the tests don't need a rand dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    fn random_input(rng: &mut Rng, len: usize) -> String {
+        let symbols = 1 + rng.below(80) as u32;
+        // Skewed weights give a mix of short and long codes.
+        let weights = (0..symbols)
+            .map(|_| {
+                let scale = rng.below(12);
+                1 + rng.below(1 << scale)
+            })
+            .collect::<Vec<_>>();
+        let total: u64 = weights.iter().sum();
+
+        (0..len)
+            .map(|_| {
+                let mut pick = rng.below(total);
+                let mut i = 0;
+                while pick >= weights[i] {
+                    pick -= weights[i];
+                    i += 1;
+                }
+                char::from_u32(0x21 + i as u32).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_naive_decoder_on_random_trees() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+
+        for _ in 0..200 {
+            let len = 1 + rng.below(400) as usize;
+            let input = random_input(&mut rng, len);
+            let mut huffman = Huffman::from_input(input.clone());
+            huffman.compress();
+
+            let mut encoded = huffman.get_compressed();
+            // Also cover a partial code at the end.
+            for _ in 0..rng.below(4) {
+                encoded.push(rng.below(2) as u8);
+            }
+            let bits = encoded
+                .iter()
+                .map(|b| *b == 1)
+                .collect::<BitVec<u8, Msb0>>();
+
+            let fast = DecodeTable::new(&huffman.tree).decode(&bits);
+            assert_eq!(fast, huffman.decompress(encoded));
+            assert!(fast.starts_with(&input));
+        }
+    }
+
+    #[test]
+    #[ignore = "benchmark; run with --ignored --nocapture"]
+    fn bench_against_tree_walk() {
+        let mut rng = Rng(42);
+        let input = random_input(&mut rng, 200_000);
+        let mut huffman = Huffman::from_input(input);
+        huffman.compress();
+        let encoded = huffman.get_compressed();
+        let bits = encoded
+            .iter()
+            .map(|b| *b == 1)
+            .collect::<BitVec<u8, Msb0>>();
+
+        let start = Instant::now();
+        let naive = huffman.decompress(encoded);
+        let naive_time = start.elapsed();
+
+        let start = Instant::now();
+        let fast = DecodeTable::new(&huffman.tree).decode(&bits);
+        let fast_time = start.elapsed();
+
+        assert_eq!(naive, fast);
+        println!(
+            "tree walk {:?}, table {:?} ({:.1}x)",
+            naive_time,
+            fast_time,
+            naive_time.as_secs_f64() / fast_time.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Text in encodings other than UTF-8, such as Latin-1 or Shift-JIS.
+//!
+//! Input is decoded to text before it is compressed, and the encoding's name
+//! is kept in the member's metadata so decompression can encode the text
+//! back to the original bytes.
+
+use encoding_rs::Encoding;
+
+use crate::RuffmanError;
+
+fn lookup(label: &str) -> Result<&'static Encoding, RuffmanError> {
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| RuffmanError::UnknownEncoding(label.to_owned()))
+}
+
+/// Decodes `bytes` as text in the encoding named by `label`, returning the
+/// text and the encoding's canonical name. Fails unless encoding the text
+/// again gives back exactly `bytes`.
+pub fn decode(bytes: &[u8], label: &str) -> Result<(String, &'static str), RuffmanError> {
+    let encoding = lookup(label)?;
+    let text = encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .ok_or_else(|| RuffmanError::NotInEncoding(encoding.name().to_owned()))?;
+
+    if encode(&text, encoding.name())? != bytes {
+        return Err(RuffmanError::NotInEncoding(encoding.name().to_owned()));
+    }
+
+    Ok((text.into_owned(), encoding.name()))
+}
+
+/// Encodes `text` in the encoding named by `label`.
+pub fn encode(text: &str, label: &str) -> Result<Vec<u8>, RuffmanError> {
+    let encoding = lookup(label)?;
+    // Encodings that can't be written, such as UTF-16, fall back to UTF-8.
+    let (bytes, used, unmappable) = encoding.encode(text);
+    if unmappable || used != encoding {
+        return Err(RuffmanError::NotInEncoding(encoding.name().to_owned()));
+    }
+
+    Ok(bytes.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{container, Huffman};
+
+    #[test]
+    fn latin1_round_trips_exactly() {
+        let input = b"caf\xe9 na\xefve, \xa3 5 \xb1 \xbd".to_vec();
+        let (text, name) = decode(&input, "latin1").unwrap();
+        assert_eq!(text, "café naïve, £ 5 ± ½");
+
+        let mut huffman = Huffman::from_input(text);
+        huffman.compress();
+        let mut metadata = container::Metadata::default();
+        metadata.encoding = Some(name.to_owned());
+
+        let mut out = Vec::new();
+        container::write_member(&huffman, &huffman.get_compressed(), &metadata, &mut out).unwrap();
+        let (output, metadata) = container::decompress_with_metadata(&out).unwrap();
+        let label = metadata.encoding.unwrap();
+
+        assert_eq!(
+            encode(std::str::from_utf8(&output).unwrap(), &label).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_inexact_encodings() {
+        assert!(matches!(
+            decode(b"abc", "klingon"),
+            Err(RuffmanError::UnknownEncoding(_))
+        ));
+        assert!(matches!(
+            decode(b"\x81", "shift_jis"),
+            Err(RuffmanError::NotInEncoding(_))
+        ));
+        assert!(matches!(
+            encode("日本", "latin1"),
+            Err(RuffmanError::NotInEncoding(_))
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+//! Recording a file's line endings so the text can be compressed with bare
+//! `\n`s.
+//!
+//! A CRLF file spends a symbol on every `\r`. [`record`] turns its endings
+//! into `\n` and reports what they were, for [`restore`] to put back after
+//! decompression. Only a file whose endings all agree is converted: one odd
+//! ending couldn't be reproduced by restoring a single kind, so such files,
+//! and files with no line endings at all, are compressed as they are.
+
+use crate::RuffmanError;
+
+/// The line ending a whole file uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    pub(crate) fn to_byte(self) -> u8 {
+        match self {
+            LineEnding::Lf => 0,
+            LineEnding::CrLf => 1,
+            LineEnding::Cr => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Result<Self, RuffmanError> {
+        match byte {
+            0 => Ok(LineEnding::Lf),
+            1 => Ok(LineEnding::CrLf),
+            2 => Ok(LineEnding::Cr),
+            _ => Err(RuffmanError::CorruptMetadata),
+        }
+    }
+}
+
+/// The one line ending `text` uses throughout, or `None` if it has none or
+/// mixes them. A `\r` directly before a `\n` is part of a CRLF, never a CR
+/// of its own.
+pub fn detect(text: &str) -> Option<LineEnding> {
+    let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+    let mut bytes = text.bytes().peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\r' if bytes.peek() == Some(&b'\n') => {
+                bytes.next();
+                crlf += 1;
+            }
+            b'\r' => cr += 1,
+            b'\n' => lf += 1,
+            _ => {}
+        }
+    }
+
+    match (lf, crlf, cr) {
+        (1.., 0, 0) => Some(LineEnding::Lf),
+        (0, 1.., 0) => Some(LineEnding::CrLf),
+        (0, 0, 1..) => Some(LineEnding::Cr),
+        _ => None,
+    }
+}
+
+/// Converts `text`'s line endings to `\n` if they all agree, returning the
+/// converted text and the ending to record. Otherwise returns `text`
+/// unchanged and `None`.
+pub fn record(text: &str) -> (String, Option<LineEnding>) {
+    let ending = detect(text);
+    let text = match ending {
+        Some(LineEnding::CrLf) => text.replace("\r\n", "\n"),
+        Some(LineEnding::Cr) => text.replace('\r', "\n"),
+        Some(LineEnding::Lf) | None => text.to_owned(),
+    };
+
+    (text, ending)
+}
+
+/// Turns the `\n`s of text converted by [`record`] back into `ending`.
+pub fn restore(text: &[u8], ending: LineEnding) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len());
+    for &byte in text {
+        match byte {
+            b'\n' => out.extend_from_slice(ending.as_str().as_bytes()),
+            byte => out.push(byte),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{container, container::Metadata, Huffman};
+
+    fn round_trip(input: &str) -> (Vec<u8>, Metadata) {
+        let (text, ending) = record(input);
+        let mut huffman = Huffman::from_input(text);
+        huffman.compress();
+        let mut metadata = Metadata::default();
+        metadata.line_ending = ending;
+
+        let mut out = Vec::new();
+        container::write_member(&huffman, &huffman.get_compressed(), &metadata, &mut out).unwrap();
+        container::decompress_with_metadata(&out).unwrap()
+    }
+
+    #[test]
+    fn consistent_endings_are_recorded() {
+        for (input, ending) in [
+            ("one\ntwo\n", LineEnding::Lf),
+            ("one\r\ntwo\r\n", LineEnding::CrLf),
+            ("one\rtwo\r", LineEnding::Cr),
+            ("no newline until the end\r", LineEnding::Cr),
+            ("\n", LineEnding::Lf),
+        ] {
+            let (output, metadata) = round_trip(input);
+            assert_eq!(output, input.as_bytes());
+            assert_eq!(metadata.line_ending, Some(ending));
+            assert!(!record(input).0.contains('\r'));
+        }
+    }
+
+    #[test]
+    fn missing_or_mixed_endings_are_kept_raw() {
+        for input in [
+            "",
+            "no newline at all",
+            "one\r\ntwo\n",
+            "one\rtwo\n",
+            "one\r\ntwo\rthree\r\n",
+            "\n\r",
+        ] {
+            assert_eq!(record(input), (input.to_owned(), None));
+
+            let (output, metadata) = round_trip(input);
+            assert_eq!(output, input.as_bytes());
+            assert_eq!(metadata.line_ending, None);
+        }
+    }
+}
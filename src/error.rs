@@ -0,0 +1,137 @@
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum RuffmanError {
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    CorruptTree,
+    CorruptMetadata,
+    CorruptRle,
+    CorruptLz,
+    CorruptIndent,
+    CorruptVarint,
+    MissingChild,
+    TreeSymbolMismatch { expected: usize, found: usize },
+    DuplicateSymbol(char),
+    UnknownSymbol(char),
+    ChecksumMismatch { expected: u32, found: u32 },
+    SizeMismatch { expected: u32, found: u32 },
+    InvalidUtf8,
+    AlphabetTooLarge { found: usize, max: usize },
+    UnknownEncoding(String),
+    NotInEncoding(String),
+    InvalidBase64,
+    MissingTree,
+    UnknownNormalization(String),
+    Cancelled,
+    UnsafePath(String),
+    InvalidCodeLengths,
+    UnknownPreset(String),
+    AlreadyCompressed,
+    OutputTooLarge,
+    MissingValueTable,
+    NonstandardFeature(&'static str),
+    InvalidSymbol(u32),
+    TreeMismatch,
+    CodeTooLong { length: usize, max: usize },
+    RoundTripMismatch,
+}
+
+impl fmt::Display for RuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuffmanError::InvalidMagic => write!(f, "not a ruffman file"),
+            RuffmanError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            RuffmanError::Truncated => write!(f, "file is truncated"),
+            RuffmanError::CorruptTree => write!(f, "tree data is malformed"),
+            RuffmanError::CorruptMetadata => write!(f, "metadata is malformed"),
+            RuffmanError::CorruptRle => write!(f, "run-length data is malformed"),
+            RuffmanError::CorruptLz => write!(f, "LZ data is malformed"),
+            RuffmanError::CorruptIndent => write!(f, "indentation data is malformed"),
+            RuffmanError::CorruptVarint => write!(f, "variable-length integer is malformed"),
+            RuffmanError::MissingChild => write!(f, "tree has an internal node with one child"),
+            RuffmanError::TreeSymbolMismatch { expected, found } => write!(
+                f,
+                "tree declares {} symbols but contains {}",
+                expected, found
+            ),
+            RuffmanError::DuplicateSymbol(symb) => {
+                write!(f, "tree contains symbol {:?} more than once", symb)
+            }
+            RuffmanError::UnknownSymbol(symb) => {
+                write!(f, "symbol {:?} is not in the allowed alphabet", symb)
+            }
+            RuffmanError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch: expected {:08x}, found {:08x}",
+                expected, found
+            ),
+            RuffmanError::SizeMismatch { expected, found } => write!(
+                f,
+                "size mismatch: expected {} bytes, found {}",
+                expected, found
+            ),
+            RuffmanError::InvalidUtf8 => write!(f, "decoded data is not UTF-8 text"),
+            RuffmanError::AlphabetTooLarge { found, max } => write!(
+                f,
+                "input has {} distinct symbols, more than the limit of {}; try byte mode",
+                found, max
+            ),
+            RuffmanError::UnknownEncoding(label) => write!(f, "unknown text encoding {:?}", label),
+            RuffmanError::NotInEncoding(name) => {
+                write!(f, "data does not round-trip exactly through {}", name)
+            }
+            RuffmanError::InvalidBase64 => write!(f, "input is not valid base64"),
+            RuffmanError::UnknownNormalization(form) => {
+                write!(f, "unknown normalization form {:?}", form)
+            }
+            RuffmanError::Cancelled => write!(f, "operation was cancelled"),
+            RuffmanError::UnsafePath(path) => {
+                write!(f, "archive path {:?} escapes the output directory", path)
+            }
+            RuffmanError::InvalidSymbol(value) => write!(
+                f,
+                "tree contains {:#X}, which is not a Unicode scalar value",
+                value
+            ),
+            RuffmanError::NonstandardFeature(feature) => {
+                write!(f, "file uses {}, which strict mode rejects", feature)
+            }
+            RuffmanError::MissingValueTable => {
+                write!(f, "member has no table of the values its symbols stand for")
+            }
+            RuffmanError::OutputTooLarge => write!(f, "output exceeds the size limit"),
+            RuffmanError::AlreadyCompressed => write!(f, "input appears already compressed"),
+            RuffmanError::UnknownPreset(name) => write!(f, "unknown preset {:?}", name),
+            RuffmanError::InvalidCodeLengths => {
+                write!(f, "code lengths do not form a complete prefix code")
+            }
+            RuffmanError::TreeMismatch => {
+                write!(f, "tree is not the one the data was compressed against")
+            }
+            RuffmanError::RoundTripMismatch => {
+                write!(f, "text does not decode back to itself with this tree")
+            }
+            RuffmanError::CodeTooLong { length, max } => write!(
+                f,
+                "tree has a {}-bit code, longer than the limit of {}",
+                length, max
+            ),
+            RuffmanError::MissingTree => write!(
+                f,
+                "data was compressed without its tree; the tree it was compressed against is needed"
+            ),
+        }
+    }
+}
+
+impl error::Error for RuffmanError {}
+
+impl From<RuffmanError> for io::Error {
+    fn from(value: RuffmanError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, value)
+    }
+}
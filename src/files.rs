@@ -0,0 +1,769 @@
+//! Filesystem helpers for the CLI.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// Prefix of the temporary files outputs are written to before being
+/// renamed into place.
+pub const TEMP_PREFIX: &str = ".ruffman-tmp-";
+
+/// Temp files untouched for this long are assumed to be left over from an
+/// interrupted run.
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Starts every part written by [`write_split`].
+const PART_MAGIC: [u8; 4] = *b"RFSP";
+
+/// Size of the header on each part: magic, u32 part number counting from 1,
+/// and a u8 that is 1 on the last part and 0 otherwise.
+pub const PART_HEADER_LEN: u64 = 4 + 4 + 1;
+
+/// Capacity of the buffer [`buffered`] puts in front of an output, and
+/// [`Stream::reader`] behind an input, unless told otherwise.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Size of the blocks input that can't be seeked, such as a pipe, is
+/// compressed in when no block size is given.
+pub const DEFAULT_STREAM_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Size above which a file is compressed in blocks as it is read, as input
+/// that can't be seeked is, rather than read into memory whole.
+pub const DEFAULT_STREAM_THRESHOLD: u64 = 1024 * 1024 * 1024;
+
+/// An input or output of the CLI: a file, or `-` for stdin or stdout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Std,
+    File(PathBuf),
+}
+
+impl Stream {
+    pub fn parse(arg: &str) -> Self {
+        match arg {
+            "-" => Stream::Std,
+            path => Stream::File(PathBuf::from(path)),
+        }
+    }
+
+    /// Reads all of stdin or the file.
+    pub fn read_all(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Stream::Std => {
+                let mut bytes = Vec::new();
+                io::stdin().lock().read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Stream::File(path) => fs::read(path),
+        }
+    }
+
+    /// Opens stdin or the file for reading a piece at a time, through a
+    /// buffer of `capacity` bytes.
+    pub fn reader(&self, capacity: usize) -> io::Result<Box<dyn Read>> {
+        match self {
+            Stream::Std => Ok(Box::new(BufReader::with_capacity(
+                capacity,
+                io::stdin().lock(),
+            ))),
+            Stream::File(path) => Ok(Box::new(BufReader::with_capacity(
+                capacity,
+                File::open(path)?,
+            ))),
+        }
+    }
+
+    /// Whether the input should be read a piece at a time rather than
+    /// whole: when its length isn't known, or is over `threshold` bytes.
+    pub fn should_stream(&self, threshold: u64) -> io::Result<bool> {
+        match self {
+            Stream::File(path) => Ok(path.metadata()?.len() > threshold),
+            Stream::Std => Ok(!self.is_seekable()),
+        }
+    }
+
+    /// Whether the input can be seeked, so its length is known before
+    /// reading it. A file can be; stdin can't when it is a pipe or terminal.
+    /// Off Unix stdin is assumed seekable.
+    pub fn is_seekable(&self) -> bool {
+        match self {
+            Stream::File(_) => true,
+            #[cfg(unix)]
+            Stream::Std => {
+                use std::os::fd::AsFd;
+
+                let Ok(fd) = io::stdin().as_fd().try_clone_to_owned() else {
+                    return false;
+                };
+                File::from(fd).stream_position().is_ok()
+            }
+            #[cfg(not(unix))]
+            Stream::Std => true,
+        }
+    }
+}
+
+/// Resolves the input and output arguments of a command. Input `-` reads
+/// stdin and, with no output given, writes to stdout; otherwise an output is
+/// required, and may itself be `-` for stdout.
+pub fn resolve_streams(input: &str, output: Option<&str>) -> io::Result<(Stream, Stream)> {
+    let input = Stream::parse(input);
+    let output = match (output, &input) {
+        (Some(output), _) => Stream::parse(output),
+        (None, Stream::Std) => Stream::Std,
+        (None, Stream::File(_)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Missing output path; pass - to write to stdout",
+            ))
+        }
+    };
+
+    Ok((input, output))
+}
+
+/// Permission bits of the file at `path`. Always `None` off Unix.
+pub fn file_mode(path: &Path) -> io::Result<Option<u32>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        Ok(Some(path.metadata()?.permissions().mode() & 0o7777))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
+
+/// Creates a new file at `path`, failing if it already exists.
+///
+/// On Unix, a stored `mode` is requested at creation so the process umask
+/// still applies; with `exact`, the mode is then set verbatim. Elsewhere the
+/// mode is ignored.
+pub fn create_output(path: &Path, mode: Option<u32>, exact: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::{fs::Permissions, os::unix::fs::OpenOptionsExt, os::unix::fs::PermissionsExt};
+
+        if let Some(mode) = mode {
+            options.mode(mode);
+            let file = options.open(path)?;
+            if exact {
+                file.set_permissions(Permissions::from_mode(mode))?;
+            }
+
+            return Ok(file);
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = (mode, exact);
+
+    options.open(path)
+}
+
+/// Removes the temp file on drop unless it was renamed into place, so errors
+/// and panics alike leave nothing behind.
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Creates `path` atomically: `write` fills a temp file in the same
+/// directory, which is renamed over `path` only if it succeeds. Fails if
+/// `path` already exists. `mode` and `exact` are as for [`create_output`].
+pub fn write_atomic<T>(
+    path: &Path,
+    mode: Option<u32>,
+    exact: bool,
+    write: impl FnOnce(&mut File) -> io::Result<T>,
+) -> io::Result<T> {
+    if path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+
+    write_renamed(path, mode, exact, write)
+}
+
+/// Like [`write_atomic`], but replaces the existing file at `path`, keeping
+/// its permissions. The old contents stay in place until `write` succeeds.
+pub fn replace_atomic<T>(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<T>,
+) -> io::Result<T> {
+    write_renamed(path, file_mode(path)?, true, write)
+}
+
+fn write_renamed<T>(
+    path: &Path,
+    mode: Option<u32>,
+    exact: bool,
+    write: impl FnOnce(&mut File) -> io::Result<T>,
+) -> io::Result<T> {
+    let dir = parent_dir(path);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut temp = TempFile {
+        path: dir.join(format!("{}{}-{}", TEMP_PREFIX, std::process::id(), name)),
+        persisted: false,
+    };
+
+    let mut file = create_output(&temp.path, mode, exact)?;
+    let result = write(&mut file)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&temp.path, path)?;
+    temp.persisted = true;
+
+    Ok(result)
+}
+
+/// Path of part `number`, counting from 1, of output split across files:
+/// `out.001`, `out.002` and so on.
+pub fn part_path(base: &Path, number: u32) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(format!(".{:03}", number));
+    PathBuf::from(path)
+}
+
+/// Writes a numbered part as a temp file, then moves on to the next.
+struct SplitWriter<'a> {
+    base: &'a Path,
+    part_size: u64,
+    parts: Vec<(TempFile, PathBuf)>,
+    current: Option<File>,
+    /// Bytes in the current part, header included.
+    written: u64,
+}
+
+impl SplitWriter<'_> {
+    fn start_part(&mut self) -> io::Result<()> {
+        if let Some(file) = self.current.take() {
+            file.sync_all()?;
+        }
+
+        let number = self.parts.len() as u32 + 1;
+        let path = part_path(self.base, number);
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = TempFile {
+            path: parent_dir(&path).join(format!("{}{}-{}", TEMP_PREFIX, std::process::id(), name)),
+            persisted: false,
+        };
+        let mut file = create_output(&temp.path, None, false)?;
+        file.write_all(&PART_MAGIC)?;
+        file.write_all(&number.to_be_bytes())?;
+        file.write_all(&[0])?;
+
+        self.parts.push((temp, path));
+        self.current = Some(file);
+        self.written = PART_HEADER_LEN;
+        Ok(())
+    }
+
+    /// Marks the current part as the last and renames every part into place.
+    fn finish(mut self) -> io::Result<()> {
+        if self.current.is_none() {
+            self.start_part()?;
+        }
+
+        let mut file = self.current.take().unwrap();
+        file.seek(SeekFrom::Start(PART_HEADER_LEN - 1))?;
+        file.write_all(&[1])?;
+        file.sync_all()?;
+        drop(file);
+
+        for (temp, path) in &mut self.parts {
+            fs::rename(&temp.path, path)?;
+            temp.persisted = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for SplitWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.current.is_none() || self.written == self.part_size {
+            self.start_part()?;
+        }
+
+        let room = (self.part_size - self.written) as usize;
+        let written = self
+            .current
+            .as_mut()
+            .unwrap()
+            .write(&buf[..buf.len().min(room)])?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Like [`write_atomic`], but spreads what `write` produces across
+/// [`part_path`]s of `base`, each at most `part_size` bytes, streaming rather
+/// than holding the output in memory. The parts are renamed into place only
+/// if `write` succeeds. Read them back with [`read_split`].
+pub fn write_split<T>(
+    base: &Path,
+    part_size: u64,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+) -> io::Result<T> {
+    if part_size <= PART_HEADER_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("split size must be more than {} bytes", PART_HEADER_LEN),
+        ));
+    }
+
+    let mut writer = SplitWriter {
+        base,
+        part_size,
+        parts: Vec::new(),
+        current: None,
+        written: 0,
+    };
+    let result = write(&mut writer)?;
+    writer.finish()?;
+
+    Ok(result)
+}
+
+/// Runs `write` against `out` through a buffer of `capacity` bytes, flushing
+/// it afterwards. The capacity only changes how the bytes reach `out`, never
+/// the bytes themselves.
+pub fn buffered<T>(
+    out: &mut dyn Write,
+    capacity: usize,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut writer = BufWriter::with_capacity(capacity, out);
+    let result = write(&mut writer)?;
+    writer.flush()?;
+
+    Ok(result)
+}
+
+/// Reassembles the output [`write_split`] spread across the parts of
+/// `base`. Fails if a part is missing, out of order or malformed.
+pub fn read_split(base: &Path) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+
+    for number in 1.. {
+        let path = part_path(base, number);
+        let part = fs::read(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("part {} is missing", path.display()),
+            ),
+            _ => e,
+        })?;
+
+        let header_len = PART_HEADER_LEN as usize;
+        let valid = part.len() >= header_len
+            && part[0..4] == PART_MAGIC
+            && part[4..8] == number.to_be_bytes()
+            && part[8] <= 1;
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not part {} of a split file", path.display(), number),
+            ));
+        }
+
+        output.extend_from_slice(&part[header_len..]);
+        if part[8] == 1 {
+            break;
+        }
+    }
+
+    Ok(output)
+}
+
+/// Deletes temp files in `dir` that an interrupted run left behind.
+/// Returns how many were removed.
+pub fn clean_stale_temps(dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX) {
+            continue;
+        }
+
+        let modified = entry.metadata()?.modified()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age >= STALE_AFTER && fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// The directory `path` lives in, treating a bare file name as relative to
+/// the current directory.
+pub fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ruffman-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn temp_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with(TEMP_PREFIX)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn atomic_write_success() {
+        use std::io::Write;
+
+        let dir = test_dir("atomic-ok");
+        let path = dir.join("out.huf");
+
+        write_atomic(&path, None, false, |file| file.write_all(b"data")).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"data");
+        assert!(temp_files(&dir).is_empty());
+        assert!(write_atomic(&path, None, false, |_| Ok(())).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_failure_leaves_nothing() {
+        use std::io::Write;
+
+        let dir = test_dir("atomic-err");
+        let path = dir.join("out.huf");
+
+        let result = write_atomic(&path, None, false, |file| {
+            file.write_all(b"half a file")?;
+            Err::<(), _>(io::Error::other("disk on fire"))
+        });
+
+        assert!(result.is_err());
+        assert!(!path.exists());
+        assert!(temp_files(&dir).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cancelled_compress_leaves_nothing() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        use crate::{container, container::Metadata, HuffmanBuilder, RuffmanError};
+
+        // Sets the flag on the first write, as a signal handler would
+        // between blocks.
+        struct Cancelling<'a, W> {
+            inner: W,
+            cancel: &'a AtomicBool,
+        }
+
+        impl<W: Write> Write for Cancelling<'_, W> {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.cancel.store(true, Ordering::Relaxed);
+                self.inner.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.inner.flush()
+            }
+        }
+
+        let dir = test_dir("cancel");
+        let path = dir.join("out.huf");
+        let input = "the quick brown fox jumps over the lazy dog ".repeat(20);
+        let cancel = AtomicBool::new(false);
+
+        let err = write_atomic(&path, None, false, |file| {
+            let mut out = Cancelling {
+                inner: file,
+                cancel: &cancel,
+            };
+            container::write_blocks_cancellable(
+                &input,
+                64,
+                &HuffmanBuilder::new(),
+                &Metadata::default(),
+                1,
+                &cancel,
+                &mut out,
+            )
+        })
+        .unwrap_err();
+
+        let err = err.get_ref().and_then(|e| e.downcast_ref::<RuffmanError>());
+        assert!(matches!(err, Some(RuffmanError::Cancelled)));
+        assert!(!path.exists());
+        assert!(temp_files(&dir).is_empty());
+
+        let mut bytes = Vec::new();
+        container::compress(input.clone(), &mut bytes).unwrap();
+        assert!(matches!(
+            container::decompress_cancellable(&bytes, &cancel),
+            Err(RuffmanError::Cancelled)
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn buffer_size_leaves_output_unchanged() {
+        use crate::{container, container::Metadata, HuffmanBuilder};
+
+        let dir = test_dir("buffer");
+        let path = dir.join("input.txt");
+        let input = "the quick brown fox jumps over the lazy dog ".repeat(50);
+        fs::write(&path, &input).unwrap();
+        let builder = HuffmanBuilder::new();
+        let write = |mut out: &mut dyn Write| {
+            container::write_blocks(&input, 256, &builder, &Metadata::default(), 1, &mut out)
+        };
+
+        let mut unbuffered = Vec::new();
+        write(&mut unbuffered).unwrap();
+        for capacity in [1, 7, DEFAULT_BUFFER_SIZE, 1 << 20] {
+            let mut out = Vec::new();
+            let written = buffered(&mut out, capacity, write).unwrap();
+
+            assert_eq!(written, out.len() as u64);
+            assert_eq!(out, unbuffered);
+
+            // The streaming path buffers its input the same way.
+            let mut reader = Stream::File(path.clone()).reader(capacity).unwrap();
+            let mut streamed = Vec::new();
+            buffered(&mut streamed, capacity, |mut out| {
+                container::write_blocks_from_reader(&mut reader, 256, &builder, &mut out)
+            })
+            .unwrap();
+            assert_eq!(streamed, unbuffered);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn split_output_reassembles() {
+        use crate::{container, Huffman};
+
+        let dir = test_dir("split");
+        let base = dir.join("out.huf");
+        let input = "the quick brown fox jumps over the lazy dog ".repeat(20);
+        let mut huffman = Huffman::from_input(input.clone());
+        huffman.compress();
+        let bits = huffman.get_compressed();
+        let metadata = container::Metadata::default();
+
+        write_split(&base, 64, |mut out| {
+            container::write_member(&huffman, &bits, &metadata, &mut out)
+        })
+        .unwrap();
+
+        let parts = fs::read_dir(&dir).unwrap().count();
+        assert!(parts > 2);
+        assert!((1..=parts as u32).all(|n| fs::metadata(part_path(&base, n)).unwrap().len() <= 64));
+        assert!(temp_files(&dir).is_empty());
+
+        let compressed = read_split(&base).unwrap();
+        assert_eq!(container::decompress(&compressed).unwrap(), input);
+
+        fs::remove_file(part_path(&base, parts as u32)).unwrap();
+        assert!(read_split(&base).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stdin_resolves_to_stdout_or_file() {
+        assert_eq!(
+            resolve_streams("-", None).unwrap(),
+            (Stream::Std, Stream::Std)
+        );
+        assert_eq!(
+            resolve_streams("-", Some("out.huf")).unwrap(),
+            (Stream::Std, Stream::File(PathBuf::from("out.huf")))
+        );
+        assert_eq!(
+            resolve_streams("in.txt", Some("-")).unwrap(),
+            (Stream::File(PathBuf::from("in.txt")), Stream::Std)
+        );
+        assert_eq!(
+            resolve_streams("in.txt", None).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn large_file_takes_streaming_path() {
+        use crate::{container, HuffmanBuilder};
+
+        let dir = test_dir("threshold");
+        let path = dir.join("input.txt");
+        let input = "a moderately sized input\n".repeat(400);
+        fs::write(&path, &input).unwrap();
+
+        let stream = Stream::File(path);
+        assert!(!stream.should_stream(DEFAULT_STREAM_THRESHOLD).unwrap());
+        assert!(stream.should_stream(1024).unwrap());
+
+        let mut out = Vec::new();
+        container::write_blocks_from_reader(
+            &mut stream.reader(DEFAULT_BUFFER_SIZE).unwrap(),
+            4096,
+            &HuffmanBuilder::new(),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(container::decompress(&out).unwrap(), input);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn piped_input_compresses_to_stdout_and_file() {
+        use crate::container;
+
+        let piped = "input read from a pipe".as_bytes();
+        let input = String::from_utf8(piped.to_vec()).unwrap();
+
+        // Stdin to stdout: any writer stands in for stdout.
+        let mut stdout = Vec::new();
+        container::compress(input.clone(), &mut stdout).unwrap();
+        assert_eq!(container::decompress(&stdout).unwrap(), input);
+
+        // Stdin to a file.
+        let dir = test_dir("stdin");
+        let (_, output) = resolve_streams("-", dir.join("out.huf").to_str()).unwrap();
+        let Stream::File(path) = output else {
+            panic!("expected a file output");
+        };
+        write_atomic(&path, None, false, |file| {
+            container::compress(input.clone(), file)
+        })
+        .unwrap();
+        assert_eq!(
+            container::decompress(&fs::read(&path).unwrap()).unwrap(),
+            input
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cleans_only_stale_temps() {
+        let dir = test_dir("stale");
+        let stale = dir.join(format!("{}1-old.huf", TEMP_PREFIX));
+        let fresh = dir.join(format!("{}2-new.huf", TEMP_PREFIX));
+        let other = dir.join("keep.huf");
+        for path in [&stale, &fresh, &other] {
+            fs::write(path, b"x").unwrap();
+        }
+        File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(SystemTime::now() - STALE_AFTER * 2)
+            .unwrap();
+
+        assert_eq!(clean_stale_temps(&dir).unwrap(), 1);
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+        assert!(other.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_survives_round_trip() {
+        use std::{fs, io::Write, os::unix::fs::PermissionsExt};
+
+        use crate::{container, container::Metadata, Huffman};
+
+        let dir = std::env::temp_dir().join(format!("ruffman-mode-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input_path = dir.join("script.sh");
+        let output_path = dir.join("restored.sh");
+        let _ = fs::remove_file(&output_path);
+
+        fs::write(&input_path, "#!/bin/sh\necho hi\n").unwrap();
+        fs::set_permissions(&input_path, fs::Permissions::from_mode(0o751)).unwrap();
+
+        let input = fs::read_to_string(&input_path).unwrap();
+        let mut huffman = Huffman::from_input(input.clone());
+        huffman.compress();
+        let mut metadata = Metadata::default();
+        metadata.mode = file_mode(&input_path).unwrap();
+        let mut compressed = Vec::new();
+        container::write_member(
+            &huffman,
+            &huffman.get_compressed(),
+            &metadata,
+            &mut compressed,
+        )
+        .unwrap();
+
+        let (text, metadata) = container::decompress_with_metadata(&compressed).unwrap();
+        let mut output = create_output(&output_path, metadata.mode, true).unwrap();
+        output.write_all(&text).unwrap();
+
+        let mode = fs::metadata(&output_path).unwrap().permissions().mode() & 0o7777;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(text, input.as_bytes());
+        assert_eq!(mode, 0o751);
+    }
+}
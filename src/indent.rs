@@ -0,0 +1,124 @@
+//! Indentation pre-pass applied before Huffman coding.
+//!
+//! The run of spaces at the start of a line becomes a single char in the
+//! private-use block at [`SPACES`], whose offset from it is the run's
+//! length, and a run of tabs likewise from [`TABS`], so each level of
+//! indentation costs one code rather than one per space. Indentation mixing
+//! the two becomes a token per run. A literal char from either block, or
+//! [`ESCAPE`] itself, is kept by putting `ESCAPE` in front of it.
+
+use crate::RuffmanError;
+
+/// Keeps the next char as it is. A private-use code point next to the
+/// markers of the other pre-passes, so they can be chained.
+pub(crate) const ESCAPE: char = '\u{E002}';
+
+/// Code point standing for no spaces; a run of `n` is `SPACES + n`.
+const SPACES: u32 = 0xF_0000;
+
+/// Code point standing for no tabs; a run of `n` is `TABS + n`.
+const TABS: u32 = 0xF_8000;
+
+/// Longest run a single token can hold; longer runs chain tokens.
+const MAX_RUN: usize = 0x7FFF;
+
+/// Whether `symb` falls in the block tokens are drawn from.
+fn is_token(symb: char) -> bool {
+    (SPACES..=TABS + MAX_RUN as u32).contains(&(symb as u32))
+}
+
+pub(crate) fn encode(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+
+    for line in input.split_inclusive('\n') {
+        let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        let mut rest = &line[..indent];
+        while let Some(first) = rest.chars().next() {
+            let run = rest.len() - rest.trim_start_matches(first).len();
+            let base = if first == ' ' { SPACES } else { TABS };
+            let mut left = run;
+            while left > 0 {
+                let count = left.min(MAX_RUN);
+                output.push(char::from_u32(base + count as u32).unwrap());
+                left -= count;
+            }
+            rest = &rest[run..];
+        }
+
+        for symb in line[indent..].chars() {
+            if symb == ESCAPE || is_token(symb) {
+                output.push(ESCAPE);
+            }
+            output.push(symb);
+        }
+    }
+
+    output
+}
+
+/// Expands the runs in text produced by [`encode`].
+pub(crate) fn decode(input: &str) -> Result<String, RuffmanError> {
+    decode_limited(input, usize::MAX)
+}
+
+/// Like [`decode`], failing with [`RuffmanError::OutputTooLarge`] as soon as
+/// the output grows past `limit` bytes. A single token stands for up to
+/// [`MAX_RUN`] spaces or tabs, so the limit is checked per token.
+pub(crate) fn decode_limited(input: &str, limit: usize) -> Result<String, RuffmanError> {
+    let mut output = String::with_capacity(input.len().min(limit));
+    let mut chars = input.chars();
+
+    while let Some(symb) = chars.next() {
+        if output.len() > limit {
+            return Err(RuffmanError::OutputTooLarge);
+        }
+
+        if symb == ESCAPE {
+            output.push(chars.next().ok_or(RuffmanError::CorruptIndent)?);
+        } else if is_token(symb) {
+            let (indent, base) = match symb as u32 >= TABS {
+                true => ('\t', TABS),
+                false => (' ', SPACES),
+            };
+            output.extend(std::iter::repeat_n(indent, (symb as u32 - base) as usize));
+        } else {
+            output.push(symb);
+        }
+    }
+
+    if output.len() > limit {
+        return Err(RuffmanError::OutputTooLarge);
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_indentation_and_escapes() {
+        let inputs = [
+            String::new(),
+            "no indentation\n".to_owned(),
+            "    four\n\t\ttwo tabs\n\t  mixed\n  \n   trailing".to_owned(),
+            format!("{}{}", " ".repeat(MAX_RUN * 2 + 3), "deep"),
+            format!("x{}y \u{F0004} {}\n\u{F8001}", ESCAPE, ESCAPE),
+            "inner    spaces stay\r\n    crlf\r\n".to_owned(),
+        ];
+
+        for input in inputs {
+            assert_eq!(decode(&encode(&input)).unwrap(), input);
+        }
+
+        assert_eq!(encode("        x\n\t\ty").chars().count(), 5);
+    }
+
+    #[test]
+    fn rejects_trailing_escape() {
+        let input = format!("abc{}", ESCAPE);
+
+        assert!(matches!(decode(&input), Err(RuffmanError::CorruptIndent)));
+    }
+}
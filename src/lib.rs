@@ -0,0 +1,2340 @@
+pub mod archive;
+mod ascii;
+pub mod bits;
+mod canonical;
+pub mod container;
+mod crc;
+mod decode;
+#[cfg(feature = "encoding")]
+pub mod encoding;
+pub mod eol;
+mod error;
+pub mod files;
+mod indent;
+mod literals;
+mod lz;
+#[cfg(feature = "normalize")]
+pub mod normalize;
+pub mod numeric;
+pub mod preset;
+mod remap;
+mod render;
+mod rle;
+mod stats;
+#[cfg(test)]
+mod testing;
+pub mod trained;
+mod tree;
+mod varint;
+
+pub use container::{FORMAT_VERSION, MAGIC};
+pub use error::RuffmanError;
+pub use stats::{compare, Comparison, CompressionStats, DecompressionStats};
+pub use tree::Tree;
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
+    io::{self, Read},
+    sync::atomic::{self, AtomicBool},
+    time::{Duration, Instant},
+};
+
+use bitvec::prelude::{BitVec, Msb0};
+
+/// A symbol and how often it occurs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeafNode {
+    pub weight: u32,
+    pub symb: char,
+}
+
+/// A branch, reached from its parent by one bit: `left` is reached by a 0
+/// and `right` by a 1. Both are set in a proper tree (see
+/// [`Node::validate`]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InternalNode {
+    pub left: Option<Box<Node>>,
+    pub right: Option<Box<Node>>,
+    /// The sum of the children's weights.
+    pub weight: u32,
+}
+
+/// A node of a code tree. The fields are public, so a tree can be built or
+/// taken apart by pattern matching as well as through the accessors below.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    Leaf(LeafNode),
+    Internal(InternalNode),
+}
+
+impl Node {
+    /// The tree of an empty input: a root with no children.
+    pub(crate) fn empty() -> Self {
+        Node::Internal(InternalNode {
+            left: None,
+            right: None,
+            weight: 0,
+        })
+    }
+
+    /// The number of occurrences this node covers: a leaf's symbol count, or
+    /// the sum of an internal node's leaves. The root's weight is the length
+    /// of the input in symbols.
+    pub fn weight(&self) -> u32 {
+        match self {
+            Node::Leaf(leaf) => leaf.weight,
+            Node::Internal(internal) => internal.weight,
+        }
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        matches!(self, Node::Leaf(_))
+    }
+
+    /// The symbol on a leaf, or `None` for an internal node.
+    pub fn symbol(&self) -> Option<char> {
+        match self {
+            Node::Leaf(leaf) => Some(leaf.symb),
+            Node::Internal(_) => None,
+        }
+    }
+
+    /// An internal node's children, reached by a 0 and a 1 bit. `None` for a
+    /// leaf, and for a node missing a child, such as the root of an empty
+    /// input's tree.
+    pub fn children(&self) -> Option<(&Node, &Node)> {
+        match self {
+            Node::Internal(InternalNode {
+                left: Some(left),
+                right: Some(right),
+                ..
+            }) => Some((left, right)),
+            _ => None,
+        }
+    }
+
+    /// The number of edges from the root down to the deepest leaf: 0 for a
+    /// lone leaf or an empty tree.
+    pub fn depth(&self) -> usize {
+        self.preorder()
+            .map(|(_, depth, _)| depth)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// How many symbols have each code length, ordered by length. A lone
+    /// symbol counts as length 1, the length it is coded with.
+    pub fn length_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for (_, _, len) in canonical::leaf_lengths(self) {
+            *histogram.entry(len.max(1)).or_insert(0) += 1;
+        }
+
+        histogram
+    }
+
+    /// Walks the tree in pre-order, yielding each node with its depth and
+    /// the bit on the edge leading to it (`None` for the root).
+    pub(crate) fn preorder(&self) -> Preorder<'_> {
+        Preorder {
+            stack: vec![(self, 0, None)],
+        }
+    }
+
+    fn leaves(&self) -> impl Iterator<Item = &LeafNode> {
+        self.preorder().filter_map(|(node, _, _)| match node {
+            Node::Leaf(leaf) => Some(leaf),
+            Node::Internal(_) => None,
+        })
+    }
+
+    fn leaf_count(&self) -> usize {
+        self.leaves().count()
+    }
+    /// Checks that this is a proper Huffman tree: every internal node has
+    /// both children and no symbol appears on more than one leaf, so every
+    /// leaf is reachable by exactly one code. The childless root of an empty
+    /// input is also accepted.
+    pub fn validate(&self) -> Result<(), RuffmanError> {
+        if *self == Node::empty() {
+            return Ok(());
+        }
+
+        let mut seen = HashSet::new();
+        for (node, _, _) in self.preorder() {
+            match node {
+                Node::Leaf(leaf) => {
+                    if !seen.insert(leaf.symb) {
+                        return Err(RuffmanError::DuplicateSymbol(leaf.symb));
+                    }
+                }
+                Node::Internal(internal) => {
+                    if internal.left.is_none() || internal.right.is_none() {
+                        return Err(RuffmanError::MissingChild);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Iterator returned by [`Node::preorder`]. It keeps its own stack, so even
+/// a degenerate, linear tree can't overflow the call stack.
+pub(crate) struct Preorder<'a> {
+    stack: Vec<(&'a Node, usize, Option<u8>)>,
+}
+
+impl<'a> Iterator for Preorder<'a> {
+    type Item = (&'a Node, usize, Option<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (node, depth, bit) = self.stack.pop()?;
+        if let Node::Internal(internal) = node {
+            if let Some(right) = &internal.right {
+                self.stack.push((right, depth + 1, Some(1)));
+            }
+            if let Some(left) = &internal.left {
+                self.stack.push((left, depth + 1, Some(0)));
+            }
+        }
+
+        Some((node, depth, bit))
+    }
+}
+
+/// Drops children iteratively; the derived drop glue would recurse once per
+/// level and overflow the stack on a deep enough tree.
+impl Drop for InternalNode {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        stack.extend(self.left.take());
+        stack.extend(self.right.take());
+
+        while let Some(mut node) = stack.pop() {
+            if let Node::Internal(internal) = &mut *node {
+                stack.extend(internal.left.take());
+                stack.extend(internal.right.take());
+            }
+        }
+    }
+}
+
+/// Heap entry ordering nodes by weight alone, so `Reverse<WeightedNode>`
+/// pops the lightest node first.
+///
+/// Ties go to leaves before internal nodes, then to the smaller symbol (for
+/// internal nodes, the symbol of their leftmost leaf). Nodes in one heap never
+/// share symbols, so this is a total order on the heap's contents.
+struct WeightedNode(Node);
+
+impl WeightedNode {
+    fn tie_key(&self) -> (bool, char) {
+        let mut node = &self.0;
+        while let Node::Internal(internal) = node {
+            match internal.left.as_deref().or(internal.right.as_deref()) {
+                Some(child) => node = child,
+                None => return (true, '\0'),
+            }
+        }
+
+        let Node::Leaf(leaf) = node else {
+            unreachable!()
+        };
+        (matches!(self.0, Node::Internal(_)), leaf.symb)
+    }
+}
+
+impl Ord for WeightedNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .weight()
+            .cmp(&other.0.weight())
+            .then_with(|| self.tie_key().cmp(&other.tie_key()))
+    }
+}
+
+impl PartialOrd for WeightedNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for WeightedNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for WeightedNode {}
+
+#[derive(Clone, Debug)]
+pub struct NodeBytes {
+    pub input: String,
+    pub node: Node,
+    pub bytes: Vec<u8>,
+}
+
+impl From<Node> for NodeBytes {
+    fn from(value: Node) -> Self {
+        Self {
+            input: String::new(),
+            node: value,
+            bytes: vec![],
+        }
+    }
+}
+
+impl TryFrom<Vec<u8>> for NodeBytes {
+    type Error = RuffmanError;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, RuffmanError> {
+        let mut bytes = Self {
+            input: String::new(),
+            node: Node::Internal(InternalNode {
+                left: None,
+                right: None,
+                weight: 0,
+            }),
+            bytes: value,
+        };
+
+        bytes.into_node()?;
+
+        Ok(bytes)
+    }
+}
+
+/// Serializes `tree` as [`NodeBytes::as_bytes`] does, without taking
+/// ownership of it.
+///
+/// After a u32 leaf count, internal edges are a 0 marker; leaves are a 1
+/// marker followed by the symbol and weight as big-endian u32s, so
+/// into_node can rebuild the same tree.
+pub(crate) fn structural_bytes(tree: &Node) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend((tree.leaf_count() as u32).to_be_bytes());
+
+    for (node, _, bit) in tree.preorder() {
+        if bit.is_some() {
+            bytes.push(0);
+        }
+        if let Node::Leaf(leaf) = node {
+            bytes.push(1);
+            bytes.extend((leaf.symb as u32).to_be_bytes());
+            bytes.extend(leaf.weight.to_be_bytes());
+        }
+    }
+
+    bytes
+}
+
+impl NodeBytes {
+    pub fn as_bytes(&mut self) {
+        self.bytes.extend(structural_bytes(&self.node));
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    pub fn into_node(&mut self) -> Result<(), RuffmanError> {
+        let mut nodes = BinaryHeap::new();
+        let mut bytes_iter = self.bytes.clone().into_iter();
+        let next_u32 = |iter: &mut std::vec::IntoIter<u8>| {
+            let mut buf = [0; 4];
+            for byte in buf.iter_mut() {
+                *byte = iter.next().ok_or(RuffmanError::Truncated)?;
+            }
+            Ok::<_, RuffmanError>(u32::from_be_bytes(buf))
+        };
+        let expected = next_u32(&mut bytes_iter)? as usize;
+        while let Some(val) = bytes_iter.next() {
+            if val == 1 {
+                let value = next_u32(&mut bytes_iter)?;
+                let symb = char::from_u32(value).ok_or(RuffmanError::InvalidSymbol(value))?;
+                let weight = next_u32(&mut bytes_iter)?;
+                nodes.push(Reverse(WeightedNode(Node::Leaf(LeafNode { weight, symb }))));
+            }
+        }
+        if nodes.len() != expected {
+            return Err(RuffmanError::TreeSymbolMismatch {
+                expected,
+                found: nodes.len(),
+            });
+        }
+        while nodes.len() > 1 {
+            let Reverse(WeightedNode(node0)) = nodes.pop().unwrap();
+            let n0w = node0.weight();
+            let Reverse(WeightedNode(node1)) = nodes.pop().unwrap();
+            let n1w = node1.weight();
+            // The weights come from a file, so needn't add up to an input.
+            let new_node = InternalNode {
+                left: Some(Box::new(node0)),
+                right: Some(Box::new(node1)),
+
+                weight: n0w.checked_add(n1w).ok_or(RuffmanError::CorruptTree)?,
+            };
+
+            nodes.push(Reverse(WeightedNode(Node::Internal(new_node))));
+        }
+
+        // No leaves means the input was empty.
+        self.node = match nodes.pop() {
+            Some(Reverse(WeightedNode(root))) => root,
+            None => Node::empty(),
+        };
+
+        Ok(())
+    }
+
+    pub fn gen_input(&mut self) {
+        for leaf in self.node.leaves() {
+            for _i in 0..leaf.weight {
+                self.input.push(leaf.symb);
+            }
+        }
+    }
+}
+
+/// One merge made while building a tree: the two lightest nodes, popped in
+/// this order, joined under a new internal node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergeStep {
+    pub left_weight: u32,
+    pub right_weight: u32,
+    pub merged: Node,
+}
+
+/// Fails with [`RuffmanError::CorruptTree`] if the weights of `leaves` read
+/// from a file add up past `u32::MAX`, which no input can, since the root's
+/// weight is the input's length.
+pub(crate) fn check_weights(leaves: &[(char, u32)]) -> Result<(), RuffmanError> {
+    let total = leaves.iter().map(|(_, weight)| *weight as u64).sum::<u64>();
+    match total > u32::MAX as u64 {
+        true => Err(RuffmanError::CorruptTree),
+        false => Ok(()),
+    }
+}
+
+pub fn calc_huff(n: Vec<(char, u32)>) -> Node {
+    calc_huff_traced(n).0
+}
+
+/// Like [`calc_huff`], also returning every merge in the order it was made,
+/// e.g. to animate the tree being built. Merged weights saturate at
+/// `u32::MAX`, so a table whose counts add up past it, such as one
+/// [`merge_freqs`] saturated, still builds a tree, if not an optimal one.
+pub fn calc_huff_traced(n: Vec<(char, u32)>) -> (Node, Vec<MergeStep>) {
+    let mut set = BinaryHeap::new();
+    let mut steps = Vec::new();
+
+    for i in n {
+        let new_node = LeafNode {
+            symb: i.0,
+
+            weight: i.1,
+        };
+
+        set.push(Reverse(WeightedNode(Node::Leaf(new_node))));
+    }
+
+    while set.len() > 1 {
+        let Reverse(WeightedNode(node0)) = set.pop().unwrap();
+        let n0w = node0.weight();
+        let Reverse(WeightedNode(node1)) = set.pop().unwrap();
+        let n1w = node1.weight();
+
+        let new_node = Node::Internal(InternalNode {
+            left: Some(Box::new(node0)),
+            right: Some(Box::new(node1)),
+
+            weight: n0w.saturating_add(n1w),
+        });
+
+        steps.push(MergeStep {
+            left_weight: n0w,
+            right_weight: n1w,
+            merged: new_node.clone(),
+        });
+        set.push(Reverse(WeightedNode(new_node)));
+    }
+
+    let root = match set.pop() {
+        Some(Reverse(WeightedNode(root))) => root,
+        None => Node::empty(),
+    };
+
+    (root, steps)
+}
+
+pub fn calc_freq(input: String) -> Vec<(char, u32)> {
+    let mut counter = FreqCounter::new();
+
+    for char in input.chars() {
+        counter.push(char);
+    }
+
+    counter.finish()
+}
+
+/// The Kraft sum of a set of code lengths, the fraction of the code space
+/// they use: 1.0 for a complete prefix code, above it for lengths no prefix
+/// code can have, and below it for one with codes left unused. Lengths of 0
+/// stand for absent symbols and are skipped. Exact unless a length exceeds
+/// 52, where the terms fall below an `f64`'s precision.
+pub fn kraft_sum(lengths: &[u8]) -> f64 {
+    lengths
+        .iter()
+        .filter(|len| **len > 0)
+        .map(|len| 2f64.powi(-i32::from(*len)))
+        .sum()
+}
+
+/// Shannon entropy of a frequency table, in bits per symbol. No prefix code
+/// can average fewer bits per symbol than this.
+pub fn entropy(freqs: &[(char, u32)]) -> f64 {
+    let total: f64 = freqs.iter().map(|(_, count)| *count as f64).sum();
+
+    freqs
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(_, count)| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Number of payload bits `freqs` would encode to: each symbol's count
+/// times its code length in the tree [`calc_huff`] builds. Cheaper than
+/// encoding when only the size matters, e.g. to decide whether a file is
+/// worth compressing.
+pub fn estimate_compressed_bits(freqs: &[(char, u32)]) -> u64 {
+    canonical::leaf_lengths(&calc_huff(freqs.to_vec()))
+        .into_iter()
+        // A lone symbol still takes one bit per occurrence.
+        .map(|(_, weight, len)| weight as u64 * len.max(1) as u64)
+        .sum()
+}
+
+/// Encodes `data` with the codes [`calc_huff`] assigns for `freqs`, packed
+/// first bit highest as a container payload holds them. Fails with
+/// [`RuffmanError::UnknownSymbol`] if `data` has a symbol `freqs` lacks.
+pub fn encode_frequencies(
+    freqs: &[(char, u32)],
+    data: &str,
+) -> Result<BitVec<u8, Msb0>, RuffmanError> {
+    let mut huffman = Huffman::from_frequencies(freqs.to_vec());
+    huffman.compress();
+    let bits = huffman.encode(data)?;
+
+    Ok(bits.into_iter().map(|bit| bit == 1).collect())
+}
+
+/// Counts symbol frequencies as symbols arrive, e.g. from a live stream,
+/// without holding the input. [`FreqCounter::finish`] gives the same table
+/// [`calc_freq`] would for the same symbols.
+#[derive(Clone, Debug, Default)]
+pub struct FreqCounter {
+    freqs: Vec<(char, u32)>,
+    index: HashMap<char, usize>,
+}
+
+impl FreqCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `c` once more, saturating at `u32::MAX`.
+    pub fn push(&mut self, c: char) {
+        match self.index.get(&c) {
+            Some(pos) => self.freqs[*pos].1 = self.freqs[*pos].1.saturating_add(1),
+            None => {
+                self.index.insert(c, self.freqs.len());
+                self.freqs.push((c, 1));
+            }
+        }
+    }
+
+    /// The counts, in the order each symbol was first pushed.
+    pub fn finish(self) -> Vec<(char, u32)> {
+        self.freqs
+    }
+}
+
+/// Sums two frequency tables per symbol. The result is sorted by symbol so
+/// that merging partial tables is deterministic regardless of chunk order.
+/// A sum past `u32::MAX` saturates there, and [`calc_huff`] builds a tree
+/// from such a table all the same.
+pub fn merge_freqs(a: Vec<(char, u32)>, b: Vec<(char, u32)>) -> Vec<(char, u32)> {
+    let mut merged = BTreeMap::new();
+
+    for (char, count) in a.into_iter().chain(b) {
+        let total = merged.entry(char).or_insert(0u32);
+        *total = total.saturating_add(count);
+    }
+
+    merged.into_iter().collect()
+}
+
+/// The phases of compression that `--benchmark` reports on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Counting,
+    Building,
+    Encoding,
+}
+
+/// Wall-clock time spent in each compression phase.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PhaseTimings {
+    pub counting: Duration,
+    pub building: Duration,
+    pub encoding: Duration,
+}
+
+impl PhaseTimings {
+    /// Runs `f` and adds its elapsed time to `phase`.
+    pub fn record<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        *self.get_mut(phase) += start.elapsed();
+
+        result
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Counting => self.counting,
+            Phase::Building => self.building,
+            Phase::Encoding => self.encoding,
+        }
+    }
+
+    fn get_mut(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::Counting => &mut self.counting,
+            Phase::Building => &mut self.building,
+            Phase::Encoding => &mut self.encoding,
+        }
+    }
+
+    /// Throughput of `phase` over `bytes` of input, in MB/s.
+    pub fn throughput(&self, phase: Phase, bytes: usize) -> f64 {
+        let secs = self.get(phase).as_secs_f64();
+        if secs == 0.0 {
+            return f64::INFINITY;
+        }
+
+        bytes as f64 / 1_000_000.0 / secs
+    }
+}
+
+#[derive(Clone)]
+pub struct Huffman {
+    input: String,
+    char_codes: HashMap<char, Vec<u8>>,
+    tree: Node,
+    canonical: bool,
+    /// `input` has been through the run-length pre-pass.
+    rle: bool,
+    /// `input` has been through the LZ pre-pass, ahead of any run-length one.
+    lz: bool,
+    /// `input` has been through the indentation pre-pass, ahead of any other.
+    indent: bool,
+    /// `input` holds bytes, each as the char of the same value.
+    bytes: bool,
+    /// The tree symbol standing for symbols coded as literals, if any.
+    escape: Option<char>,
+}
+
+/// Wraps a tree as is, with no input of its own, so tests can build codecs
+/// over specific or deliberately malformed trees. Nothing is checked; see
+/// [`Node::validate`]. Encode with [`Huffman::encode`] once
+/// [`Huffman::compress`] has assigned the codes.
+impl From<Node> for Huffman {
+    fn from(value: Node) -> Self {
+        Self {
+            tree: value,
+            input: String::new(),
+            char_codes: HashMap::new(),
+            canonical: false,
+            rle: false,
+            lz: false,
+            indent: false,
+            bytes: false,
+            escape: None,
+        }
+    }
+}
+
+impl Huffman {
+    /// Builds the tree for a modeled rather than observed distribution. The
+    /// codec has no input of its own; encode text with [`Huffman::encode`]
+    /// once [`Huffman::compress`] has assigned the codes.
+    pub fn from_frequencies(freqs: Vec<(char, u32)>) -> Self {
+        Self::from(calc_huff(freqs))
+    }
+
+    pub fn from_input(input: String) -> Self {
+        Self::from_input_timed(input, &mut PhaseTimings::default())
+    }
+
+    /// Like [`Huffman::from_input`], recording the counting and
+    /// tree-building phases into `timings`.
+    pub fn from_input_timed(input: String, timings: &mut PhaseTimings) -> Self {
+        let freqs = timings.record(Phase::Counting, || calc_freq(input.clone()));
+        let tree = timings.record(Phase::Building, || calc_huff(freqs));
+
+        Self {
+            tree,
+            input,
+            char_codes: HashMap::new(),
+            canonical: false,
+            rle: false,
+            lz: false,
+            indent: false,
+            bytes: false,
+            escape: None,
+        }
+    }
+
+    /// Compresses `input` into a file as [`container::compress`] writes it,
+    /// encoded as standard base64 for embedding in JSON or URLs.
+    #[cfg(feature = "base64")]
+    pub fn compress_to_base64(input: String) -> String {
+        use base64::Engine;
+
+        let mut out = Vec::new();
+        // Writing to a `Vec` can't fail.
+        container::compress(input, &mut out).unwrap();
+        base64::engine::general_purpose::STANDARD.encode(out)
+    }
+
+    /// Decodes a file produced by [`Huffman::compress_to_base64`].
+    #[cfg(feature = "base64")]
+    pub fn decompress_from_base64(encoded: &str) -> Result<String, RuffmanError> {
+        use base64::Engine;
+
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|_| RuffmanError::InvalidBase64)?;
+        container::decompress(&bytes)
+    }
+
+    /// Builds the tree over raw bytes rather than text, for binary files.
+    /// Each byte is coded as the symbol U+0000 to U+00FF of the same value and
+    /// decodes back to exactly that byte.
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut huffman = Self::from_input(data.iter().map(|byte| char::from(*byte)).collect());
+        huffman.bytes = true;
+        huffman
+    }
+
+    /// Builds a byte-mode codec from a count per byte value, as a streaming
+    /// byte counter produces, leaving bytes with no count out of the tree.
+    /// Like [`Huffman::from_frequencies`] the codec has no input of its own.
+    /// Counts too large for the tree's u32 weights are scaled down together,
+    /// each nonzero count staying at least 1.
+    pub fn from_histogram(counts: [u64; 256]) -> Self {
+        let total = counts.iter().map(|count| *count as u128).sum::<u128>();
+        // Room for every byte's count to be rounded up to 1.
+        let scale = total.div_ceil(u32::MAX as u128 - 256).max(1);
+
+        let freqs = (0..=u8::MAX)
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(byte, count)| {
+                let weight = (count as u128 / scale).max(1);
+                (char::from(byte), weight as u32)
+            })
+            .collect();
+
+        let mut huffman = Self::from(calc_huff(freqs));
+        huffman.bytes = true;
+        huffman
+    }
+
+    /// Rebuilds a canonical codec from `(symbol, code length)` pairs, such as a
+    /// table exported by another tool, assigning codes as DEFLATE does:
+    /// shorter codes first, ties broken by symbol. The inverse of
+    /// [`Huffman::code_lengths`] on a canonicalized codec. A length of 0
+    /// leaves the symbol out, and a lone symbol may have length 1. The codes
+    /// are assigned, ready to [`Huffman::encode`] with, but the codec has no
+    /// input of its own.
+    ///
+    /// Fails with [`RuffmanError::InvalidCodeLengths`] if the lengths
+    /// over-subscribe the code space or leave part of it unused, so unless
+    /// their [`kraft_sum`] is 1, and with
+    /// [`RuffmanError::DuplicateSymbol`] if a symbol is listed twice.
+    pub fn from_code_lengths(lengths: &[(char, u8)]) -> Result<Self, RuffmanError> {
+        let lengths = lengths
+            .iter()
+            .filter(|(_, len)| *len > 0)
+            .map(|(symb, len)| (*symb, 0, *len))
+            .collect::<Vec<_>>();
+
+        let mut seen = HashSet::new();
+        if let Some((symb, _, _)) = lengths.iter().find(|(symb, _, _)| !seen.insert(*symb)) {
+            return Err(RuffmanError::DuplicateSymbol(*symb));
+        }
+
+        let tree = match lengths[..] {
+            [] => return Err(RuffmanError::InvalidCodeLengths),
+            // A lone leaf is coded with one bit, as `compress` codes it.
+            [(symb, weight, 1)] => Node::Leaf(LeafNode { weight, symb }),
+            _ => {
+                let lens = lengths.iter().map(|(_, _, len)| *len).collect::<Vec<_>>();
+                if kraft_sum(&lens) > 1.0 {
+                    return Err(RuffmanError::InvalidCodeLengths);
+                }
+
+                // The sum rounds away very long codes, so completeness is
+                // checked exactly, and a tree has nowhere to put unused codes.
+                let mut counts = [0usize; u8::MAX as usize + 1];
+                lengths
+                    .iter()
+                    .for_each(|(_, _, len)| counts[*len as usize] += 1);
+
+                // Codes still free at each length, as inflate counts them.
+                // More than there are symbols left can never all be used.
+                let mut free = 1usize;
+                let mut left = lengths.len();
+                for count in &counts[1..] {
+                    free = (free * 2)
+                        .checked_sub(*count)
+                        .ok_or(RuffmanError::InvalidCodeLengths)?;
+                    left -= count;
+                    if free > left {
+                        return Err(RuffmanError::InvalidCodeLengths);
+                    }
+                }
+                if free != 0 {
+                    return Err(RuffmanError::InvalidCodeLengths);
+                }
+
+                let lengths = lengths
+                    .into_iter()
+                    .map(|(symb, weight, len)| (symb, weight, len as usize))
+                    .collect();
+                canonical::tree_from_codes(&canonical::canonical_codes(lengths))
+                    .ok_or(RuffmanError::InvalidCodeLengths)?
+            }
+        };
+
+        let mut huffman = Self::from(tree);
+        huffman.canonical = true;
+        huffman.compress();
+        Ok(huffman)
+    }
+
+    /// Builds the tree from everything `r` yields, counting frequencies chunk
+    /// by chunk instead of reading the whole input into memory. The tree is
+    /// the same as [`Huffman::from_input`] would build for the same text, but
+    /// the input itself is not retained.
+    pub fn from_reader<R: Read>(r: &mut R) -> io::Result<Self> {
+        Self::from_reader_cancellable(r, &AtomicBool::new(false))
+    }
+
+    /// Like [`Huffman::from_reader`], checking `cancel` before each read and
+    /// failing with [`RuffmanError::Cancelled`] once it is set.
+    pub fn from_reader_cancellable<R: Read>(r: &mut R, cancel: &AtomicBool) -> io::Result<Self> {
+        let mut counter = FreqCounter::new();
+        let mut pending = Vec::new();
+        let mut buf = [0; 8192];
+
+        loop {
+            if cancel.load(atomic::Ordering::Relaxed) {
+                return Err(RuffmanError::Cancelled.into());
+            }
+
+            let read = r.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            pending.extend_from_slice(&buf[..read]);
+
+            // A multi-byte char may be split across reads; count what decodes
+            // and carry the incomplete tail over to the next chunk.
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(text) => text,
+                Err(e) if e.error_len().is_none() => {
+                    std::str::from_utf8(&pending[..e.valid_up_to()]).unwrap()
+                }
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+
+            valid.chars().for_each(|c| counter.push(c));
+
+            let consumed = valid.len();
+            pending.drain(..consumed);
+        }
+
+        if !pending.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            ));
+        }
+
+        Ok(Self::from(calc_huff(counter.finish())))
+    }
+
+    /// Encodes `input` with an existing `tree`, such as one loaded with
+    /// [`trained::read_tree`]. Fails with [`RuffmanError::UnknownSymbol`] if
+    /// `input` uses a symbol the tree has no code for.
+    pub fn with_tree(tree: Node, input: String) -> Result<Self, RuffmanError> {
+        let symbols = tree.leaves().map(|leaf| leaf.symb).collect::<HashSet<_>>();
+
+        if let Some(symb) = input.chars().find(|symb| !symbols.contains(symb)) {
+            return Err(RuffmanError::UnknownSymbol(symb));
+        }
+
+        Ok(Self {
+            tree,
+            input,
+            char_codes: HashMap::new(),
+            canonical: false,
+            rle: false,
+            lz: false,
+            indent: false,
+            bytes: false,
+            escape: None,
+        })
+    }
+
+    /// Reshapes the tree into canonical form, keeping every symbol's code
+    /// length but reassigning codes in (length, symbol) order. A canonical
+    /// tree can be stored as just its code lengths.
+    pub fn canonicalize(&mut self) {
+        let codes = canonical::canonical_codes(canonical::leaf_lengths(&self.tree));
+        self.tree = canonical::tree_from_codes(&codes).unwrap_or_else(Node::empty);
+        self.char_codes.clear();
+        self.compress();
+        self.canonical = true;
+    }
+
+    pub fn tree(&self) -> &Node {
+        &self.tree
+    }
+
+    /// Replaces the tree as is, keeping the input, as [`Huffman::from`] a
+    /// tree does for a new codec. The codes are cleared until
+    /// [`Huffman::compress`] assigns them from the new tree.
+    pub fn set_tree(&mut self, tree: Node) {
+        self.tree = tree;
+        self.char_codes.clear();
+        self.canonical = false;
+        self.escape = None;
+    }
+
+    /// How the tree will be stored when written to a container.
+    pub fn serialization(&self) -> Serialization {
+        match self.canonical {
+            true => Serialization::Canonical,
+            false => Serialization::Structural,
+        }
+    }
+
+    /// Number of symbols the tree was built over, i.e. its root's weight.
+    pub fn total_weight(&self) -> u32 {
+        self.tree.weight()
+    }
+
+    /// Number of distinct symbols in the tree.
+    pub fn num_symbols(&self) -> usize {
+        self.tree.leaf_count()
+    }
+
+    pub fn compress(&mut self) {
+        // A lone leaf would get an empty code; give it one bit so the
+        // payload still records how many symbols there are.
+        if let Node::Leaf(leaf) = &self.tree {
+            self.char_codes.insert(leaf.symb, vec![0]);
+            return;
+        }
+
+        // Pre-order visits a node right after its parent's subtree to the
+        // left, so trimming the path to the node's depth leaves its parent's
+        // code.
+        let mut code = Vec::new();
+        for (node, depth, bit) in self.tree.preorder() {
+            if let Some(bit) = bit {
+                code.truncate(depth - 1);
+                code.push(bit);
+            }
+            if let Node::Leaf(leaf) = node {
+                self.char_codes.insert(leaf.symb, code.clone());
+            }
+        }
+    }
+
+    /// The code for `symb`, one bit per element, or `None` if the tree has no
+    /// code for it. Empty until [`Huffman::compress`] has assigned the codes.
+    pub fn code_for(&self, symb: char) -> Option<&[u8]> {
+        self.char_codes.get(&symb).map(Vec::as_slice)
+    }
+
+    /// Every symbol's code as a string of `0`s and `1`s, ordered by symbol.
+    /// Empty until [`Huffman::compress`] has assigned the codes.
+    pub fn code_strings(&self) -> BTreeMap<char, String> {
+        self.char_codes
+            .iter()
+            .map(|(symb, code)| {
+                let code = code.iter().map(|bit| if *bit == 0 { '0' } else { '1' });
+                (*symb, code.collect())
+            })
+            .collect()
+    }
+
+    /// Every symbol's code length, read off its depth in the tree, without
+    /// assigning the codes themselves. A lone symbol still gets one bit, as in
+    /// [`Huffman::compress`]. With [`HuffmanBuilder::coded_symbols`] the
+    /// escape has a length too, but the symbols it stands for don't.
+    pub fn code_lengths(&self) -> HashMap<char, usize> {
+        self.tree
+            .preorder()
+            .filter_map(|(node, depth, _)| match node {
+                Node::Leaf(leaf) => Some((leaf.symb, depth.max(1))),
+                Node::Internal(_) => None,
+            })
+            .collect()
+    }
+
+    /// The longest code any symbol gets, which is the tree's
+    /// [`Node::depth`] except for a lone symbol, coded with 1 bit. `None` for
+    /// an empty tree.
+    pub fn max_code_length(&self) -> Option<usize> {
+        self.code_lengths().into_values().max()
+    }
+
+    /// The most and least frequent symbols in the tree with their weights, as
+    /// `(max, min)`, ties broken towards the lower symbol. A lone symbol is
+    /// both, and an empty tree has neither. The escape of
+    /// [`HuffmanBuilder::coded_symbols`] stands for several symbols, so it
+    /// isn't counted.
+    pub fn extremes(&self) -> Option<((char, u32), (char, u32))> {
+        let weights = self
+            .tree
+            .leaves()
+            .filter(|leaf| Some(leaf.symb) != self.escape)
+            .map(|leaf| (leaf.symb, leaf.weight))
+            .collect::<Vec<_>>();
+
+        let max = weights
+            .iter()
+            .copied()
+            .min_by_key(|(symb, weight)| (std::cmp::Reverse(*weight), *symb))?;
+        let min = weights
+            .into_iter()
+            .min_by_key(|(symb, weight)| (*weight, *symb))?;
+        Some((max, min))
+    }
+
+    /// How much of the payload each symbol of the input accounts for, as
+    /// `(symbol, count, code length, total bits)`, costliest first and ties
+    /// broken by symbol. A symbol escaped as a literal (see
+    /// [`HuffmanBuilder::coded_symbols`]) costs the escape's code plus the
+    /// literal. The totals add up to the length of
+    /// [`Huffman::get_compressed`].
+    pub fn symbol_costs(&self) -> Vec<(char, u32, usize, u64)> {
+        let lengths = self.code_lengths();
+        let escape_len = self
+            .escape
+            .and_then(|escape| lengths.get(&escape))
+            .map(|len| *len + literals::LITERAL_BITS);
+
+        let mut counter = FreqCounter::new();
+        self.input.chars().for_each(|c| counter.push(c));
+        let mut costs = counter
+            .finish()
+            .into_iter()
+            .map(|(symb, count)| {
+                let len = lengths.get(&symb).copied().or(escape_len).unwrap_or(0);
+                (symb, count, len, count as u64 * len as u64)
+            })
+            .collect::<Vec<_>>();
+        costs.sort_by(|a, b| b.3.cmp(&a.3).then(a.0.cmp(&b.0)));
+
+        costs
+    }
+
+    /// Every symbol whose code differs between this codec and `other`, or
+    /// which only one of them has a code for, with its code in each as a
+    /// string of `0`s and `1`s. Ordered by symbol. Both codecs need their
+    /// codes assigned by [`Huffman::compress`].
+    pub fn code_diff(&self, other: &Self) -> Vec<(char, Option<String>, Option<String>)> {
+        let mut ours = self.code_strings();
+        let mut theirs = other.code_strings();
+        let symbols = ours
+            .keys()
+            .chain(theirs.keys())
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        symbols
+            .into_iter()
+            .map(|symb| (symb, ours.remove(&symb), theirs.remove(&symb)))
+            .filter(|(_, ours, theirs)| ours != theirs)
+            .collect()
+    }
+
+    /// Encodes `input` with this codec's codes, which need not be the input
+    /// the tree was built from. Fails with [`RuffmanError::UnknownSymbol`] on
+    /// a symbol with no code, including any symbol before
+    /// [`Huffman::compress`] has assigned the codes.
+    pub fn encode(&self, input: &str) -> Result<Vec<u8>, RuffmanError> {
+        let mut result = Vec::new();
+
+        for char in input.chars() {
+            self.push_code(char, &mut result)?;
+        }
+
+        Ok(result)
+    }
+
+    /// The bytes of a file holding `input` coded over this codec's tree, as
+    /// the CLI writes it, so format tests can run on buffers alone. As with
+    /// [`Huffman::encode`], `input` need not be what the tree was built
+    /// from, and fails with [`RuffmanError::UnknownSymbol`] on a symbol with
+    /// no code. A byte-mode codec codes the bytes of `input`.
+    pub fn to_container(&self, input: &str) -> Result<Vec<u8>, RuffmanError> {
+        let mut huffman = self.clone();
+        huffman.input = if self.bytes {
+            input.bytes().map(char::from).collect()
+        } else {
+            let input = match self.indent {
+                true => indent::encode(input),
+                false => input.to_owned(),
+            };
+            let input = match self.lz {
+                true => lz::encode(&input),
+                false => input,
+            };
+            match self.rle {
+                true => rle::encode(&input),
+                false => input,
+            }
+        };
+        if huffman.char_codes.is_empty() {
+            huffman.compress();
+        }
+
+        let bits = huffman.encode(&huffman.input)?;
+        let mut out = Vec::new();
+        // Writing to a `Vec` can't fail.
+        container::write_member(&huffman, &bits, &container::Metadata::default(), &mut out)
+            .unwrap();
+
+        Ok(out)
+    }
+
+    /// Decodes a file produced by [`Huffman::to_container`], or any other
+    /// file of text members.
+    pub fn from_container(bytes: &[u8]) -> Result<String, RuffmanError> {
+        container::decompress(bytes)
+    }
+
+    pub fn get_compressed(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        for char in self.input.chars() {
+            self.push_code(char, &mut result).unwrap();
+        }
+
+        result
+    }
+
+    /// Appends the code for `symb`, or the escape code and a literal if the
+    /// tree has an escape but no code for it. The escape symbol itself is
+    /// always written as a literal.
+    fn push_code(&self, symb: char, out: &mut Vec<u8>) -> Result<(), RuffmanError> {
+        if let Some(code) = self.char_codes.get(&symb) {
+            if Some(symb) != self.escape {
+                out.extend(code);
+                return Ok(());
+            }
+        }
+
+        let code = self
+            .escape
+            .and_then(|escape| self.char_codes.get(&escape))
+            .ok_or(RuffmanError::UnknownSymbol(symb))?;
+        out.extend(code);
+        literals::push(symb, out);
+        Ok(())
+    }
+
+    /// Whether a member coding `input` with this codec's tree, header and
+    /// tree included, would be larger than `input` itself, estimated from
+    /// code lengths without encoding. Input with a symbol the tree can't
+    /// code always would, as does any input if the tree can't be stored.
+    /// This is the check behind storing input as is rather than compressing
+    /// it.
+    pub fn would_expand(&self, input: &str) -> bool {
+        let lengths = self.code_lengths();
+        let escape_len = self
+            .escape
+            .and_then(|escape| lengths.get(&escape))
+            .map(|len| *len as u64 + literals::LITERAL_BITS as u64);
+
+        let (mut symbols, mut bits) = (0, 0);
+        for symb in input.chars() {
+            let len = match lengths.get(&symb) {
+                Some(len) if Some(symb) != self.escape => Some(*len as u64),
+                _ => escape_len,
+            };
+            let Some(len) = len else {
+                return true;
+            };
+            symbols += 1;
+            bits += len;
+        }
+
+        container::estimate_member_len(self, symbols, bits)
+            .is_none_or(|len| len > input.len() as u64)
+    }
+
+    /// Encodes `input` with this codec's tree, which may be a shared one from
+    /// [`Huffman::with_tree`], and decodes it again. Fails with
+    /// [`RuffmanError::UnknownSymbol`] if the tree has no code for a symbol of
+    /// `input`, or [`RuffmanError::RoundTripMismatch`] if the text doesn't
+    /// come back the same.
+    pub fn self_check(&self, input: &str) -> Result<(), RuffmanError> {
+        self.tree.validate()?;
+
+        let mut codec = Self {
+            input: String::new(),
+            char_codes: HashMap::new(),
+            tree: self.tree.clone(),
+            canonical: self.canonical,
+            rle: false,
+            lz: false,
+            indent: false,
+            bytes: false,
+            escape: self.escape,
+        };
+        codec.compress();
+
+        let mut bits = Vec::new();
+        for symb in input.chars() {
+            codec.push_code(symb, &mut bits)?;
+        }
+
+        let decoded = codec.decompress(bits);
+        if decoded != input {
+            return Err(RuffmanError::RoundTripMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// [`Huffman::get_compressed`] as a string of `0`s and `1`s, e.g. to
+    /// show the exact encoding or for test fixtures.
+    pub fn get_compressed_string(&self) -> String {
+        self.get_compressed()
+            .iter()
+            .map(|bit| if *bit == 0 { '0' } else { '1' })
+            .collect()
+    }
+
+    pub fn decompress(&self, compressed: Vec<u8>) -> String {
+        let mut result = String::new();
+        let mut current_node = &self.tree;
+        let mut compressed = compressed.into_iter();
+
+        while let Some(val) = compressed.next() {
+            if val == 0 {
+                if let Node::Internal(internal) = current_node {
+                    if let Some(left) = &internal.left {
+                        current_node = left;
+                    }
+                }
+            } else if let Node::Internal(internal) = current_node {
+                if let Some(right) = &internal.right {
+                    current_node = right;
+                }
+            }
+
+            if let Node::Leaf(leaf) = current_node {
+                if Some(leaf.symb) == self.escape {
+                    let bits = compressed
+                        .by_ref()
+                        .take(literals::LITERAL_BITS)
+                        .map(|bit| bit == 1)
+                        .collect::<BitVec<u8, Msb0>>();
+                    match literals::read(&bits) {
+                        Some(symb) => result.push(symb),
+                        None => break,
+                    }
+                } else {
+                    result.push(leaf.symb);
+                }
+                current_node = &self.tree;
+            }
+        }
+
+        result
+    }
+}
+
+/// Default for [`HuffmanBuilder::max_symbols`].
+pub const DEFAULT_MAX_SYMBOLS: usize = 65536;
+
+/// How a container member stores its tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Serialization {
+    /// The tree's shape with a leaf for each symbol, as plain symbols or
+    /// through a symbol table, whichever is smaller.
+    #[default]
+    Structural,
+    /// Canonical code lengths only, which is smaller for large alphabets and
+    /// what other canonical Huffman decoders expect. The tree is
+    /// canonicalized to match, see [`Huffman::canonicalize`].
+    Canonical,
+}
+
+/// Configures how a [`Huffman`] is built from its input.
+#[derive(Clone, Debug)]
+pub struct HuffmanBuilder {
+    allowed_symbols: Option<HashSet<char>>,
+    serialization: Serialization,
+    rle: bool,
+    lz: bool,
+    indent: bool,
+    max_symbols: usize,
+    coded_symbols: Option<usize>,
+    weights: HashMap<char, f64>,
+}
+
+impl Default for HuffmanBuilder {
+    fn default() -> Self {
+        Self {
+            allowed_symbols: None,
+            serialization: Serialization::Structural,
+            rle: false,
+            lz: false,
+            indent: false,
+            max_symbols: DEFAULT_MAX_SYMBOLS,
+            coded_symbols: None,
+            weights: HashMap::new(),
+        }
+    }
+}
+
+impl HuffmanBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts the input to `symbols`; building over anything else fails
+    /// with [`RuffmanError::UnknownSymbol`].
+    pub fn allowed_symbols(mut self, symbols: impl IntoIterator<Item = char>) -> Self {
+        self.allowed_symbols = Some(symbols.into_iter().collect());
+        self
+    }
+
+    /// Chooses how the tree is stored when written to a container.
+    pub fn serialization(mut self, serialization: Serialization) -> Self {
+        self.serialization = serialization;
+        self
+    }
+
+    /// Shorthand for [`Serialization::Canonical`] when `canonical` is set,
+    /// and [`Serialization::Structural`] otherwise.
+    pub fn canonical(self, canonical: bool) -> Self {
+        self.serialization(match canonical {
+            true => Serialization::Canonical,
+            false => Serialization::Structural,
+        })
+    }
+
+    /// Collapses runs of a repeated symbol before coding, which helps inputs
+    /// such as logs and bitmaps. The runs are expanded again on decode.
+    pub fn rle(mut self, rle: bool) -> Self {
+        self.rle = rle;
+        self
+    }
+
+    /// Replaces repeats of earlier text with references back to it before
+    /// coding, in the manner of DEFLATE, which helps repetitive inputs such
+    /// as markup and source code. The repeats are restored on decode.
+    pub fn lz(mut self, lz: bool) -> Self {
+        self.lz = lz;
+        self
+    }
+
+    /// Replaces the spaces or tabs indenting each line with a single symbol
+    /// per run before coding, which helps deeply indented source code and
+    /// markup. The indentation is restored on decode.
+    pub fn indent(mut self, indent: bool) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Caps the number of distinct symbols in the tree; building over more
+    /// fails with [`RuffmanError::AlphabetTooLarge`]. Defaults to
+    /// [`DEFAULT_MAX_SYMBOLS`].
+    pub fn max_symbols(mut self, max_symbols: usize) -> Self {
+        self.max_symbols = max_symbols;
+        self
+    }
+
+    /// Gives codes only to the `coded_symbols` most frequent symbols. The
+    /// rest share an escape code followed by the symbol itself, which keeps
+    /// the tree small for inputs with a few common symbols and many rare
+    /// ones.
+    pub fn coded_symbols(mut self, coded_symbols: usize) -> Self {
+        self.coded_symbols = Some(coded_symbols);
+        self
+    }
+
+    /// Scales `symb`'s count by `factor` before the tree is built, so a
+    /// factor below 1 lengthens its code and one above 1 shortens it. A
+    /// present symbol keeps a weight of at least 1, so it still gets a code
+    /// and the output still decodes exactly.
+    pub fn weight_override(mut self, symb: char, factor: f64) -> Self {
+        self.weights.insert(symb, factor);
+        self
+    }
+
+    pub fn build(&self, input: String) -> Result<Huffman, RuffmanError> {
+        if let Some(allowed) = &self.allowed_symbols {
+            if let Some(symb) = input.chars().find(|symb| !allowed.contains(symb)) {
+                return Err(RuffmanError::UnknownSymbol(symb));
+            }
+        }
+
+        let input = match self.indent {
+            true => indent::encode(&input),
+            false => input,
+        };
+        let input = match self.lz {
+            true => lz::encode(&input),
+            false => input,
+        };
+        let input = match self.rle {
+            true => rle::encode(&input),
+            false => input,
+        };
+
+        // Checked ahead of counting, which is slow on huge alphabets.
+        let found = input.chars().collect::<HashSet<_>>().len();
+        if found > self.max_symbols {
+            return Err(RuffmanError::AlphabetTooLarge {
+                found,
+                max: self.max_symbols,
+            });
+        }
+
+        let mut freqs = calc_freq(input.clone());
+        for (symb, count) in &mut freqs {
+            if let Some(factor) = self.weights.get(symb) {
+                // A NaN weight falls back to 1, and `as` saturates.
+                *count = (*count as f64 * factor).round().max(1.0) as u32;
+            }
+        }
+        // A large factor can push the total past what the tree's weights
+        // hold, so the table is scaled back down to fit, each symbol keeping
+        // a count of at least 1.
+        let total = freqs.iter().map(|(_, count)| *count as u64).sum::<u64>();
+        if total > u32::MAX as u64 {
+            let room = (u32::MAX as u64 - 2 * freqs.len() as u64) as f64 / total as f64;
+            for (_, count) in &mut freqs {
+                *count = (*count as f64 * room).floor().max(1.0) as u32;
+            }
+        }
+
+        let (freqs, escape) = match self.coded_symbols {
+            Some(coded) => literals::split(freqs, coded),
+            None => (freqs, None),
+        };
+
+        let mut huffman = Huffman {
+            tree: calc_huff(freqs),
+            input,
+            char_codes: HashMap::new(),
+            canonical: false,
+            rle: self.rle,
+            lz: self.lz,
+            indent: self.indent,
+            bytes: false,
+            escape,
+        };
+        if self.serialization == Serialization::Canonical {
+            huffman.canonicalize();
+        }
+
+        Ok(huffman)
+    }
+}
+
+impl From<Huffman> for NodeBytes {
+    fn from(value: Huffman) -> Self {
+        Self::from(value.tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_roundtrip;
+
+    #[test]
+    fn compress_huff() {
+        let mut h = Huffman::from_input("Hello".to_owned());
+        h.compress();
+
+        assert_eq!(h.get_compressed(), vec![0, 0, 0, 1, 1, 1, 1, 1, 1, 0]);
+        assert_roundtrip("Hello");
+    }
+
+    #[test]
+    fn compressed_string_for_hello() {
+        let mut h = Huffman::from_input("Hello".to_owned());
+        h.compress();
+
+        assert_eq!(h.get_compressed_string(), "0001111110");
+    }
+
+    #[test]
+    fn decompress_huff() {
+        let h = Huffman::from_input("Hello".to_owned());
+
+        assert_eq!(h.decompress(vec![0, 0, 0, 1, 1, 1, 1, 1, 1, 0]), "Hello");
+        assert_roundtrip("Hello");
+    }
+
+    #[test]
+    fn root_weight_is_input_length() {
+        let h = Huffman::from_input("Hello".to_owned());
+        let leaves = canonical::leaf_lengths(h.tree());
+
+        assert_eq!(h.tree().weight(), 5);
+        assert_eq!(h.total_weight(), 5);
+        assert_eq!(leaves.iter().map(|(_, weight, _)| weight).sum::<u32>(), 5);
+    }
+
+    #[test]
+    fn accessors_walk_hello_tree() {
+        let h = Huffman::from_input("Hello".to_owned());
+
+        let mut leaves = Vec::new();
+        let mut stack = vec![(h.tree(), String::new())];
+        while let Some((node, code)) = stack.pop() {
+            match node.children() {
+                Some((left, right)) => {
+                    assert!(!node.is_leaf() && node.symbol().is_none());
+                    stack.push((right, format!("{}1", code)));
+                    stack.push((left, format!("{}0", code)));
+                }
+                None => {
+                    assert!(node.is_leaf());
+                    leaves.push((node.symbol().unwrap(), code));
+                }
+            }
+        }
+
+        let expected = [('H', "00"), ('e', "01"), ('o', "10"), ('l', "11")]
+            .map(|(symb, code)| (symb, code.to_owned()));
+        assert_eq!(leaves, expected);
+        assert_eq!(Node::empty().children(), None);
+        assert!(!Node::empty().is_leaf());
+    }
+
+    #[cfg(feature = "base64")]
+    #[test]
+    fn base64_round_trip() {
+        let input = "the rain in spain stays mainly in the plain";
+        let encoded = Huffman::compress_to_base64(input.to_owned());
+
+        assert!(encoded.starts_with("UlVGRg")); // "RUFF"
+        assert!(encoded
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"+/=".contains(&b)));
+        assert_eq!(Huffman::decompress_from_base64(&encoded).unwrap(), input);
+        assert!(matches!(
+            Huffman::decompress_from_base64("not base64!"),
+            Err(RuffmanError::InvalidBase64)
+        ));
+    }
+
+    #[test]
+    fn freq_counter_matches_calc_freq() {
+        let input = "mississippi, said the riverside ☃☃";
+        let mut counter = FreqCounter::new();
+        for c in input.chars() {
+            counter.push(c);
+        }
+
+        assert_eq!(counter.finish(), calc_freq(input.to_owned()));
+        assert!(FreqCounter::new().finish().is_empty());
+
+        // Symbols keep the order they were first seen in.
+        assert_eq!(
+            calc_freq("abcab".to_owned()),
+            vec![('a', 2), ('b', 2), ('c', 1)]
+        );
+
+        let mut counter = FreqCounter::new();
+        counter.push('a');
+        counter.freqs[0].1 = u32::MAX;
+        counter.push('a');
+        assert_eq!(counter.finish(), vec![('a', u32::MAX)]);
+    }
+
+    #[test]
+    fn code_strings_for_hello() {
+        let mut h = Huffman::from_input("Hello".to_owned());
+        h.compress();
+
+        let expected = [('H', "00"), ('e', "01"), ('l', "11"), ('o', "10")]
+            .map(|(symb, code)| (symb, code.to_owned()));
+        assert_eq!(h.code_strings(), BTreeMap::from(expected));
+    }
+
+    #[test]
+    fn code_lengths_import_deflate_table() {
+        // The example in RFC 1951, section 3.2.2.
+        let lengths = [
+            ('A', 3),
+            ('B', 3),
+            ('C', 3),
+            ('D', 3),
+            ('E', 3),
+            ('F', 2),
+            ('G', 4),
+            ('H', 4),
+        ];
+        let h = Huffman::from_code_lengths(&lengths).unwrap();
+        let expected = [
+            ('A', "010"),
+            ('B', "011"),
+            ('C', "100"),
+            ('D', "101"),
+            ('E', "110"),
+            ('F', "00"),
+            ('G', "1110"),
+            ('H', "1111"),
+        ]
+        .map(|(symb, code)| (symb, code.to_owned()));
+        assert_eq!(h.code_strings(), BTreeMap::from(expected));
+        let widened = lengths.map(|(symb, len)| (symb, len as usize));
+        assert_eq!(h.code_lengths(), HashMap::from(widened));
+        assert_eq!(h.encode("FACE").unwrap(), [0, 0, 0, 1, 0, 1, 0, 0, 1, 1, 0]);
+
+        let lone = Huffman::from_code_lengths(&[('a', 1), ('b', 0)]).unwrap();
+        assert_eq!(lone.encode("aa").unwrap(), [0, 0]);
+    }
+
+    #[test]
+    fn injected_two_leaf_tree() {
+        let leaf = |symb| Box::new(Node::Leaf(LeafNode { weight: 1, symb }));
+        let tree = |left, right| {
+            Node::Internal(InternalNode {
+                left: Some(leaf(left)),
+                right: Some(leaf(right)),
+                weight: 2,
+            })
+        };
+
+        let mut huffman = Huffman::from(tree('a', 'b'));
+        huffman.compress();
+        assert_eq!(huffman.encode("abba").unwrap(), [0, 1, 1, 0]);
+        assert_eq!(huffman.decompress(vec![1, 0, 0]), "baa");
+
+        huffman.set_tree(tree('b', 'a'));
+        assert!(matches!(
+            huffman.encode("a"),
+            Err(RuffmanError::UnknownSymbol('a'))
+        ));
+        huffman.compress();
+        assert_eq!(huffman.encode("abba").unwrap(), [1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn kraft_sum_of_code_lengths() {
+        assert_eq!(kraft_sum(&[1, 2, 3, 3]), 1.0);
+        assert_eq!(kraft_sum(&[3, 3, 3, 3, 3, 2, 4, 4]), 1.0);
+        assert!(kraft_sum(&[1, 1, 2]) > 1.0);
+        assert_eq!(kraft_sum(&[1, 2, 0]), 0.75);
+        assert_eq!(kraft_sum(&[]), 0.0);
+    }
+
+    #[test]
+    fn code_lengths_must_form_complete_code() {
+        for lengths in [
+            // Over-subscribed: three 1-bit codes.
+            &[('a', 1), ('b', 1), ('c', 1)][..],
+            // Incomplete: 11 is never used.
+            &[('a', 1), ('b', 2)],
+            &[('a', 2)],
+            &[],
+        ] {
+            assert!(matches!(
+                Huffman::from_code_lengths(lengths),
+                Err(RuffmanError::InvalidCodeLengths)
+            ));
+        }
+        assert!(matches!(
+            Huffman::from_code_lengths(&[('a', 1), ('a', 1)]),
+            Err(RuffmanError::DuplicateSymbol('a'))
+        ));
+    }
+
+    #[test]
+    fn histogram_builds_byte_tree() {
+        let mut counts = [0; 256];
+        counts[b'a' as usize] = 5;
+        counts[0x00] = 2;
+        counts[b'\n' as usize] = 1;
+        counts[0xFF] = 1;
+
+        let mut h = Huffman::from_histogram(counts);
+        h.compress();
+        let expected = [('a', 1), ('\0', 2), ('\n', 3), ('\u{FF}', 3)];
+        assert_eq!(h.code_lengths(), HashMap::from(expected));
+
+        let mut from_bytes = Huffman::from_bytes(b"aaaaa\0\0\n\xFF");
+        from_bytes.compress();
+        assert_eq!(h.code_strings(), from_bytes.code_strings());
+
+        // Counts past u32 are scaled rather than overflowing.
+        let huge = Huffman::from_histogram(counts.map(|count| count * (u64::MAX / 8)));
+        assert_eq!(huge.code_lengths(), HashMap::from(expected));
+    }
+
+    #[test]
+    fn would_expand_short_random_input() {
+        let h = Huffman::from_input("q7#Zx!".to_owned());
+        assert!(h.would_expand("q7#Zx!"));
+        assert!(h.would_expand("unknown symbols"));
+
+        let input = "abab".repeat(500);
+        let h = Huffman::from_input(input.clone());
+        assert!(!h.would_expand(&input));
+    }
+
+    #[test]
+    fn self_check_needs_every_symbol() {
+        let h = Huffman::from_input("the rain in spain".to_owned());
+        assert!(h.self_check("the rain in spain").is_ok());
+        assert!(h.self_check("spin the train").is_ok());
+        assert!(matches!(
+            h.self_check("the rain in spain stays"),
+            Err(RuffmanError::UnknownSymbol('y'))
+        ));
+
+        let shared = Huffman::with_tree(h.tree().clone(), "pain".to_owned()).unwrap();
+        assert!(shared.self_check("in a rain pit").is_ok());
+
+        // Escaped symbols need no code of their own.
+        let escaped = HuffmanBuilder::new()
+            .coded_symbols(2)
+            .build("aaaabbbc".to_owned())
+            .unwrap();
+        assert!(escaped.self_check("cabbage ☃").is_ok());
+    }
+
+    #[test]
+    fn code_lengths_match_hello_tree() {
+        let h = Huffman::from_input("Hello".to_owned());
+        let expected = [('H', 2), ('e', 2), ('l', 2), ('o', 2)];
+        assert_eq!(h.code_lengths(), HashMap::from(expected));
+
+        let h = Huffman::from_input("aaaa".to_owned());
+        assert_eq!(h.code_lengths(), HashMap::from([('a', 1)]));
+    }
+
+    #[test]
+    fn tree_matches_by_structure() {
+        let leaf = |symb, weight| Some(Box::new(Node::Leaf(LeafNode { weight, symb })));
+        let tree = Node::Internal(InternalNode {
+            left: leaf('a', 3),
+            right: Some(Box::new(Node::Internal(InternalNode {
+                left: leaf('b', 1),
+                right: leaf('c', 1),
+                weight: 2,
+            }))),
+            weight: 5,
+        });
+        assert!(tree.validate().is_ok());
+
+        let Node::Internal(InternalNode {
+            left: Some(left),
+            right: Some(right),
+            weight: 5,
+        }) = &tree
+        else {
+            panic!("expected a branch");
+        };
+        assert!(matches!(
+            **left,
+            Node::Leaf(LeafNode {
+                symb: 'a',
+                weight: 3
+            })
+        ));
+        assert!(matches!(
+            **right,
+            Node::Internal(InternalNode { weight: 2, .. })
+        ));
+
+        let mut h = Huffman::with_tree(tree, "abcab".to_owned()).unwrap();
+        h.compress();
+        assert_eq!(h.get_compressed_string(), "01011010");
+    }
+
+    #[test]
+    fn container_round_trips_in_memory() {
+        let huffman = Huffman::from_input("abracadabra".to_owned());
+        for input in ["abracadabra", "cabbad", ""] {
+            let bytes = huffman.to_container(input).unwrap();
+            assert_eq!(Huffman::from_container(&bytes).unwrap(), input);
+        }
+
+        assert!(matches!(
+            huffman.to_container("abc!"),
+            Err(RuffmanError::UnknownSymbol('!'))
+        ));
+
+        let mut bytes = Vec::new();
+        container::compress("abracadabra".to_owned(), &mut bytes).unwrap();
+        assert_eq!(huffman.to_container("abracadabra").unwrap(), bytes);
+    }
+
+    #[test]
+    fn stored_surrogate_is_rejected() {
+        for value in [0xD800u32, 0xDFFF, 0x11_0000] {
+            let mut bytes = 1u32.to_be_bytes().to_vec();
+            bytes.push(1);
+            bytes.extend(value.to_be_bytes());
+            bytes.extend(1u32.to_be_bytes());
+
+            assert!(matches!(
+                NodeBytes::try_from(bytes),
+                Err(RuffmanError::InvalidSymbol(v)) if v == value
+            ));
+        }
+    }
+
+    #[test]
+    fn extremes_of_hello() {
+        let h = Huffman::from_input("Hello".to_owned());
+        assert_eq!(h.extremes(), Some((('l', 2), ('H', 1))));
+
+        let h = Huffman::from_input("aaaa".to_owned());
+        assert_eq!(h.extremes(), Some((('a', 4), ('a', 4))));
+        assert_eq!(Huffman::from_input(String::new()).extremes(), None);
+    }
+
+    #[test]
+    fn symbol_costs_add_up_to_payload() {
+        let mut h = Huffman::from_input("Hello".to_owned());
+        h.compress();
+        assert_eq!(
+            h.symbol_costs(),
+            [
+                ('l', 2, 2, 4),
+                ('H', 1, 2, 2),
+                ('e', 1, 2, 2),
+                ('o', 1, 2, 2)
+            ]
+        );
+
+        let input = "abracadabra, alakazam! ☃".repeat(3);
+        for builder in [
+            HuffmanBuilder::new(),
+            HuffmanBuilder::new().coded_symbols(3),
+        ] {
+            let mut h = builder.build(input.clone()).unwrap();
+            h.compress();
+
+            let costs = h.symbol_costs();
+            assert!(costs.windows(2).all(|w| w[0].3 >= w[1].3));
+            let total = costs.iter().map(|(_, _, _, bits)| bits).sum::<u64>();
+            assert_eq!(total, h.get_compressed().len() as u64);
+        }
+
+        let mut h = Huffman::from_input("zzz".to_owned());
+        h.compress();
+        assert_eq!(h.symbol_costs(), [('z', 3, 1, 3)]);
+    }
+
+    #[test]
+    fn code_diff_lists_moved_symbols() {
+        let leaf = |symb, weight| Some(Box::new(Node::Leaf(LeafNode { symb, weight })));
+        let internal = |left, right, weight| {
+            Some(Box::new(Node::Internal(InternalNode {
+                left,
+                right,
+                weight,
+            })))
+        };
+
+        let mut h = Huffman::from_input("abccdd".to_owned());
+        h.compress();
+
+        // The same weights with the tie between 'a' and 'b' broken the
+        // other way.
+        let tree = internal(
+            internal(leaf('b', 1), leaf('a', 1), 2),
+            internal(leaf('c', 2), leaf('d', 2), 4),
+            6,
+        );
+        let mut other = Huffman::with_tree(*tree.unwrap(), "abccdd".to_owned()).unwrap();
+        other.compress();
+
+        let some = |code: &str| Some(code.to_owned());
+        assert_eq!(
+            h.code_diff(&other),
+            vec![('a', some("00"), some("01")), ('b', some("01"), some("00"))]
+        );
+        assert!(h.code_diff(&h).is_empty());
+
+        let mut small = Huffman::from_input("ab".to_owned());
+        small.compress();
+        assert_eq!(
+            small.code_diff(&h),
+            vec![
+                ('a', some("0"), some("00")),
+                ('b', some("1"), some("01")),
+                ('c', None, some("10")),
+                ('d', None, some("11")),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_code_length_of_skewed_input() {
+        // Fibonacci counts give the deepest tree for their alphabet size.
+        let input = "abcccddddd".to_owned() + &"e".repeat(8) + &"f".repeat(13);
+        let huffman = Huffman::from_input(input);
+
+        assert_eq!(huffman.max_code_length(), Some(5));
+        assert_eq!(huffman.tree().depth(), 5);
+        assert_eq!(
+            Huffman::from_input("zzz".to_owned()).max_code_length(),
+            Some(1)
+        );
+        assert_eq!(Huffman::from_input(String::new()).max_code_length(), None);
+    }
+
+    #[test]
+    fn length_histogram_of_known_trees() {
+        let histogram = |input: &str| {
+            Huffman::from_input(input.to_owned())
+                .tree
+                .length_histogram()
+        };
+
+        assert_eq!(histogram("Hello"), BTreeMap::from([(2, 4)]));
+        assert_eq!(histogram("aaaabbc"), BTreeMap::from([(1, 1), (2, 2)]));
+        assert_eq!(histogram("zzz"), BTreeMap::from([(1, 1)]));
+        assert!(histogram("").is_empty());
+    }
+
+    #[test]
+    fn code_for_single_symbols() {
+        let mut h = Huffman::from_input("Hello".to_owned());
+        h.compress();
+
+        assert_eq!(h.code_for('H'), Some(&[0, 0][..]));
+        assert_eq!(h.code_for('z'), None);
+    }
+
+    #[test]
+    fn canonicalize_keeps_lengths() {
+        let mut h = Huffman::from_input("abracadabra alakazam".to_owned());
+        h.compress();
+        let before = h.char_codes.clone();
+
+        h.canonicalize();
+        let after = h.char_codes.clone();
+        h.char_codes.clear();
+        h.compress();
+
+        assert_eq!(h.char_codes, after);
+        for (symb, code) in &before {
+            assert_eq!(code.len(), after[symb].len());
+        }
+        assert_eq!(h.decompress(h.get_compressed()), "abracadabra alakazam");
+    }
+
+    #[test]
+    fn from_reader_matches_from_input() {
+        // Yields one byte per read, so every multi-byte char is split.
+        struct Trickle<'a>(&'a [u8]);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let Some((first, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                buf[0] = *first;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+
+        let input = "naïve café ✓✓ 🦀 résumé";
+        let h = Huffman::from_reader(&mut Trickle(input.as_bytes())).unwrap();
+
+        assert_eq!(h.tree, Huffman::from_input(input.to_owned()).tree);
+    }
+
+    #[test]
+    fn from_reader_rejects_invalid_utf8() {
+        let bytes: &[u8] = &[b'a', 0xE2, 0x9C];
+
+        assert!(Huffman::from_reader(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn validate_tree() {
+        let h = Huffman::from_input("abracadabra".to_owned());
+        assert!(h.tree.validate().is_ok());
+
+        let leaf = |symb| Node::Leaf(LeafNode { weight: 1, symb });
+        let single_child = Node::Internal(InternalNode {
+            left: Some(Box::new(leaf('a'))),
+            right: None,
+            weight: 1,
+        });
+        assert!(matches!(
+            single_child.validate(),
+            Err(RuffmanError::MissingChild)
+        ));
+
+        let duplicate = Node::Internal(InternalNode {
+            left: Some(Box::new(leaf('a'))),
+            right: Some(Box::new(leaf('a'))),
+            weight: 2,
+        });
+        assert!(matches!(
+            duplicate.validate(),
+            Err(RuffmanError::DuplicateSymbol('a'))
+        ));
+    }
+
+    #[test]
+    fn leaf_count_mismatch() {
+        let mut tree = NodeBytes::from(Huffman::from_input("abc".to_owned()).tree);
+        tree.as_bytes();
+        assert!(NodeBytes::try_from(tree.bytes.clone()).is_ok());
+
+        // Declares more leaves than follow, as a truncated tree would.
+        let mut too_few = tree.bytes.clone();
+        too_few[..4].copy_from_slice(&4u32.to_be_bytes());
+        assert!(matches!(
+            NodeBytes::try_from(too_few),
+            Err(RuffmanError::TreeSymbolMismatch {
+                expected: 4,
+                found: 3
+            })
+        ));
+
+        // Trailing junk that parses as an extra leaf.
+        let mut too_many = tree.bytes.clone();
+        too_many.push(1);
+        too_many.extend(u32::from('z').to_be_bytes());
+        too_many.extend(1u32.to_be_bytes());
+        assert!(matches!(
+            NodeBytes::try_from(too_many),
+            Err(RuffmanError::TreeSymbolMismatch {
+                expected: 3,
+                found: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn deep_tree_walks_without_recursion() {
+        use bitvec::prelude::*;
+
+        const DEPTH: u32 = 2000;
+
+        fn symb(i: u32) -> char {
+            char::from_u32(0x4E00 + i).unwrap()
+        }
+
+        // A caterpillar: each level has one leaf and one subtree, so the
+        // deepest code is DEPTH bits. A recursive walk would need a stack
+        // frame per level, far more than the small stack below.
+        fn caterpillar() -> Node {
+            let leaf = |i| {
+                Node::Leaf(LeafNode {
+                    weight: 1,
+                    symb: symb(i),
+                })
+            };
+            let mut tree = leaf(DEPTH);
+            for i in (0..DEPTH).rev() {
+                tree = Node::Internal(InternalNode {
+                    weight: tree.weight() + 1,
+                    left: Some(Box::new(leaf(i))),
+                    right: Some(Box::new(tree)),
+                });
+            }
+
+            tree
+        }
+
+        let run = || {
+            let tree = caterpillar();
+            tree.validate().unwrap();
+            assert_eq!(tree.leaf_count(), DEPTH as usize + 1);
+            assert_eq!(tree.to_string().lines().count(), 2 * DEPTH as usize + 1);
+            assert!(remap::write_tree(&tree).len() < structural_bytes(&tree).len());
+
+            let mut nodes = NodeBytes::from(caterpillar());
+            nodes.gen_input();
+            assert_eq!(nodes.input.chars().count(), DEPTH as usize + 1);
+
+            let input = (0..=DEPTH).rev().map(symb).collect::<String>();
+            let mut h = Huffman::with_tree(tree, input.clone()).unwrap();
+            h.compress();
+            assert_eq!(h.code_for(symb(DEPTH)).unwrap().len(), DEPTH as usize);
+
+            let bits = h.get_compressed();
+            let packed = bits
+                .iter()
+                .map(|bit| *bit == 1)
+                .collect::<BitVec<u8, Msb0>>();
+            assert_eq!(decode::DecodeTable::new(h.tree()).decode(&packed), input);
+            assert_eq!(h.decompress(bits), input);
+
+            // Lengths past a byte are reported as they are, and refused
+            // where a format has no room for them.
+            let histogram = h.tree().length_histogram();
+            assert_eq!(histogram.len(), DEPTH as usize);
+            assert_eq!(histogram[&(DEPTH as usize)], 2);
+            assert_eq!(h.max_code_length(), Some(DEPTH as usize));
+            assert_eq!(h.code_lengths()[&symb(DEPTH)], DEPTH as usize);
+            assert!(matches!(
+                h.tree().to_c_header(),
+                Err(RuffmanError::CodeTooLong {
+                    length: 2000,
+                    max: 64
+                })
+            ));
+
+            h.canonicalize();
+            assert_eq!(h.code_for(symb(DEPTH)).unwrap().len(), DEPTH as usize);
+            assert_eq!(h.decompress(h.get_compressed()), input);
+            let mut out = Vec::new();
+            let written =
+                container::write_member(&h, &h.get_compressed(), &Default::default(), &mut out);
+            assert!(written.is_err());
+        };
+
+        std::thread::Builder::new()
+            .stack_size(64 * 1024)
+            .spawn(run)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn rebuild_empty_and_single_leaf_trees() {
+        for input in ["", "aaa"] {
+            let mut h = Huffman::from_input(input.to_owned());
+            h.compress();
+            assert_eq!(h.decompress(h.get_compressed()), input);
+
+            let mut tree = NodeBytes::from(h.tree.clone());
+            tree.as_bytes();
+            let rebuilt = NodeBytes::try_from(tree.bytes).unwrap().node;
+            assert_eq!(rebuilt, h.tree);
+            assert!(rebuilt.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn entropy_of_tables() {
+        assert_eq!(entropy(&[('a', 4)]), 0.0);
+        assert_eq!(entropy(&[('a', 1), ('b', 1)]), 1.0);
+        assert_eq!(entropy(&[('a', 2), ('b', 1), ('c', 1)]), 1.5);
+    }
+
+    #[test]
+    fn estimate_matches_payload_bits() {
+        for input in ["", "aaaa", "Hello", "abracadabra alakazam"] {
+            let mut h = Huffman::from_input(input.to_owned());
+            h.compress();
+
+            assert_eq!(
+                estimate_compressed_bits(&calc_freq(input.to_owned())),
+                h.get_compressed().len() as u64
+            );
+        }
+    }
+
+    #[test]
+    fn encode_from_frequency_table() {
+        let freqs = [('a', 3), ('b', 1)];
+
+        let bits = encode_frequencies(&freqs, "aaab").unwrap();
+        assert_eq!(bits, bitvec::bits![u8, Msb0; 1, 1, 1, 0]);
+        assert_eq!(bits.into_vec(), [0b1110_0000]);
+        assert!(matches!(
+            encode_frequencies(&freqs, "abc"),
+            Err(RuffmanError::UnknownSymbol('c'))
+        ));
+    }
+
+    #[test]
+    fn traced_build_records_every_merge() {
+        let freqs = calc_freq("abracadabra alakazam".to_owned());
+        let symbols = freqs.len();
+        let (tree, steps) = calc_huff_traced(freqs.clone());
+
+        assert_eq!(tree, calc_huff(freqs));
+        assert_eq!(steps.len(), symbols - 1);
+        assert_eq!(steps.last().unwrap().merged, tree);
+        for step in &steps {
+            assert!(step.left_weight <= step.right_weight);
+            assert_eq!(step.merged.weight(), step.left_weight + step.right_weight);
+        }
+    }
+
+    #[test]
+    fn tree_ignores_frequency_order() {
+        let freqs = calc_freq("mississippi river banks".to_owned());
+        let mut reversed = freqs.clone();
+        reversed.reverse();
+
+        assert_eq!(calc_huff(reversed), calc_huff(freqs));
+    }
+
+    #[test]
+    fn heap_pops_equal_weights_in_tie_order() {
+        let leaf = |symb, weight| Node::Leaf(LeafNode { weight, symb });
+        let internal = Node::Internal(InternalNode {
+            left: Some(Box::new(leaf('c', 1))),
+            right: Some(Box::new(leaf('d', 1))),
+            weight: 2,
+        });
+
+        let mut heap = BinaryHeap::new();
+        for node in [internal, leaf('b', 2), leaf('e', 1), leaf('a', 2)] {
+            heap.push(Reverse(WeightedNode(node)));
+        }
+
+        let order = std::iter::from_fn(|| heap.pop())
+            .map(|Reverse(node)| (node.0.weight(), node.tie_key()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            order,
+            vec![
+                (1, (false, 'e')),
+                (2, (false, 'a')),
+                (2, (false, 'b')),
+                (2, (true, 'c')),
+            ]
+        );
+    }
+
+    #[test]
+    fn builder_rejects_disallowed_symbols() {
+        let builder = HuffmanBuilder::new().allowed_symbols("ACGT".chars());
+
+        let h = builder.build("GATTACA".to_owned()).unwrap();
+        assert_eq!(h.num_symbols(), 4);
+
+        assert!(matches!(
+            builder.build("GATTACAN".to_owned()),
+            Err(RuffmanError::UnknownSymbol('N'))
+        ));
+    }
+
+    #[test]
+    fn builder_caps_alphabet_size() {
+        let wide = (0x10000..=0x10000 + DEFAULT_MAX_SYMBOLS as u32)
+            .filter_map(char::from_u32)
+            .collect::<String>();
+
+        assert!(matches!(
+            HuffmanBuilder::new().build(wide),
+            Err(RuffmanError::AlphabetTooLarge {
+                found: 65537,
+                max: DEFAULT_MAX_SYMBOLS
+            })
+        ));
+
+        let builder = HuffmanBuilder::new().max_symbols(3);
+        assert!(builder.build("abcabc".to_owned()).is_ok());
+        assert!(matches!(
+            builder.build("abcd".to_owned()),
+            Err(RuffmanError::AlphabetTooLarge { found: 4, max: 3 })
+        ));
+    }
+
+    #[test]
+    fn weight_override_lengthens_code() {
+        let input = "aaaaaaaabbbbcccd";
+        let plain = HuffmanBuilder::new().build(input.to_owned()).unwrap();
+        let weighted = HuffmanBuilder::new()
+            .weight_override('a', 0.1)
+            .build(input.to_owned())
+            .unwrap();
+
+        assert!(weighted.code_lengths()[&'a'] > plain.code_lengths()[&'a']);
+        assert!(weighted.tree().validate().is_ok());
+
+        let mut weighted = weighted;
+        weighted.compress();
+        assert_eq!(weighted.decompress(weighted.get_compressed()), input);
+
+        // Factors too large for the counts to add up in a u32 still build.
+        for coded in [None, Some(2)] {
+            let mut builder = HuffmanBuilder::new()
+                .weight_override('a', 1e12)
+                .weight_override('b', f64::INFINITY);
+            if let Some(coded) = coded {
+                builder = builder.coded_symbols(coded);
+            }
+            let mut huge = builder.build(input.to_owned()).unwrap();
+            assert!(huge.tree().validate().is_ok());
+            huge.compress();
+            assert_eq!(huge.decompress(huge.get_compressed()), input);
+        }
+    }
+
+    #[test]
+    fn merge_frequency_tables() {
+        assert_eq!(
+            merge_freqs(vec![('a', 2)], vec![('a', 3), ('b', 1)]),
+            vec![('a', 5), ('b', 1)]
+        );
+        assert_eq!(
+            merge_freqs(vec![('b', 1), ('a', 3)], vec![('a', 2)]),
+            vec![('a', 5), ('b', 1)]
+        );
+        assert_eq!(
+            merge_freqs(vec![('a', u32::MAX - 1)], vec![('a', 3)]),
+            vec![('a', u32::MAX)]
+        );
+
+        let saturated = merge_freqs(vec![('a', u32::MAX)], vec![('b', u32::MAX)]);
+        let mut h = Huffman::from_frequencies(saturated);
+        assert!(h.tree().validate().is_ok());
+        h.compress();
+        assert_eq!(h.decompress(h.encode("abba").unwrap()), "abba");
+    }
+
+    #[test]
+    fn phase_timings_populated() {
+        let input = "the quick brown fox jumps over the lazy dog ".repeat(200);
+        let mut timings = PhaseTimings::default();
+        let mut h = Huffman::from_input_timed(input, &mut timings);
+        h.compress();
+        timings.record(Phase::Encoding, || h.get_compressed());
+
+        assert!(timings.counting > Duration::ZERO);
+        assert!(timings.building > Duration::ZERO);
+        assert!(timings.encoding > Duration::ZERO);
+    }
+}
@@ -0,0 +1,81 @@
+//! Escaped literals for symbols left out of the tree.
+//!
+//! With [`crate::HuffmanBuilder::coded_symbols`] only the most frequent
+//! symbols get codes. The rest share one escape symbol in the tree, whose
+//! code is followed in the payload by the symbol's code point as a
+//! [`LITERAL_BITS`]-bit number, most significant bit first.
+
+use std::collections::HashSet;
+
+use bitvec::prelude::*;
+
+/// Enough bits for any code point up to `char::MAX`.
+pub(crate) const LITERAL_BITS: usize = 21;
+
+/// Keeps the `coded` most frequent symbols of `freqs`, ties broken by
+/// symbol, and merges the rest into one escape symbol weighted by their
+/// total count. The escape is the lowest code point `freqs` doesn't use, so
+/// it can't be mistaken for a coded symbol. Returns `None` for the escape if
+/// every symbol fits.
+pub(crate) fn split(mut freqs: Vec<(char, u32)>, coded: usize) -> (Vec<(char, u32)>, Option<char>) {
+    if freqs.len() <= coded {
+        return (freqs, None);
+    }
+
+    let used = freqs.iter().map(|(symb, _)| *symb).collect::<HashSet<_>>();
+    let escape = (0..=char::MAX as u32)
+        .filter_map(char::from_u32)
+        .find(|symb| !used.contains(symb))
+        .unwrap();
+
+    freqs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let rare = freqs.split_off(coded);
+    // Saturating, since the counts needn't come from a single input.
+    let count = rare
+        .iter()
+        .fold(0u32, |sum, (_, count)| sum.saturating_add(*count));
+    freqs.push((escape, count));
+
+    (freqs, Some(escape))
+}
+
+/// Appends `symb`'s literal bits, one per element.
+pub(crate) fn push(symb: char, out: &mut Vec<u8>) {
+    let value = symb as u32;
+    out.extend((0..LITERAL_BITS).rev().map(|bit| (value >> bit & 1) as u8));
+}
+
+/// Reads the literal at the start of `bits`, or `None` if `bits` is too
+/// short or doesn't hold a valid code point.
+pub(crate) fn read(bits: &BitSlice<u8, Msb0>) -> Option<char> {
+    let bits = bits.get(..LITERAL_BITS)?;
+    char::from_u32(bits.load_be::<u32>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_keeps_most_frequent() {
+        let freqs = vec![('a', 5), ('b', 1), ('c', 3), ('\0', 1), ('d', 3)];
+        let (coded, escape) = split(freqs.clone(), 2);
+
+        assert_eq!(escape, Some('\u{1}'));
+        assert_eq!(coded, vec![('a', 5), ('c', 3), ('\u{1}', 5)]);
+        assert_eq!(split(freqs.clone(), 5), (freqs, None));
+    }
+
+    #[test]
+    fn literal_round_trips() {
+        for symb in ['\0', 'z', '€', char::MAX] {
+            let mut bits = Vec::new();
+            push(symb, &mut bits);
+            assert_eq!(bits.len(), LITERAL_BITS);
+
+            let bits = bits.iter().map(|b| *b == 1).collect::<BitVec<u8, Msb0>>();
+            assert_eq!(read(&bits), Some(symb));
+            assert_eq!(read(&bits[1..]), None);
+        }
+    }
+}
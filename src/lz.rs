@@ -0,0 +1,188 @@
+//! LZ77-style pre-pass applied before Huffman coding.
+//!
+//! A repeat of at least [`MIN_MATCH`] symbols starting within the last
+//! [`WINDOW`] symbols becomes [`MARKER`] followed by a length char and a
+//! distance char, whose code points are the length of the match and how far
+//! back it starts. Both stay below the surrogate range, and a literal
+//! `MARKER` in the input is doubled. The tokens are then Huffman-coded along
+//! with the literals, much as DEFLATE codes its literals and matches.
+
+use std::collections::HashMap;
+
+use crate::RuffmanError;
+
+/// Starts a match token, or a literal marker when doubled. A private-use
+/// code point next to [`crate::rle::ESCAPE`], so the two pre-passes can be
+/// chained.
+pub(crate) const MARKER: char = '\u{E001}';
+
+/// Matches shorter than this cost more as a token than as literals.
+const MIN_MATCH: usize = 4;
+
+/// Longest match a single token can hold; longer repeats chain tokens.
+const MAX_MATCH: usize = 0xD7FF;
+
+/// How many symbols back a match may start.
+const WINDOW: usize = 4096;
+
+/// Earlier occurrences checked for each match, most recent first. More find
+/// longer matches, but slow down compression on repetitive input.
+const MAX_CANDIDATES: usize = 32;
+
+pub(crate) fn encode(input: &str) -> String {
+    let chars = input.chars().collect::<Vec<_>>();
+    let mut output = String::with_capacity(input.len());
+    // Where each run of MIN_MATCH symbols starts, oldest first.
+    let mut starts = HashMap::<&[char], Vec<usize>>::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (len, distance) = longest_match(&chars, i, &starts);
+        let end = if len >= MIN_MATCH {
+            output.push(MARKER);
+            output.push(char::from_u32(len as u32).unwrap());
+            output.push(char::from_u32(distance as u32).unwrap());
+            i + len
+        } else {
+            if chars[i] == MARKER {
+                output.push(MARKER);
+            }
+            output.push(chars[i]);
+            i + 1
+        };
+
+        for start in i..end.min(chars.len().saturating_sub(MIN_MATCH - 1)) {
+            starts
+                .entry(&chars[start..start + MIN_MATCH])
+                .or_default()
+                .push(start);
+        }
+        i = end;
+    }
+
+    output
+}
+
+/// The length and distance of the longest match for the symbols at `i`,
+/// or a length of 0 if there is none.
+fn longest_match(
+    chars: &[char],
+    i: usize,
+    starts: &HashMap<&[char], Vec<usize>>,
+) -> (usize, usize) {
+    let Some(candidates) = chars.get(i..i + MIN_MATCH).and_then(|key| starts.get(key)) else {
+        return (0, 0);
+    };
+
+    let max = (chars.len() - i).min(MAX_MATCH);
+    let mut best = (0, 0);
+    for &start in candidates.iter().rev().take(MAX_CANDIDATES) {
+        if i - start > WINDOW {
+            break;
+        }
+
+        // A match may overlap the symbols it repeats; decoding copies one
+        // symbol at a time, so they are there by the time they're needed.
+        let len = (0..max)
+            .take_while(|&n| chars[start + n] == chars[i + n])
+            .count();
+        if len > best.0 {
+            best = (len, i - start);
+        }
+    }
+
+    best
+}
+
+/// Expands the matches in text produced by [`encode`].
+pub(crate) fn decode(input: &str) -> Result<String, RuffmanError> {
+    decode_limited(input, usize::MAX)
+}
+
+/// Like [`decode`], failing with [`RuffmanError::OutputTooLarge`] as soon as
+/// the output grows past `limit` bytes. Each three-char token can copy up to
+/// [`MAX_MATCH`] chars, so the limit is checked per token.
+pub(crate) fn decode_limited(input: &str, limit: usize) -> Result<String, RuffmanError> {
+    let mut output = Vec::with_capacity(input.len().min(limit));
+    let mut output_len = 0;
+    let mut chars = input.chars();
+
+    while let Some(symb) = chars.next() {
+        if output_len > limit {
+            return Err(RuffmanError::OutputTooLarge);
+        }
+
+        if symb != MARKER {
+            output.push(symb);
+            output_len += symb.len_utf8();
+            continue;
+        }
+
+        match chars.next() {
+            Some(MARKER) => {
+                output.push(MARKER);
+                output_len += MARKER.len_utf8();
+            }
+            Some(len) => {
+                let len = len as usize;
+                let distance = chars.next().ok_or(RuffmanError::CorruptLz)? as usize;
+                if !(MIN_MATCH..=MAX_MATCH).contains(&len)
+                    || distance == 0
+                    || distance > WINDOW.min(output.len())
+                {
+                    return Err(RuffmanError::CorruptLz);
+                }
+
+                let start = output.len() - distance;
+                for n in start..start + len {
+                    let symb = output[n];
+                    output.push(symb);
+                    output_len += symb.len_utf8();
+                }
+            }
+            None => return Err(RuffmanError::CorruptLz),
+        }
+    }
+
+    if output_len > limit {
+        return Err(RuffmanError::OutputTooLarge);
+    }
+
+    Ok(output.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_matches_and_markers() {
+        let inputs = [
+            String::new(),
+            "abc".to_owned(),
+            "abcabcabcabcabc".to_owned(),
+            "z".repeat(MAX_MATCH * 2 + 10),
+            format!("{0}{0}{0}{0}{0}x{0}{0}{0}{0}{0}", MARKER),
+            format!("a{}\u{4}\u{1}aaaaa", MARKER),
+            "the cat sat on the mat; the cat sat on the hat".to_owned(),
+        ];
+
+        for input in inputs {
+            assert_eq!(decode(&encode(&input)).unwrap(), input);
+        }
+
+        assert_eq!(encode("abcabcabcabc").chars().count(), 6);
+    }
+
+    #[test]
+    fn rejects_match_before_start() {
+        for input in [
+            format!("ab{}\u{4}\u{3}", MARKER),
+            format!("abcd{}\u{4}\u{0}", MARKER),
+            format!("abcd{}\u{2}\u{1}", MARKER),
+            format!("abcd{}\u{4}", MARKER),
+        ] {
+            assert!(matches!(decode(&input), Err(RuffmanError::CorruptLz)));
+        }
+    }
+}
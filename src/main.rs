@@ -1,431 +1,1061 @@
 use std::{
-    cmp::Reverse,
-    collections::{BinaryHeap, HashMap},
+    collections::{HashMap, HashSet},
     env::args,
-    fs::File,
+    fs::{self, File},
     io::{self, Error, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
 };
 
-use bitvec::prelude::*;
-
-trait HasWeight {
-    fn weight(&self) -> u32;
-}
-
-#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd)]
-struct LeafNode {
-    weight: u32,
-    symb: char,
-}
+use huffman::{
+    archive, compare,
+    container::{self, Metadata},
+    eol,
+    files::{self, Stream},
+    preset, trained, CompressionStats, DecompressionStats, Huffman, HuffmanBuilder, Phase,
+    PhaseTimings,
+};
 
-#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd)]
-struct InternalNode {
-    left: Option<Box<Node>>,
-    right: Option<Box<Node>>,
-    weight: u32,
+/// The arguments following a command, split into flags and positionals.
+struct Args {
+    flags: Vec<String>,
+    values: HashMap<String, String>,
+    positional: Vec<String>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Ord, PartialOrd)]
-enum Node {
-    Leaf(LeafNode),
-    Internal(InternalNode),
-}
+impl Args {
+    /// Parses `args`, accepting the boolean `flags` and the `values` flags
+    /// that take an argument.
+    fn parse(
+        mut args: impl Iterator<Item = String>,
+        flags: &[&str],
+        values: &[&str],
+    ) -> io::Result<Self> {
+        let mut parsed = Self {
+            flags: Vec::new(),
+            values: HashMap::new(),
+            positional: Vec::new(),
+        };
 
-impl HasWeight for Node {
-    fn weight(&self) -> u32 {
-        match self {
-            Node::Leaf(leaf) => leaf.weight,
-            Node::Internal(internal) => internal.weight,
+        while let Some(arg) = args.next() {
+            if flags.contains(&arg.as_str()) {
+                parsed.flags.push(arg);
+            } else if values.contains(&arg.as_str()) {
+                let Some(value) = args.next() else {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Missing value for {}", arg),
+                    ));
+                };
+                parsed.values.insert(arg, value);
+            } else if arg.starts_with("--") {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown flag {}", arg),
+                ));
+            } else {
+                parsed.positional.push(arg);
+            }
         }
-    }
-}
 
-#[derive(Clone, Debug)]
-struct NodeBytes {
-    input: String,
-    node: Node,
-    bytes: Vec<u8>,
-}
-
-impl From<Node> for NodeBytes {
-    fn from(value: Node) -> Self {
-        Self {
-            input: String::new(),
-            node: value,
-            bytes: vec![],
-        }
+        Ok(parsed)
     }
-}
 
-impl From<Vec<u8>> for NodeBytes {
-    fn from(value: Vec<u8>) -> Self {
-        let mut bytes = Self {
-            input: String::new(),
-            node: Node::Internal(InternalNode {
-                left: None,
-                right: None,
-                weight: 0,
-            }),
-            bytes: value,
-        };
-
-        bytes.into_node();
-
-        bytes
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
     }
-}
 
-impl NodeBytes {
-    fn as_bytes(&mut self) {
-        self.as_bytes_rec(Box::new(self.node.clone()));
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
     }
 
-    // [(symb, weight), second_node]
-    fn as_bytes_rec(&mut self, node: Box<Node>) {
-        match *node {
-            Node::Internal(internal) => {
-                if let Some(left_node) = internal.left {
-                    self.bytes.push(0);
-                    self.as_bytes_rec(left_node);
-                }
+    /// The value of `name` as a number greater than zero, if given.
+    fn positive(&self, name: &str) -> io::Result<Option<u64>> {
+        let Some(value) = self.value(name) else {
+            return Ok(None);
+        };
 
-                if let Some(right_node) = internal.right {
-                    self.bytes.push(0);
-                    self.as_bytes_rec(right_node);
-                }
-            }
-            Node::Leaf(leaf) => {
-                self.bytes.push(1);
-                self.bytes.push(leaf.symb as u8);
-            }
+        match value.parse::<u64>() {
+            Ok(number) if number > 0 => Ok(Some(number)),
+            _ => Err(Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid value {} for {}", value, name),
+            )),
         }
     }
 
-    fn into_node(&mut self) {
-        let mut nodes = BinaryHeap::new();
-        let mut bytes_iter = self.bytes.clone().into_iter();
-        while let Some(val) = bytes_iter.next() {
-            if val == 1 {
-                nodes.push(Reverse(Node::Leaf(LeafNode {
-                    weight: 0,
-                    symb: bytes_iter.next().unwrap() as char,
-                })));
-            }
-        }
-        while nodes.len() > 1 {
-            let node0 = nodes.pop().unwrap();
-            let n0w = node0.0.weight();
-            let node1 = nodes.pop().unwrap();
-            let n1w = node1.0.weight();
-            let new_node = InternalNode {
-                left: Some(Box::new(node0.0)),
-                right: Some(Box::new(node1.0)),
-
-                weight: n0w + n1w,
-            };
-
-            nodes.push(Reverse(Node::Internal(new_node)));
-        }
-
-        self.node = nodes.pop().unwrap().0
+    fn positional(&self, index: usize, error: &str) -> io::Result<&str> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| Error::new(io::ErrorKind::InvalidInput, error.to_owned()))
     }
+}
 
-    fn gen_input(&mut self) {
-        fn get_input_req(node: Node, result: &mut String) {
-            match node {
-                Node::Leaf(leaf) => {
-                    for _i in 0..leaf.weight {
-                        result.push(leaf.symb);
-                    }
-                }
-
-                Node::Internal(internal) => {
-                    if let Some(left_node) = internal.left {
-                        get_input_req(*left_node, result);
-                    }
-
-                    if let Some(right_node) = internal.right {
-                        get_input_req(*right_node, result);
-                    }
-                }
-            }
-        }
-
-        get_input_req(self.node.clone(), &mut self.input);
+fn print_benchmark(timings: &PhaseTimings, bytes: usize) {
+    for (name, phase) in [
+        ("counting", Phase::Counting),
+        ("building", Phase::Building),
+        ("encoding", Phase::Encoding),
+    ] {
+        eprintln!(
+            "{:<9} {:>10.3?} {:>10.2} MB/s",
+            name,
+            timings.get(phase),
+            timings.throughput(phase, bytes)
+        );
     }
 }
 
-fn calc_huff(n: Vec<(char, u32)>) -> Node {
-    let mut set = BinaryHeap::new();
+/// With `--min-ratio`, sizes the output with a dry run through `write` and
+/// fails, before anything touches the disk, if it wouldn't shrink the input
+/// to at most that fraction of its size.
+fn check_min_ratio(
+    min_ratio: Option<f64>,
+    input_len: usize,
+    write: impl Fn(&mut dyn Write) -> io::Result<u64>,
+) -> io::Result<()> {
+    let Some(min_ratio) = min_ratio else {
+        return Ok(());
+    };
 
-    for i in n {
-        let new_node = LeafNode {
-            symb: i.0,
+    let size = write(&mut io::sink())?;
+    let stats = CompressionStats::new(input_len as u64, size, 0, Duration::ZERO);
+    if !stats.meets_ratio(min_ratio) {
+        return Err(Error::other(format!(
+            "Compression ratio {:.3} is above --min-ratio {}",
+            stats.ratio, min_ratio
+        )));
+    }
 
-            weight: i.1,
-        };
+    Ok(())
+}
 
-        set.push(Reverse(Node::Leaf(new_node)));
-    }
+/// Decodes input in the encoding named by `label`, returning the text and
+/// the encoding's name to record.
+#[cfg(feature = "encoding")]
+fn decode_input(bytes: &[u8], label: &str) -> io::Result<(String, String)> {
+    let (text, name) = huffman::encoding::decode(bytes, label)?;
+    Ok((text, name.to_owned()))
+}
 
-    while set.len() > 1 {
-        let node0 = set.pop().unwrap();
-        let n0w = node0.0.weight();
-        let node1 = set.pop().unwrap();
-        let n1w = node1.0.weight();
+#[cfg(not(feature = "encoding"))]
+fn decode_input(_: &[u8], _: &str) -> io::Result<(String, String)> {
+    Err(Error::new(
+        io::ErrorKind::Unsupported,
+        "--encoding requires building with the encoding feature",
+    ))
+}
 
-        let new_node = InternalNode {
-            left: Some(Box::new(node0.0)),
-            right: Some(Box::new(node1.0)),
+/// Normalizes input text to the form named by `form`, returning the text and
+/// the form's name to record.
+#[cfg(feature = "normalize")]
+fn normalize_input(text: &str, form: &str) -> io::Result<(String, String)> {
+    let (text, name) = huffman::normalize::normalize(text, form)?;
+    Ok((text, name.to_owned()))
+}
 
-            weight: n0w + n1w,
-        };
+#[cfg(not(feature = "normalize"))]
+fn normalize_input(_: &str, _: &str) -> io::Result<(String, String)> {
+    Err(Error::new(
+        io::ErrorKind::Unsupported,
+        "--normalize requires building with the normalize feature",
+    ))
+}
 
-        set.push(Reverse(Node::Internal(new_node)));
-    }
+/// Encodes decompressed text back to the encoding it was read in.
+#[cfg(feature = "encoding")]
+fn encode_output(text: Vec<u8>, label: &str) -> io::Result<Vec<u8>> {
+    let text = String::from_utf8(text).map_err(|_| huffman::RuffmanError::InvalidUtf8)?;
+    Ok(huffman::encoding::encode(&text, label)?)
+}
 
-    return set.pop().unwrap().0;
+#[cfg(not(feature = "encoding"))]
+fn encode_output(_: Vec<u8>, label: &str) -> io::Result<Vec<u8>> {
+    Err(Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "the original file was {} text; restoring it requires building with the encoding feature",
+            label
+        ),
+    ))
 }
 
-fn calc_freq(input: String) -> Vec<(char, u32)> {
-    let mut freqs: Vec<(char, u32)> = Vec::new();
-
-    for char in input.chars() {
-        if let Some(pos) = freqs
-            .clone()
-            .into_iter()
-            .position(|(c, _v)| c.clone() == char)
-        {
-            freqs[pos].1 += 1;
-        } else {
-            freqs.push((char, 1));
+/// Writes the compressed output to stdout or `output`, or across numbered
+/// parts of it when `split_size` is given, through a buffer of
+/// `buffer_size` bytes.
+fn write_output(
+    output: &Stream,
+    split_size: Option<u64>,
+    buffer_size: usize,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<u64>,
+) -> io::Result<u64> {
+    match (output, split_size) {
+        (Stream::Std, Some(_)) => Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            "--split-size needs an output path",
+        )),
+        (Stream::Std, None) => {
+            let mut stdout = io::stdout().lock();
+            files::buffered(&mut stdout, buffer_size, write)
         }
+        (Stream::File(path), Some(split_size)) => files::write_split(path, split_size, |output| {
+            files::buffered(output, buffer_size, write)
+        }),
+        (Stream::File(path), None) => files::write_atomic(path, None, false, |output| {
+            files::buffered(output, buffer_size, write)
+        }),
     }
-
-    freqs
 }
 
-#[derive(Clone)]
-struct Huffman {
-    input: String,
-    char_codes: HashMap<char, Vec<u8>>,
-    tree: Node,
+/// The shared tree to code against, read from the tree file given with
+/// `--tree` or built in and named with `--preset`, if either was given.
+fn shared_tree(args: &Args) -> io::Result<Option<huffman::Node>> {
+    match (args.value("--tree"), args.value("--preset")) {
+        (Some(_), Some(_)) => Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            "--tree cannot be combined with --preset",
+        )),
+        (Some(tree_path), None) => Ok(Some(trained::read_tree(&fs::read(tree_path)?)?)),
+        (None, Some(name)) => Ok(Some(preset::tree(name)?)),
+        (None, None) => Ok(None),
+    }
 }
 
-impl From<Node> for Huffman {
-    fn from(value: Node) -> Self {
-        Self {
-            tree: value,
-            input: String::new(),
-            char_codes: HashMap::new(),
-        }
+/// Decodes `compressed` against the shared `tree`, whether it holds only the
+/// payload or was written with `--no-tree`.
+fn decompress_shared(
+    compressed: &[u8],
+    tree: &huffman::Node,
+) -> Result<(Vec<u8>, Metadata), huffman::RuffmanError> {
+    // Payload-only files have no magic, --no-tree ones do.
+    if compressed.starts_with(&huffman::MAGIC) {
+        container::decompress_with_tree(compressed, tree)
+    } else {
+        Ok((
+            trained::read_payload(tree, compressed)?.into_bytes(),
+            Metadata::default(),
+        ))
     }
 }
 
-impl Huffman {
-    pub fn from_input(input: String) -> Self {
-        let tree = calc_huff(calc_freq(input.clone()));
+/// Prints the summary of a compress run, and with `--verbose` its notices.
+fn print_compress_summary(args: &Args, output: &Stream, stats: &CompressionStats) {
+    // Keep stdout clean when it carries the compressed data.
+    let summary = match args.flag("--json") {
+        #[cfg(feature = "serde")]
+        true => stats.to_json(),
+        _ => format!("Compressed! {} bytes", stats.output_bytes),
+    };
+    match output {
+        Stream::Std => eprintln!("{}", summary),
+        Stream::File(_) => println!("{}", summary),
+    }
 
-        Self {
-            tree,
-            input,
-            char_codes: HashMap::new(),
+    if args.flag("--verbose") {
+        for notice in stats.notices() {
+            eprintln!("note: {}", notice);
         }
     }
+}
 
-    fn compress(&mut self) {
-        self.huff_compress(Box::new(self.tree.clone()), Vec::new());
+/// Fails early if `--json` was asked for but can't be honoured.
+fn check_json(args: &Args) -> io::Result<()> {
+    if args.flag("--json") && !cfg!(feature = "serde") {
+        return Err(Error::new(
+            io::ErrorKind::Unsupported,
+            "--json requires building with the serde feature",
+        ));
     }
 
-    fn huff_compress(&mut self, node: Box<Node>, code: Vec<u8>) {
-        match *node {
-            Node::Internal(internal) => {
-                if let Some(left_node) = internal.left {
-                    let mut vec = Vec::from(code.clone());
-                    vec.push(0);
-                    self.huff_compress(left_node, vec);
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let mut args = args();
+
+    args.next().unwrap();
+
+    let Some(command) = args.next() else {
+        return Err(Error::new(io::ErrorKind::InvalidInput, "Invalid arg"));
+    };
+
+    match command.as_str() {
+        "compress" => {
+            let args = Args::parse(
+                args,
+                &[
+                    "--benchmark",
+                    "--bytes",
+                    "--compact-tree",
+                    "--gzip-trailer",
+                    "--indent",
+                    "--json",
+                    "--lz",
+                    "--no-crc",
+                    "--no-tree",
+                    "--preserve-first-line",
+                    "--record-eol",
+                    "--refuse-recompress",
+                    "--rle",
+                    "--store-perms",
+                    "--verbose",
+                ],
+                &[
+                    "--align",
+                    "--block-size",
+                    "--buffer-size",
+                    "--encoding",
+                    "--min-ratio",
+                    "--normalize",
+                    "--preset",
+                    "--split-size",
+                    "--stream-threshold",
+                    "--symbols",
+                    "--tree",
+                    "--tree-out",
+                ],
+            )?;
+            let (input, output) = files::resolve_streams(
+                args.positional(0, "Invalid file path")?,
+                args.positional(1, "").ok(),
+            )?;
+            check_json(&args)?;
+
+            let align = args.positive("--align")?;
+            let min_ratio = match args.value("--min-ratio") {
+                Some(value) => Some(value.parse::<f64>().map_err(|_| {
+                    Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid value {} for --min-ratio", value),
+                    )
+                })?),
+                None => None,
+            };
+            let block_size = args.positive("--block-size")?;
+            let split_size = args.positive("--split-size")?;
+            let buffer_size = args
+                .positive("--buffer-size")?
+                .map_or(files::DEFAULT_BUFFER_SIZE, |size| size as usize);
+            let coded_symbols = args.positive("--symbols")?;
+
+            let bytes = args.flag("--bytes");
+            if bytes {
+                // These all work on the input as text.
+                let text_only = [
+                    "--preserve-first-line",
+                    "--record-eol",
+                    "--indent",
+                    "--lz",
+                    "--rle",
+                    "--block-size",
+                    "--encoding",
+                    "--normalize",
+                    "--symbols",
+                ];
+                let conflict = text_only
+                    .into_iter()
+                    .find(|name| args.flag(name) || args.value(name).is_some());
+                if let Some(name) = conflict {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("--bytes cannot be combined with {}", name),
+                    ));
                 }
+            }
+
+            if args.flag("--gzip-trailer") && args.flag("--no-crc") {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--gzip-trailer cannot be combined with --no-crc",
+                ));
+            }
+            if args.flag("--gzip-trailer") && block_size.is_some() {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--gzip-trailer cannot be combined with --block-size",
+                ));
+            }
+            // The trailer covers the decoded text, not the bytes it is
+            // re-encoded to afterwards.
+            if args.flag("--gzip-trailer") && args.value("--encoding").is_some() {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--gzip-trailer cannot be combined with --encoding",
+                ));
+            }
 
-                if let Some(right_node) = internal.right {
-                    let mut vec = Vec::from(code);
-                    vec.push(1);
-                    self.huff_compress(right_node, vec);
+            // The tree goes to its own file, so the output is written as
+            // with --no-tree.
+            let tree_out = args.value("--tree-out");
+            if tree_out.is_some() {
+                let conflict = [
+                    "--no-tree",
+                    "--gzip-trailer",
+                    "--block-size",
+                    "--preset",
+                    "--tree",
+                ]
+                .into_iter()
+                .find(|name| args.flag(name) || args.value(name).is_some());
+                if let Some(name) = conflict {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("--tree-out cannot be combined with {}", name),
+                    ));
                 }
             }
 
-            Node::Leaf(leaf) => {
-                self.char_codes.insert(leaf.symb, code);
+            let no_tree = args.flag("--no-tree");
+            let shared = ["--tree", "--preset"]
+                .into_iter()
+                .find(|name| args.value(name).is_some());
+            if no_tree && shared.is_none() {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--no-tree needs --tree or --preset with the tree to compress against",
+                ));
+            }
+            if no_tree && args.flag("--gzip-trailer") {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--gzip-trailer cannot be combined with --no-tree",
+                ));
             }
-        }
-    }
 
-    fn get_compressed(&self) -> Vec<u8> {
-        let mut result = Vec::new();
+            let trained_tree = match shared {
+                Some(source) => {
+                    // These all shape the tree, which the shared one fixes.
+                    let shapes_tree = [
+                        "--bytes",
+                        "--compact-tree",
+                        "--indent",
+                        "--lz",
+                        "--rle",
+                        "--block-size",
+                        "--symbols",
+                    ];
+                    // Payload-only output has no header to record these in;
+                    // --no-tree output does.
+                    let needs_header = [
+                        "--gzip-trailer",
+                        "--no-crc",
+                        "--preserve-first-line",
+                        "--record-eol",
+                        "--store-perms",
+                        "--align",
+                        "--encoding",
+                        "--normalize",
+                    ];
+                    let conflict = shapes_tree
+                        .into_iter()
+                        .chain(needs_header.into_iter().filter(|_| !no_tree))
+                        .find(|name| args.flag(name) || args.value(name).is_some());
+                    if let Some(name) = conflict {
+                        return Err(Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{} cannot be combined with {}", source, name),
+                        ));
+                    }
 
-        for char in self.input.chars() {
-            result.extend(self.char_codes.get(&char).unwrap());
-        }
+                    shared_tree(&args)?
+                }
+                None => None,
+            };
 
-        result
-    }
+            let mut builder = HuffmanBuilder::new()
+                .indent(args.flag("--indent"))
+                .lz(args.flag("--lz"))
+                .rle(args.flag("--rle"));
+            if let Some(coded_symbols) = coded_symbols {
+                builder = builder.coded_symbols(coded_symbols as usize);
+            }
 
-    fn decompress(&self, compressed: Vec<u8>) -> String {
-        let mut result = String::new();
-        let mut current_node = self.tree.clone();
+            let start = Instant::now();
+
+            // A pipe's length isn't known until it ends, and a huge file may
+            // not fit in memory, so unless an option needs all of it at once,
+            // either is compressed a block at a time as it is read rather
+            // than read in first.
+            let whole_input = [
+                "--benchmark",
+                "--bytes",
+                "--gzip-trailer",
+                "--no-crc",
+                "--preserve-first-line",
+                "--record-eol",
+                "--refuse-recompress",
+                "--store-perms",
+                "--align",
+                "--encoding",
+                "--min-ratio",
+                "--normalize",
+                "--tree-out",
+            ];
+            let stream_threshold = args
+                .positive("--stream-threshold")?
+                .unwrap_or(files::DEFAULT_STREAM_THRESHOLD);
+            let streamed = trained_tree.is_none()
+                && input.should_stream(stream_threshold)?
+                && !whole_input
+                    .into_iter()
+                    .any(|name| args.flag(name) || args.value(name).is_some());
+            if streamed {
+                let builder = builder.canonical(args.flag("--compact-tree"));
+                let block_size =
+                    block_size.map_or(files::DEFAULT_STREAM_BLOCK_SIZE, |size| size as usize);
+                if let Stream::File(path) = &output {
+                    files::clean_stale_temps(files::parent_dir(path))?;
+                }
 
-        for val in compressed {
-            if val == 0 {
-                if let Node::Internal(internal) = current_node.clone() {
-                    if let Some(left) = internal.left {
-                        current_node = *left;
-                    }
+                // Only the magic is needed to spot compressed input, so it is
+                // read ahead and put back in front of the rest.
+                let mut reader = input.reader(buffer_size)?;
+                let mut head = Vec::new();
+                (&mut reader)
+                    .take(container::MAGIC.len() as u64)
+                    .read_to_end(&mut head)?;
+                let warning = container::check_recompress(&head, false)?;
+                if let Some(warning) = warning.filter(|_| args.flag("--verbose")) {
+                    eprintln!("warning: {}", warning);
                 }
+
+                let mut stats = None;
+                write_output(&output, split_size, buffer_size, |mut output| {
+                    let written = container::write_blocks_from_reader(
+                        &mut io::Cursor::new(head).chain(reader),
+                        block_size,
+                        &builder,
+                        &mut output,
+                    )?;
+                    Ok(stats.insert(written).output_bytes)
+                })?;
+
+                print_compress_summary(&args, &output, &stats.unwrap());
+                return Ok(());
+            }
+
+            let raw = input.read_all()?;
+            let input_len = raw.len();
+            let warning = container::check_recompress(&raw, args.flag("--refuse-recompress"))?;
+            if let Some(warning) = warning.filter(|_| args.flag("--verbose")) {
+                eprintln!("warning: {}", warning);
+            }
+
+            // Byte mode never turns the input into a `String`, so it needn't
+            // be UTF-8.
+            let mut metadata = Metadata::default();
+            metadata.no_checksum = args.flag("--no-crc");
+            let (mut buff, raw) = if bytes {
+                (String::new(), raw)
+            } else if let Some(label) = args.value("--encoding") {
+                let (text, name) = decode_input(&raw, label)?;
+                metadata.encoding = Some(name);
+                (text, Vec::new())
             } else {
-                if let Node::Internal(internal) = current_node.clone() {
-                    if let Some(right) = internal.right {
-                        current_node = *right;
-                    }
-                }
+                let text = String::from_utf8(raw)
+                    .map_err(|e| Error::new(io::ErrorKind::InvalidData, e))?;
+                (text, Vec::new())
+            };
+
+            if let Some(form) = args.value("--normalize") {
+                let (text, name) = normalize_input(&buff, form)?;
+                metadata.normalization = Some(name);
+                buff = text;
             }
+            if args.flag("--record-eol") {
+                let (text, ending) = eol::record(&buff);
+                metadata.line_ending = ending;
+                buff = text;
+            }
+
+            let mut timings = PhaseTimings::default();
 
-            if let Node::Leaf(leaf) = current_node.clone() {
-                result.push(leaf.symb);
-                current_node = self.tree.clone();
+            if args.flag("--store-perms") {
+                // Stdin has no permissions of its own to record.
+                if let Stream::File(path) = &input {
+                    metadata.mode = files::file_mode(path)?;
+                }
+            }
+            if args.flag("--preserve-first-line") {
+                let (first_line, rest) = container::split_first_line(&buff);
+                metadata.prefix = Some(first_line.to_owned());
+                buff = rest.to_owned();
             }
-        }
 
-        result
-    }
-}
+            if let Stream::File(path) = &output {
+                files::clean_stale_temps(files::parent_dir(path))?;
+            }
 
-impl From<Huffman> for NodeBytes {
-    fn from(value: Huffman) -> Self {
-        Self::from(value.tree)
-    }
-}
+            let (written_bytes, symbols) = if let Some(block_size) = block_size {
+                // Each block builds its own tree, so there are no separate
+                // phases to time.
+                let builder = builder.canonical(args.flag("--compact-tree"));
+                let write = |mut output: &mut dyn Write| {
+                    container::write_blocks(
+                        &buff,
+                        block_size as usize,
+                        &builder,
+                        &metadata,
+                        align.unwrap_or(1),
+                        &mut output,
+                    )
+                };
+
+                let written_bytes = timings.record(Phase::Encoding, || {
+                    check_min_ratio(min_ratio, input_len, write)?;
+                    write_output(&output, split_size, buffer_size, write)
+                })?;
+
+                (written_bytes, buff.chars().collect::<HashSet<_>>().len())
+            } else {
+                // Now we compress the data
+                let trained = trained_tree.is_some();
+                let mut huffman = match trained_tree {
+                    Some(tree) => Huffman::with_tree(tree, buff)?,
+                    None if bytes => timings.record(Phase::Counting, || Huffman::from_bytes(&raw)),
+                    None if ["--indent", "--lz", "--rle"]
+                        .into_iter()
+                        .any(|name| args.flag(name))
+                        || coded_symbols.is_some() =>
+                    {
+                        timings.record(Phase::Counting, || builder.build(buff))?
+                    }
+                    None => Huffman::from_input_timed(buff, &mut timings),
+                };
 
-fn main() -> io::Result<()> {
-    let mut args = args();
+                timings.record(Phase::Building, || {
+                    if args.flag("--compact-tree") {
+                        huffman.canonicalize();
+                    }
+                    huffman.compress()
+                });
+
+                let bits = timings.record(Phase::Encoding, || huffman.get_compressed());
+
+                let write = |mut output: &mut dyn Write| {
+                    if no_tree || tree_out.is_some() {
+                        container::write_member_without_tree(
+                            &huffman,
+                            &bits,
+                            &metadata,
+                            align.unwrap_or(1),
+                            &mut output,
+                        )
+                    } else if trained {
+                        trained::write_payload(&huffman, &bits, &mut output)
+                    } else if args.flag("--gzip-trailer") {
+                        container::write_member_with_trailer(
+                            &huffman,
+                            &bits,
+                            &metadata,
+                            align.unwrap_or(1),
+                            &mut output,
+                        )
+                    } else {
+                        container::write_member_or_stored(
+                            &huffman,
+                            &bits,
+                            &metadata,
+                            align.unwrap_or(1),
+                            &mut output,
+                        )
+                    }
+                };
 
-    args.next().unwrap();
+                check_min_ratio(min_ratio, input_len, write)?;
+                let written_bytes = write_output(&output, split_size, buffer_size, write)?;
 
-    let Some(command) = args.next() else {
-        return Err(Error::new(io::ErrorKind::InvalidInput, "Invalid arg"));
-    };
+                // Written last, so a failed check or write leaves no tree
+                // file behind without the data it belongs to.
+                if let Some(tree_path) = tree_out {
+                    files::write_atomic(Path::new(tree_path), None, false, |output| {
+                        trained::write_tree(huffman.tree(), output)
+                    })?;
+                }
 
-    match command.as_str() {
-        "compress" => {
-            let Some(file_path) = args.next() else {
-                return Err(Error::new(io::ErrorKind::InvalidInput, "Invalid file path"));
+                (written_bytes, huffman.num_symbols())
             };
 
-            let Some(output_path) = args.next() else {
+            let stats =
+                CompressionStats::new(input_len as u64, written_bytes, symbols, start.elapsed());
+            print_compress_summary(&args, &output, &stats);
+
+            if args.flag("--benchmark") {
+                print_benchmark(&timings, input_len);
+            }
+        }
+
+        "decompress" => {
+            let args = Args::parse(
+                args,
+                &[
+                    "--best-effort",
+                    "--include-bom",
+                    "--json",
+                    "--preserve-perms",
+                    "--stats",
+                    "--strict",
+                ],
+                &["--max-output", "--preset", "--tree"],
+            )?;
+            let file_path = args.positional(0, "Invalid file path")?;
+            let output_path = args.positional(1, "Invalid output file path")?;
+            check_json(&args)?;
+            for name in ["--tree", "--preset"] {
+                if args.flag("--best-effort") && args.value(name).is_some() {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("--best-effort cannot be combined with {}", name),
+                    ));
+                }
+            }
+            let max_output = args.positive("--max-output")?;
+            let unlimited = ["--best-effort", "--preset", "--tree"]
+                .into_iter()
+                .find(|name| args.flag(name) || args.value(name).is_some());
+            if let (Some(_), Some(name)) = (max_output, unlimited) {
                 return Err(Error::new(
                     io::ErrorKind::InvalidInput,
-                    "Invalid output file path",
+                    format!("--max-output cannot be combined with {}", name),
                 ));
-            };
+            }
+            // Strict mode only reads complete, self-contained files.
+            if let (true, Some(name)) = (args.flag("--strict"), unlimited) {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("--strict cannot be combined with {}", name),
+                ));
+            }
 
-            let mut file = File::open(file_path)?;
-            let mut buff = String::new();
-            file.read_to_string(&mut buff)?;
+            let start = Instant::now();
 
-            // Now we compress the data
-            let mut huffman = Huffman::from_input(buff);
+            // Output written with --split-size is found by its first part.
+            let split = files::part_path(Path::new(file_path), 1);
+            let compressed = if !Path::new(file_path).exists() && split.exists() {
+                files::read_split(Path::new(file_path))?
+            } else {
+                let mut compressed = Vec::new();
+                File::open(file_path)?.read_to_end(&mut compressed)?;
+                compressed
+            };
 
-            huffman.compress();
+            if archive::is_archive(&compressed) {
+                let conflict = [
+                    "--best-effort",
+                    "--include-bom",
+                    "--json",
+                    "--max-output",
+                    "--preserve-perms",
+                    "--preset",
+                    "--stats",
+                    "--strict",
+                    "--tree",
+                ]
+                .into_iter()
+                .find(|name| args.flag(name) || args.value(name).is_some());
+                if let Some(name) = conflict {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} cannot be used when extracting an archive", name),
+                    ));
+                }
 
-            let mut bin = NodeBytes::from(huffman.clone());
+                let paths = archive::extract(&compressed, Path::new(output_path))?;
+                println!("Extracted! {} files", paths.len());
+                return Ok(());
+            }
 
-            bin.as_bytes();
+            if args.flag("--strict") {
+                container::check_strict(&compressed)?;
+            }
 
-            let bytes = huffman
-                .get_compressed()
-                .iter()
-                .map(|v| if *v == 1 as u8 { true } else { false })
-                .collect::<Vec<bool>>();
-
-            let mut bv: BitVec = BitVec::from_iter(bytes);
-
-            let mut output = File::create_new(output_path)?;
-
-            let mut written_bytes = 0;
-
-            for byte in bin.bytes {
-                if byte == 1 {
-                    let mut tmp_b: BitVec = BitVec::from_iter([true]);
-                    println!("{:?}", tmp_b);
-                    let written = io::copy(&mut tmp_b, &mut output)?;
-                    written_bytes += written;
-                } else if byte == 0 {
-                    let mut tmp_b: BitVec = BitVec::from_iter([false]);
-                    println!("{:?}", tmp_b);
-                    let written = io::copy(&mut tmp_b, &mut output)?;
-                    written_bytes += written;
-                } else {
-                    let written = output.write(&[byte])?;
-                    written_bytes += written as u64;
+            let mut damage = None;
+            let (mut decompressed, metadata) = match shared_tree(&args)? {
+                Some(tree) => decompress_shared(&compressed, &tree)?,
+                None if args.flag("--best-effort") => {
+                    let recovery = container::recover(&compressed);
+                    damage = recovery.error;
+                    (recovery.data, recovery.metadata)
                 }
+                None => match max_output {
+                    Some(max_output) => container::decompress_limited(&compressed, max_output),
+                    None => container::decompress_with_metadata(&compressed),
+                }
+                .map_err(|e| match e {
+                    huffman::RuffmanError::MissingTree => Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "this file was compressed with --no-tree; pass the tree it was compressed against with --tree or --preset",
+                    ),
+                    e => e.into(),
+                })?,
+            };
+            if args.flag("--include-bom") {
+                container::include_bom(&mut decompressed);
             }
-            output.write(&[0])?;
-            let written = io::copy(&mut bv, &mut output)?;
-            written_bytes += written;
+            let decompressed = match &metadata.encoding {
+                // Partial text is written as decoded, since it may not
+                // survive encoding.
+                Some(label) if damage.is_none() => encode_output(decompressed, label)?,
+                _ => decompressed,
+            };
 
-            output.flush()?;
+            // Salvaged output gets a name that can't be mistaken for the
+            // real thing.
+            let partial_path = format!("{}.partial", output_path);
+            let output_path = match damage {
+                Some(_) => Path::new(&partial_path),
+                None => Path::new(output_path),
+            };
+            files::clean_stale_temps(files::parent_dir(output_path))?;
+            files::write_atomic(
+                output_path,
+                metadata.mode,
+                args.flag("--preserve-perms"),
+                |output| output.write_all(&decompressed),
+            )?;
+
+            let stats = DecompressionStats::new(
+                compressed.len() as u64,
+                decompressed.len() as u64,
+                // Byte-mode output counts one symbol per byte.
+                std::str::from_utf8(&decompressed)
+                    .map_or(decompressed.len(), |text| text.chars().count()),
+                // Payload-only files always carry a checksum; every member
+                // of a container must.
+                damage.is_none()
+                    && (!compressed.starts_with(&huffman::MAGIC)
+                        || container::all_checksummed(&compressed)),
+                start.elapsed(),
+            );
+
+            if args.flag("--json") {
+                #[cfg(feature = "serde")]
+                println!("{}", stats.to_json());
+            } else if args.flag("--stats") {
+                print!("{}", stats);
+            } else {
+                println!("Decompressed! {} bytes", stats.output_bytes);
+            }
 
-            println!("Compressed! {} bytes", written_bytes);
+            if let Some(error) = damage {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "input is damaged ({}); recovered {} bytes into {}",
+                        error,
+                        decompressed.len(),
+                        output_path.display()
+                    ),
+                ));
+            }
         }
 
-        "decompress" => {
-            let Some(file_path) = args.next() else {
-                return Err(Error::new(io::ErrorKind::InvalidInput, "Invalid file path"));
-            };
+        "train" => {
+            let args = Args::parse(args, &[], &[])?;
+            let corpus_path = args.positional(0, "Invalid corpus directory")?;
+            let tree_path = args.positional(1, "Invalid tree file path")?;
+
+            let mut inputs = Vec::new();
+            for entry in fs::read_dir(corpus_path)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    inputs.push(fs::read_to_string(entry.path())?);
+                }
+            }
+            let file_count = inputs.len();
 
-            let Some(output_path) = args.next() else {
+            let Some(tree) = trained::train(inputs) else {
                 return Err(Error::new(
                     io::ErrorKind::InvalidInput,
-                    "Invalid output file path",
+                    "Training corpus is empty",
                 ));
             };
 
-            let mut compressed_file = File::open(file_path)?;
+            let tree_path = Path::new(tree_path);
+            files::clean_stale_temps(files::parent_dir(tree_path))?;
+            files::write_atomic(tree_path, None, false, |output| {
+                trained::write_tree(&tree, output)
+            })?;
+
+            println!("Trained! {} files", file_count);
+        }
 
-            let mut bv: BitVec = BitVec::new();
+        "archive" => {
+            let args = Args::parse(args, &["--verbose"], &[])?;
+            let dir = args.positional(0, "Invalid directory")?;
+            let output_path = Path::new(args.positional(1, "Invalid output file path")?);
 
-            io::copy(&mut compressed_file, &mut bv)?;
+            let entries = archive::entries_from_dir(Path::new(dir))?;
+            files::clean_stale_temps(files::parent_dir(output_path))?;
+            let progress = |i, total, path: &str| {
+                if args.flag("--verbose") {
+                    eprintln!("[{}/{}] compressing {}", i, total, path);
+                }
+            };
+            let written = files::write_atomic(output_path, None, false, |output| {
+                archive::write_with_progress(&entries, progress, output)
+            })?;
 
+            println!("Archived! {} files, {} bytes", entries.len(), written);
+        }
 
-            println!("{:?}", bv);
+        "optimize" => {
+            let args = Args::parse(args, &[], &["--tree", "--preset"])?;
+            let file_path = Path::new(args.positional(0, "Invalid file path")?);
 
+            let bytes = fs::read(file_path)?;
+            let (data, metadata) = match shared_tree(&args)? {
+                Some(tree) => decompress_shared(&bytes, &tree)?,
+                None => container::decompress_with_metadata(&bytes)?,
+            };
+
+            match container::optimize(&data, &metadata, bytes.len())? {
+                Some(optimized) => {
+                    files::replace_atomic(file_path, |output| output.write_all(&optimized))?;
+                    println!("Optimized! {} -> {} bytes", bytes.len(), optimized.len());
+                }
+                None => println!("Left unchanged; no smaller tree found"),
+            }
         }
 
-        c => {
-            return Err(Error::new(
-                io::ErrorKind::Other,
-                format!("Commnad Not Found {}", c),
-            ));
+        "verify" => {
+            let args = Args::parse(args, &[], &[])?;
+            let file_path = args.positional(0, "Invalid file path")?;
+
+            let bytes = fs::read(file_path)?;
+            let reports = container::verify(&bytes);
+            for (i, report) in reports.iter().enumerate() {
+                match &report.result {
+                    Ok(()) if !report.checksum => println!(
+                        "block {} at {}: ok, no checksum available",
+                        i, report.offset
+                    ),
+                    Ok(()) => println!("block {} at {}: ok", i, report.offset),
+                    Err(error) => println!("block {} at {}: {}", i, report.offset, error),
+                }
+            }
+
+            let failed = reports
+                .iter()
+                .filter(|report| report.result.is_err())
+                .count();
+            if failed > 0 {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} of {} blocks failed verification", failed, reports.len()),
+                ));
+            }
         }
-    }
 
-    Ok(())
-}
+        "describe" => {
+            let args = Args::parse(args, &[], &[])?;
+            let file_path = args.positional(0, "Invalid file path")?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+            let bytes = fs::read(file_path)?;
+            for (i, layout) in container::describe(&bytes).iter().enumerate() {
+                println!("member {}:", i);
+                print!("{}", layout);
+            }
+        }
 
-    #[test]
-    fn compress_huff() {
-        let mut h = Huffman::from_input("Hello".to_owned());
-        h.compress();
+        "analyze" => {
+            let args = Args::parse(args, &["--max-len"], &[])?;
+            let file_path = args.positional(0, "Invalid file path")?;
 
-        assert_eq!(h.get_compressed(), vec![0, 0, 0, 1, 1, 1, 1, 1, 1, 0]);
-    }
+            let mut buff = String::new();
+            File::open(file_path)?.read_to_string(&mut buff)?;
+
+            // Only reports; nothing is written. With no flags, every report
+            // is printed.
+            let huffman = Huffman::from_input(buff);
+            let all = args.flags.is_empty();
+            if all || args.flag("--max-len") {
+                match huffman.max_code_length() {
+                    Some(len) => println!("max code length: {} bits", len),
+                    None => println!("max code length: none, the input is empty"),
+                }
+            }
+        }
 
-    #[test]
-    fn decompress_huff() {
-        let h = Huffman::from_input("Hello".to_owned());
+        "export-codes" => {
+            let args = Args::parse(args, &[], &["--format"])?;
+            let file_path = args.positional(0, "Invalid file path")?;
+            let format = args.value("--format").unwrap_or("c-header");
+            if format != "c-header" {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown format {}; the only one is c-header", format),
+                ));
+            }
+
+            let mut buff = String::new();
+            File::open(file_path)?.read_to_string(&mut buff)?;
+
+            print!("{}", Huffman::from_input(buff).tree().to_c_header()?);
+        }
 
-        assert_eq!(h.decompress(vec![0, 0, 0, 1, 1, 1, 1, 1, 1, 0]), "Hello");
+        "tree" => {
+            let args = Args::parse(args, &["--dot", "--length-histogram"], &[])?;
+            let file_path = args.positional(0, "Invalid file path")?;
+
+            let mut buff = String::new();
+            File::open(file_path)?.read_to_string(&mut buff)?;
+
+            let huffman = Huffman::from_input(buff);
+            if args.flag("--length-histogram") {
+                for (len, count) in huffman.tree().length_histogram() {
+                    let noun = if count == 1 { "symbol" } else { "symbols" };
+                    println!("len {}: {} {}", len, count, noun);
+                }
+            } else if args.flag("--dot") {
+                print!("{}", huffman.tree().to_dot());
+            } else {
+                print!("{}", huffman.tree());
+            }
+        }
+
+        "encode-bits" => {
+            let args = Args::parse(args, &[], &[])?;
+            let text = args.positional(0, "Missing text to encode")?;
+
+            let mut huffman = Huffman::from_input(text.to_owned());
+            huffman.compress();
+            println!("{}", huffman.get_compressed_string());
+        }
+
+        "compare" => {
+            let args = Args::parse(args, &[], &[])?;
+            let file_path = args.positional(0, "Invalid file path")?;
+
+            let mut buff = String::new();
+            File::open(file_path)?.read_to_string(&mut buff)?;
+
+            let comparison = compare(&buff);
+            // An empty file has no ratio to speak of.
+            let percent = match comparison.raw_bytes {
+                0 => "n/a".to_owned(),
+                raw => format!(
+                    "{:.1}%",
+                    comparison.compressed_bytes as f64 / raw as f64 * 100.0
+                ),
+            };
+            println!("raw size:        {} bytes", comparison.raw_bytes);
+            println!(
+                "compressed size: {} bytes ({})",
+                comparison.compressed_bytes, percent
+            );
+            println!("entropy bound:   {} bytes", comparison.entropy_bound_bytes);
+            println!(
+                "passthrough:     {}",
+                if comparison.passthrough { "yes" } else { "no" }
+            );
+        }
+
+        c => {
+            return Err(Error::other(format!("Commnad Not Found {}", c)));
+        }
     }
+
+    Ok(())
 }
@@ -0,0 +1,67 @@
+//! Unicode normalization ahead of compression.
+//!
+//! The same text can arrive precomposed or decomposed, which makes
+//! byte-for-byte comparison of the outputs unreliable. Normalizing first
+//! makes equivalent texts compress identically, at the cost of no longer
+//! restoring the original bytes, so it is opt-in and recorded in the
+//! member's metadata.
+
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::RuffmanError;
+
+/// Puts `text` in the normalization form named by `form`, returning the
+/// normalized text and the form's name to record. Only NFC is supported.
+pub fn normalize(text: &str, form: &str) -> Result<(String, &'static str), RuffmanError> {
+    if !form.eq_ignore_ascii_case("nfc") {
+        return Err(RuffmanError::UnknownNormalization(form.to_owned()));
+    }
+
+    let text = match is_nfc(text) {
+        true => text.to_owned(),
+        false => text.nfc().collect(),
+    };
+
+    Ok((text, "NFC"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{container, Huffman};
+
+    fn round_trip(input: &str) -> (String, container::Metadata) {
+        let (text, form) = normalize(input, "nfc").unwrap();
+        let mut huffman = Huffman::from_input(text);
+        huffman.compress();
+        let mut metadata = container::Metadata::default();
+        metadata.normalization = Some(form.to_owned());
+
+        let mut out = Vec::new();
+        container::write_member(&huffman, &huffman.get_compressed(), &metadata, &mut out).unwrap();
+        let (output, metadata) = container::decompress_with_metadata(&out).unwrap();
+        (String::from_utf8(output).unwrap(), metadata)
+    }
+
+    #[test]
+    fn nfc_input_round_trips_exactly() {
+        let input = "café, naïve, Ångström";
+        let (output, metadata) = round_trip(input);
+
+        assert_eq!(output, input);
+        assert_eq!(metadata.normalization.as_deref(), Some("NFC"));
+    }
+
+    #[test]
+    fn decomposed_input_comes_back_composed() {
+        let input = "cafe\u{301}, A\u{30a}ngstro\u{308}m";
+        let (output, _) = round_trip(input);
+
+        assert_ne!(output, input);
+        assert_eq!(output, "café, Ångström");
+        assert!(matches!(
+            normalize(input, "nfkd"),
+            Err(RuffmanError::UnknownNormalization(_))
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+//! Huffman coding of `u32` values, such as an enum-coded column, where
+//! repeated values compress well.
+//!
+//! The codec works on chars, which can't hold every `u32`, so each distinct
+//! value is coded as the symbol of its index in a table of the values, in
+//! ascending order, skipping the surrogate code points. [`write()`] stores an
+//! ordinary text member of those symbols, with the table, 4 bytes per value,
+//! in its metadata; the table is what marks the member as numeric, and
+//! [`read`] maps the decoded symbols back through it.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+};
+
+use crate::{container, container::Metadata, Huffman, RuffmanError};
+
+/// Surrogates aren't chars, so symbols skip over these code points.
+const SURROGATE_START: u32 = 0xD800;
+const SURROGATE_COUNT: u32 = 0x800;
+
+/// The most distinct values one member can hold: one per char.
+pub const MAX_VALUES: usize = char::MAX as usize + 1 - SURROGATE_COUNT as usize;
+
+fn symbol(index: usize) -> char {
+    let index = index as u32;
+    match index < SURROGATE_START {
+        true => char::from_u32(index).unwrap(),
+        false => char::from_u32(index + SURROGATE_COUNT).unwrap(),
+    }
+}
+
+fn index(symb: char) -> usize {
+    let symb = symb as u32;
+    match symb < SURROGATE_START {
+        true => symb as usize,
+        false => (symb - SURROGATE_COUNT) as usize,
+    }
+}
+
+/// Compresses `values` into a single member. Fails with
+/// [`RuffmanError::AlphabetTooLarge`] if there are more than [`MAX_VALUES`]
+/// distinct values. Returns the number of bytes written.
+pub fn write<W: Write>(values: &[u32], out: &mut W) -> io::Result<u64> {
+    let table = values
+        .iter()
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if table.len() > MAX_VALUES {
+        return Err(RuffmanError::AlphabetTooLarge {
+            found: table.len(),
+            max: MAX_VALUES,
+        }
+        .into());
+    }
+
+    let symbols = values
+        .iter()
+        .map(|value| symbol(table.binary_search(value).unwrap()))
+        .collect();
+    let mut huffman = Huffman::from_input(symbols);
+    huffman.compress();
+
+    let mut metadata = Metadata::default();
+    metadata.values = Some(table);
+    container::write_member(&huffman, &huffman.get_compressed(), &metadata, out)
+}
+
+/// Decodes a member written by [`write()`] back into its values. Fails with
+/// [`RuffmanError::MissingValueTable`] on a member of text or bytes.
+pub fn read(bytes: &[u8]) -> Result<Vec<u32>, RuffmanError> {
+    let member = container::read_member(bytes)?;
+    let table = member
+        .metadata
+        .values
+        .ok_or(RuffmanError::MissingValueTable)?;
+
+    // A text member's data is checked to be UTF-8 as it decodes.
+    let symbols = String::from_utf8(member.data).map_err(|_| RuffmanError::InvalidUtf8)?;
+    symbols
+        .chars()
+        .map(|symb| table.get(index(symb)).copied())
+        .collect::<Option<_>>()
+        .ok_or(RuffmanError::CorruptMetadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_values_round_trip() {
+        let column = [7, 7, 7, 0, u32::MAX, 7, 0xD800, 7, 7, 0, 0x10_0000, 7];
+        let values = column.repeat(20);
+
+        let mut bytes = Vec::new();
+        let written = write(&values, &mut bytes).unwrap();
+        assert_eq!(written, bytes.len() as u64);
+        assert!(bytes.len() < values.len());
+        assert_eq!(read(&bytes).unwrap(), values);
+
+        let mut empty = Vec::new();
+        write(&[], &mut empty).unwrap();
+        assert!(read(&empty).unwrap().is_empty());
+
+        let mut text = Vec::new();
+        container::compress("7770".to_owned(), &mut text).unwrap();
+        assert!(matches!(read(&text), Err(RuffmanError::MissingValueTable)));
+    }
+
+    #[test]
+    fn symbols_skip_surrogates() {
+        for i in [0, 0xD7FF, 0xD800, 0xDFFF, MAX_VALUES - 1] {
+            assert_eq!(index(symbol(i)), i);
+        }
+        assert_eq!(symbol(0xD800), '\u{E000}');
+        assert_eq!(symbol(MAX_VALUES - 1), char::MAX);
+    }
+}
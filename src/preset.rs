@@ -0,0 +1,90 @@
+//! Built-in trees for compressing without storing one.
+//!
+//! Even a trained tree file has to be shipped alongside the data. A preset is
+//! compiled in instead, so a tiny file compressed against it is just the
+//! payload written by [`crate::trained::write_payload`]. The tree is fixed by
+//! typical frequencies rather than fitted to the input, so it codes worse
+//! than a tree of the input's own, and input may only use the symbols it
+//! covers: printable ASCII, tab and newline.
+
+use crate::{calc_huff, Node, RuffmanError};
+
+/// Frequencies per 100,000 lowercase letters of English text.
+const LETTERS: [u32; 26] = [
+    8167, 1492, 2782, 4253, 12702, 2228, 2015, 6094, 6966, 153, 772, 4025, 2406, 6749, 7507, 1929,
+    95, 5987, 6327, 9056, 2758, 978, 2360, 150, 1974, 74,
+];
+
+/// Frequencies on the same scale for symbols other than letters that
+/// English text uses often. The rest of printable ASCII gets a weight of 1.
+const OTHERS: [(char, u32); 12] = [
+    (' ', 19000),
+    ('\n', 1500),
+    (',', 1200),
+    ('.', 1100),
+    ('\'', 250),
+    ('"', 200),
+    ('-', 200),
+    ('?', 60),
+    ('!', 40),
+    (';', 30),
+    (':', 30),
+    ('\t', 10),
+];
+
+/// The tree for the preset named `name`, matched case-insensitively. Only
+/// `english` is built in.
+pub fn tree(name: &str) -> Result<Node, RuffmanError> {
+    match name.to_ascii_lowercase().as_str() {
+        "english" => Ok(english()),
+        _ => Err(RuffmanError::UnknownPreset(name.to_owned())),
+    }
+}
+
+/// The tree tuned for English prose: letters weighted by how often they
+/// occur, capitals a thirtieth as often as their lowercase letter, and
+/// digits a little more often than rare punctuation.
+pub fn english() -> Node {
+    let mut freqs = (' '..='~')
+        .chain(['\t', '\n'])
+        .map(|symb| (symb, 1))
+        .collect::<Vec<_>>();
+
+    for (symb, weight) in &mut freqs {
+        *weight = match *symb {
+            'a'..='z' => LETTERS[*symb as usize - 'a' as usize],
+            'A'..='Z' => (LETTERS[*symb as usize - 'A' as usize] / 30).max(1),
+            '0'..='9' => 20,
+            _ => OTHERS
+                .iter()
+                .find(|(other, _)| other == symb)
+                .map_or(1, |(_, weight)| *weight),
+        };
+    }
+
+    calc_huff(freqs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{trained, Huffman};
+
+    #[test]
+    fn english_round_trips_without_tree() {
+        let input = "The quick brown fox jumps over the lazy dog.\n";
+        let mut huffman = Huffman::with_tree(tree("English").unwrap(), input.to_owned()).unwrap();
+        huffman.compress();
+
+        let mut out = Vec::new();
+        trained::write_payload(&huffman, &huffman.get_compressed(), &mut out).unwrap();
+        assert!(out.len() < input.len());
+        assert_eq!(trained::read_payload(&english(), &out).unwrap(), input);
+
+        assert!(english().validate().is_ok());
+        assert!(matches!(
+            tree("klingon"),
+            Err(RuffmanError::UnknownPreset(name)) if name == "klingon"
+        ));
+    }
+}
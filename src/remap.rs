@@ -0,0 +1,150 @@
+//! Structural trees with symbols stored once in a dense table.
+//!
+//! Storing a full u32 per leaf is wasteful when the alphabet is a small
+//! subset of Unicode. This encoding lists the symbols once, sorted, as gaps
+//! between neighbours, and leaves refer to them by index:
+//!
+//! ```text
+//! leaf count  u32
+//! symbols     leaf count LEB128 varints: each symbol minus the one before
+//!             it, the first counted from zero
+//! walk        as the structural tree, except that a leaf's symbol is its
+//!             index in the table: a u8 for up to 256 symbols, a u16 for up
+//!             to 65536 and a u32 beyond that
+//! ```
+
+use crate::{calc_huff, check_weights, varint, Node, RuffmanError};
+
+/// Bytes used for a leaf index into a table of `count` symbols.
+fn index_width(count: usize) -> usize {
+    match count {
+        0..=0x100 => 1,
+        0x101..=0x10000 => 2,
+        _ => 4,
+    }
+}
+
+pub(crate) fn write_tree(tree: &Node) -> Vec<u8> {
+    let mut symbols = tree
+        .preorder()
+        .filter_map(|(node, _, _)| match node {
+            Node::Leaf(leaf) => Some(leaf.symb),
+            Node::Internal(_) => None,
+        })
+        .collect::<Vec<_>>();
+    symbols.sort_unstable();
+
+    let mut bytes = Vec::new();
+    bytes.extend((symbols.len() as u32).to_be_bytes());
+    let mut prev = 0;
+    for symb in &symbols {
+        varint::write((*symb as u32 - prev) as u64, &mut bytes);
+        prev = *symb as u32;
+    }
+
+    let width = index_width(symbols.len());
+    for (node, _, bit) in tree.preorder() {
+        if bit.is_some() {
+            bytes.push(0);
+        }
+        if let Node::Leaf(leaf) = node {
+            let index = symbols.binary_search(&leaf.symb).unwrap() as u32;
+            bytes.push(1);
+            bytes.extend(&index.to_be_bytes()[4 - width..]);
+            bytes.extend(leaf.weight.to_be_bytes());
+        }
+    }
+
+    bytes
+}
+
+/// Parses a tree written by [`write_tree`], rebuilding it from the leaf
+/// weights as the structural tree does.
+pub(crate) fn read_tree(bytes: &[u8]) -> Result<Node, RuffmanError> {
+    let count = bytes.get(..4).ok_or(RuffmanError::Truncated)?;
+    let count = u32::from_be_bytes(count.try_into().unwrap()) as usize;
+    let mut pos = 4;
+
+    let mut symbols = Vec::new();
+    let mut prev = 0u64;
+    for _ in 0..count {
+        prev = prev
+            .checked_add(varint::read(bytes, &mut pos)?)
+            .ok_or(RuffmanError::CorruptTree)?;
+        let value = u32::try_from(prev).map_err(|_| RuffmanError::CorruptTree)?;
+        let symb = char::from_u32(value).ok_or(RuffmanError::InvalidSymbol(value))?;
+        symbols.push(symb);
+    }
+
+    let width = index_width(count);
+    let mut leaves = Vec::new();
+    while let Some(marker) = bytes.get(pos) {
+        pos += 1;
+        match marker {
+            0 => {}
+            1 => {
+                let index = bytes.get(pos..pos + width).ok_or(RuffmanError::Truncated)?;
+                let index = index.iter().fold(0, |acc, b| acc << 8 | *b as usize);
+                let weight = bytes.get(pos + width..pos + width + 4);
+                let weight = weight.ok_or(RuffmanError::Truncated)?;
+                pos += width + 4;
+
+                let symb = *symbols.get(index).ok_or(RuffmanError::CorruptTree)?;
+                leaves.push((symb, u32::from_be_bytes(weight.try_into().unwrap())));
+            }
+            _ => return Err(RuffmanError::CorruptTree),
+        }
+    }
+
+    if leaves.len() != count {
+        return Err(RuffmanError::TreeSymbolMismatch {
+            expected: count,
+            found: leaves.len(),
+        });
+    }
+    check_weights(&leaves)?;
+
+    Ok(calc_huff(leaves))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Huffman;
+
+    #[test]
+    fn round_trip_small_and_wide_alphabets() {
+        let wide = (0..300u32)
+            .map(|i| char::from_u32(0x4E00 + i * 3).unwrap())
+            .collect::<String>();
+
+        for input in ["", "a", "abracadabra", wide.as_str()] {
+            let tree = Huffman::from_input(input.to_owned()).tree;
+            assert_eq!(read_tree(&write_tree(&tree)).unwrap(), tree);
+        }
+    }
+
+    #[test]
+    fn rejects_weights_past_u32() {
+        let mut tree = Huffman::from_input("éè".to_owned()).tree;
+        if let Node::Internal(internal) = &mut tree {
+            for child in [&mut internal.left, &mut internal.right] {
+                if let Some(Node::Leaf(leaf)) = child.as_deref_mut() {
+                    leaf.weight = u32::MAX;
+                }
+            }
+        }
+
+        let bytes = write_tree(&tree);
+        assert!(matches!(read_tree(&bytes), Err(RuffmanError::CorruptTree)));
+    }
+
+    #[test]
+    fn rejects_gap_past_u64() {
+        let mut bytes = 2u32.to_be_bytes().to_vec();
+        varint::write(0x61, &mut bytes);
+        varint::write(u64::MAX, &mut bytes);
+
+        assert!(matches!(read_tree(&bytes), Err(RuffmanError::CorruptTree)));
+    }
+}
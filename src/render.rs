@@ -0,0 +1,168 @@
+//! Human-readable renderings of a tree.
+
+use std::fmt::{self, Write};
+
+use crate::{canonical, Node, RuffmanError};
+
+fn label(node: &Node) -> String {
+    match node {
+        Node::Leaf(leaf) => format!("{:?} {}", leaf.symb, leaf.weight),
+        Node::Internal(internal) => internal.weight.to_string(),
+    }
+}
+
+/// One node per line, indented by depth and prefixed with its edge bit.
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (node, depth, bit) in self.preorder() {
+            let edge = bit.map_or(String::new(), |bit| format!("{}: ", bit));
+            writeln!(
+                f,
+                "{:indent$}{}{}",
+                "",
+                edge,
+                label(node),
+                indent = depth * 2
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Node {
+    /// Renders the tree as a Graphviz digraph. Leaves are boxes labelled
+    /// with their symbol and weight, internal nodes show their weight, and
+    /// edges are labelled with their bit.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph huffman {\n");
+        let mut parents: Vec<usize> = Vec::new();
+
+        for (id, (node, depth, bit)) in self.preorder().enumerate() {
+            let label = label(node).replace('\\', "\\\\").replace('"', "\\\"");
+            let shape = match node {
+                Node::Leaf(_) => ", shape=box",
+                Node::Internal(_) => "",
+            };
+            writeln!(dot, "    n{} [label=\"{}\"{}];", id, label, shape).unwrap();
+
+            parents.truncate(depth);
+            if let (Some(parent), Some(bit)) = (parents.last(), bit) {
+                writeln!(dot, "    n{} -> n{} [label=\"{}\"];", parent, id, bit).unwrap();
+            }
+            parents.push(id);
+        }
+
+        dot.push_str("}\n");
+
+        dot
+    }
+
+    /// Renders the tree's codes as a C header for decoders elsewhere, such as
+    /// in firmware: a `const` array of symbol, code and length triples. The
+    /// codes are canonical, ordered by length then symbol, so they match a
+    /// file compressed with the tree canonicalized. Each code is written as
+    /// an integer whose `length` low bits are the code, first bit highest.
+    /// An empty tree gives an empty array, which C only accepts from C23.
+    /// Fails with [`RuffmanError::CodeTooLong`] on a code too long for the
+    /// `uint64_t` it is written as.
+    pub fn to_c_header(&self) -> Result<String, RuffmanError> {
+        let mut lengths = canonical::leaf_lengths(self);
+        // A lone leaf is coded with one bit, as `compress` codes it.
+        for (_, _, len) in &mut lengths {
+            *len = (*len).max(1);
+        }
+        let length = lengths.iter().map(|(_, _, len)| *len).max().unwrap_or(0);
+        if length > 64 {
+            return Err(RuffmanError::CodeTooLong { length, max: 64 });
+        }
+
+        let mut header = String::from(
+            "/* Canonical Huffman codes generated by ruffman. */\n\
+             #ifndef RUFFMAN_CODES_H\n\
+             #define RUFFMAN_CODES_H\n\
+             \n\
+             #include <stdint.h>\n\
+             \n\
+             struct ruffman_code {\n    \
+             uint32_t symbol;\n    \
+             uint64_t code;\n    \
+             uint8_t length;\n\
+             };\n\
+             \n\
+             static const struct ruffman_code RUFFMAN_CODES[] = {\n",
+        );
+
+        let codes = canonical::canonical_codes(lengths);
+        for (symb, _, code) in &codes {
+            let value = code
+                .iter()
+                .fold(0u64, |value, bit| value << 1 | *bit as u64);
+            writeln!(
+                header,
+                "    {{{:#x}, {:#x}, {}}}, /* {:?} */",
+                *symb as u32,
+                value,
+                code.len(),
+                symb
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            header,
+            "}};\n\n#define RUFFMAN_CODE_COUNT {}\n",
+            codes.len()
+        )
+        .unwrap();
+        header.push_str("#endif\n");
+
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Huffman;
+
+    #[test]
+    fn dot_for_two_symbols() {
+        let h = Huffman::from_input("abb".to_owned());
+        let dot = h.tree.to_dot();
+
+        assert!(dot.starts_with("digraph huffman {\n"));
+        assert!(dot.contains("n0 [label=\"3\"];"));
+        assert!(dot.contains("n1 [label=\"'a' 1\", shape=box];"));
+        assert!(dot.contains("n2 [label=\"'b' 2\", shape=box];"));
+        assert!(dot.contains("n0 -> n1 [label=\"0\"];"));
+        assert!(dot.contains("n0 -> n2 [label=\"1\"];"));
+    }
+
+    #[test]
+    fn c_header_for_small_tree() {
+        let h = Huffman::from_input("aabbbc".to_owned());
+        let header = h.tree.to_c_header().unwrap();
+
+        assert!(header.contains("#include <stdint.h>\n"));
+        assert!(header.contains("static const struct ruffman_code RUFFMAN_CODES[] = {\n"));
+        assert!(header.contains(
+            "    {0x62, 0x0, 1}, /* 'b' */\n    {0x61, 0x2, 2}, /* 'a' */\n    {0x63, 0x3, 2}, /* 'c' */\n};"
+        ));
+        assert!(header.contains("#define RUFFMAN_CODE_COUNT 3\n"));
+        assert!(header.ends_with("#endif\n"));
+    }
+
+    #[test]
+    fn display_indents_by_depth() {
+        let h = Huffman::from_input("abb".to_owned());
+
+        assert_eq!(h.tree.to_string(), "3\n  0: 'a' 1\n  1: 'b' 2\n");
+    }
+
+    #[test]
+    fn dot_escapes_quotes() {
+        let h = Huffman::from_input("\"\"x".to_owned());
+
+        assert!(h.tree.to_dot().contains("label=\"'\\\"' 2\""));
+    }
+}
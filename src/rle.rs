@@ -0,0 +1,126 @@
+//! Run-length pre-pass applied before Huffman coding.
+//!
+//! A run of four or more of the same symbol becomes the symbol followed by
+//! [`ESCAPE`] and a count char whose code point is the number of further
+//! repeats. Counts never reach the surrogate range, so they can't be mistaken
+//! for `ESCAPE`, and a literal `ESCAPE` in the input is doubled.
+
+use crate::RuffmanError;
+
+/// Marks a run count, or a literal escape when doubled. A private-use
+/// code point, so it rarely costs anything in real text.
+pub(crate) const ESCAPE: char = '\u{E000}';
+
+/// Runs shorter than this are cheaper left as they are.
+const MIN_RUN: usize = 4;
+
+/// Largest count a single run token can hold; longer runs chain tokens.
+const MAX_COUNT: usize = 0xD7FF;
+
+pub(crate) fn encode(input: &str) -> String {
+    let chars = input.chars().collect::<Vec<_>>();
+    let mut output = String::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let symb = chars[i];
+        let run = chars[i..].iter().take_while(|c| **c == symb).count();
+
+        if symb == ESCAPE {
+            for _ in 0..run {
+                output.push(ESCAPE);
+                output.push(ESCAPE);
+            }
+        } else if run >= MIN_RUN {
+            output.push(symb);
+            let mut repeats = run - 1;
+            while repeats > 0 {
+                let count = repeats.min(MAX_COUNT);
+                output.push(ESCAPE);
+                output.push(char::from_u32(count as u32).unwrap());
+                repeats -= count;
+            }
+        } else {
+            output.extend(std::iter::repeat_n(symb, run));
+        }
+
+        i += run;
+    }
+
+    output
+}
+
+/// Expands the runs in text produced by [`encode`].
+pub(crate) fn decode(input: &str) -> Result<String, RuffmanError> {
+    decode_limited(input, usize::MAX)
+}
+
+/// Like [`decode`], failing with [`RuffmanError::OutputTooLarge`] as soon as
+/// the output grows past `limit` bytes. A run token is a couple of symbols
+/// but can stand for tens of thousands, so this is checked as runs expand.
+pub(crate) fn decode_limited(input: &str, limit: usize) -> Result<String, RuffmanError> {
+    let mut output = String::with_capacity(input.len().min(limit));
+    let mut chars = input.chars();
+    let mut last = None;
+
+    while let Some(symb) = chars.next() {
+        if output.len() > limit {
+            return Err(RuffmanError::OutputTooLarge);
+        }
+
+        if symb != ESCAPE {
+            output.push(symb);
+            last = Some(symb);
+            continue;
+        }
+
+        match chars.next() {
+            Some(ESCAPE) => {
+                output.push(ESCAPE);
+                last = None;
+            }
+            Some(count) if count as usize <= MAX_COUNT => {
+                let symb = last.ok_or(RuffmanError::CorruptRle)?;
+                output.extend(std::iter::repeat_n(symb, count as usize));
+            }
+            _ => return Err(RuffmanError::CorruptRle),
+        }
+    }
+
+    if output.len() > limit {
+        return Err(RuffmanError::OutputTooLarge);
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_runs_and_escapes() {
+        let long_run = "z".repeat(MAX_COUNT * 2 + 10);
+        let inputs = [
+            String::new(),
+            "abc".to_owned(),
+            "aaaabbbbbbbbcdddd".to_owned(),
+            format!("{0}{0}{0}{0}{0}x{0}", ESCAPE),
+            format!("a{}\u{5}aaaaa", ESCAPE),
+            long_run,
+        ];
+
+        for input in inputs {
+            assert_eq!(decode(&encode(&input)).unwrap(), input);
+        }
+
+        assert_eq!(encode("aaaaaa").chars().count(), 3);
+    }
+
+    #[test]
+    fn rejects_run_without_symbol() {
+        let input = format!("{}\u{5}", ESCAPE);
+
+        assert!(matches!(decode(&input), Err(RuffmanError::CorruptRle)));
+    }
+}
@@ -0,0 +1,276 @@
+use std::{fmt, io, time::Duration};
+
+use crate::{calc_freq, container, container::Metadata, entropy, Huffman};
+
+/// Summary of a compress run, printed by the CLI.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompressionStats {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// `output_bytes / input_bytes`; below 1.0 means the file shrank.
+    pub ratio: f64,
+    /// Number of distinct symbols in the input.
+    pub symbols: usize,
+    pub duration_ms: f64,
+}
+
+impl CompressionStats {
+    pub fn new(input_bytes: u64, output_bytes: u64, symbols: usize, duration: Duration) -> Self {
+        Self {
+            input_bytes,
+            output_bytes,
+            ratio: ratio(output_bytes, input_bytes),
+            symbols,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+        }
+    }
+
+    /// Whether the output is at most `min_ratio` of the input's size, so a
+    /// ratio of 0.9 asks for at least a 10% saving.
+    pub fn meets_ratio(&self, min_ratio: f64) -> bool {
+        self.ratio <= min_ratio
+    }
+
+    /// Explanations for surprising results, printed by the CLI with
+    /// `--verbose`.
+    pub fn notices(&self) -> Vec<&'static str> {
+        let mut notices = Vec::new();
+        if self.symbols == 1 {
+            notices.push("input has 1 distinct symbol; output is near-constant size");
+        }
+
+        notices
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Summary of a decompress run, printed by the CLI.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecompressionStats {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    /// `input_bytes / output_bytes`, the ratio the file was compressed at.
+    pub ratio: f64,
+    /// Number of symbols decoded.
+    pub symbols: usize,
+    /// Whether the output was checked against a stored CRC-32, which files
+    /// written with [`Metadata::no_checksum`] and damaged ones can't be.
+    pub checksum_verified: bool,
+    pub duration_ms: f64,
+}
+
+impl DecompressionStats {
+    pub fn new(
+        input_bytes: u64,
+        output_bytes: u64,
+        symbols: usize,
+        checksum_verified: bool,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            input_bytes,
+            output_bytes,
+            ratio: ratio(input_bytes, output_bytes),
+            symbols,
+            checksum_verified,
+            duration_ms: duration.as_secs_f64() * 1000.0,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// The summary printed by the CLI with `--stats`, one field per line.
+impl fmt::Display for DecompressionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "compressed:   {} bytes", self.input_bytes)?;
+        writeln!(f, "decompressed: {} bytes", self.output_bytes)?;
+        writeln!(f, "symbols:      {}", self.symbols)?;
+        let checksum = match self.checksum_verified {
+            true => "verified",
+            false => "not verified",
+        };
+        writeln!(f, "checksum:     {}", checksum)
+    }
+}
+
+/// How an input would fare under compression, computed without writing
+/// anything.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub raw_bytes: u64,
+    /// Size of the compressed file, tree and header included.
+    pub compressed_bytes: u64,
+    /// Entropy lower bound on the payload alone.
+    pub entropy_bound_bytes: u64,
+    /// Whether storing the input raw would beat compressing it.
+    pub passthrough: bool,
+}
+
+pub fn compare(input: &str) -> Comparison {
+    let freqs = calc_freq(input.to_owned());
+    let symbols: u64 = freqs.iter().map(|(_, count)| *count as u64).sum();
+    let bound_bits = (entropy(&freqs) * symbols as f64).ceil() as u64;
+
+    let mut huffman = Huffman::from_input(input.to_owned());
+    huffman.compress();
+    let compressed_bytes = container::write_member(
+        &huffman,
+        &huffman.get_compressed(),
+        &Metadata::default(),
+        &mut io::sink(),
+    )
+    .unwrap();
+
+    let raw_bytes = input.len() as u64;
+    Comparison {
+        raw_bytes,
+        compressed_bytes,
+        entropy_bound_bytes: bound_bits.div_ceil(8),
+        passthrough: huffman.would_expand(input),
+    }
+}
+
+fn ratio(compressed: u64, original: u64) -> f64 {
+    if original == 0 {
+        return 0.0;
+    }
+
+    compressed as f64 / original as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratio_of_sizes() {
+        let stats = CompressionStats::new(200, 50, 4, Duration::from_millis(3));
+
+        assert_eq!(stats.ratio, 0.25);
+        assert_eq!(stats.duration_ms, 3.0);
+    }
+
+    #[test]
+    fn decompress_stats_list_sizes_and_checksum() {
+        let input = "the rain in spain stays mainly in the plain";
+        let mut bytes = Vec::new();
+        container::compress(input.to_owned(), &mut bytes).unwrap();
+        let output = container::decompress(&bytes).unwrap();
+
+        let stats = DecompressionStats::new(
+            bytes.len() as u64,
+            output.len() as u64,
+            input.chars().count(),
+            container::all_checksummed(&bytes),
+            Duration::ZERO,
+        );
+        assert_eq!(
+            stats.to_string(),
+            format!(
+                "compressed:   {} bytes\ndecompressed: 43 bytes\nsymbols:      43\nchecksum:     verified\n",
+                bytes.len()
+            )
+        );
+    }
+
+    #[test]
+    fn notices_single_symbol_input() {
+        let stats = |input: &str| {
+            let huffman = Huffman::from_input(input.to_owned());
+            CompressionStats::new(input.len() as u64, 0, huffman.num_symbols(), Duration::ZERO)
+        };
+
+        assert_eq!(
+            stats("aaaa").notices(),
+            ["input has 1 distinct symbol; output is near-constant size"]
+        );
+        assert!(stats("abab").notices().is_empty());
+    }
+
+    #[test]
+    fn random_data_fails_ratio_gate() {
+        // Uniform printable ASCII: about 6.6 bits per byte before the tree.
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let input = (0..4000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                char::from(0x20 + (state % 95) as u8)
+            })
+            .collect::<String>();
+
+        let comparison = compare(&input);
+        let stats = CompressionStats::new(
+            comparison.raw_bytes,
+            comparison.compressed_bytes,
+            95,
+            Duration::ZERO,
+        );
+        assert!(!stats.meets_ratio(0.9));
+
+        let text = "the rain in spain stays mainly in the plain ".repeat(20);
+        let comparison = compare(&text);
+        let stats = CompressionStats::new(
+            comparison.raw_bytes,
+            comparison.compressed_bytes,
+            0,
+            Duration::ZERO,
+        );
+        assert!(stats.meets_ratio(0.9));
+    }
+
+    #[test]
+    fn compare_reports_raw_size() {
+        let input = "the rain in spain stays mainly in the plain ".repeat(20);
+        let comparison = compare(&input);
+
+        assert_eq!(comparison.raw_bytes, input.len() as u64);
+        assert!(comparison.entropy_bound_bytes < comparison.compressed_bytes);
+        assert!(!comparison.passthrough);
+        assert!(compare("abcdefgh").passthrough);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_for_known_file() {
+        use crate::{container, container::Metadata, Huffman};
+
+        let input = "abracadabra";
+        let mut huffman = Huffman::from_input(input.to_owned());
+        huffman.compress();
+        let mut out = Vec::new();
+        let written = container::write_member(
+            &huffman,
+            &huffman.get_compressed(),
+            &Metadata::default(),
+            &mut out,
+        )
+        .unwrap();
+
+        let stats = CompressionStats::new(
+            input.len() as u64,
+            written,
+            huffman.num_symbols(),
+            Duration::ZERO,
+        );
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+
+        assert_eq!(json["input_bytes"], 11);
+        assert_eq!(json["output_bytes"], out.len());
+        assert_eq!(json["symbols"], 5);
+        // serde_json's default float parsing isn't exact in the last bit.
+        let ratio = json["ratio"].as_f64().unwrap();
+        assert!((ratio - out.len() as f64 / 11.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,17 @@
+//! Helpers shared by the unit tests.
+
+use crate::container;
+
+/// Compresses `input` into a member, decodes it again and checks that both
+/// the text and the symbol count recorded in the header survive.
+pub(crate) fn assert_roundtrip(input: &str) {
+    let mut bytes = Vec::new();
+    container::compress(input.to_owned(), &mut bytes).unwrap();
+
+    let member = container::read_member(&bytes).unwrap();
+    assert_eq!(member.data, input.as_bytes());
+    assert_eq!(member.len, bytes.len());
+
+    let layout = &container::describe(&bytes)[0];
+    assert_eq!(layout.symbol_count, Some(input.chars().count() as u64));
+}
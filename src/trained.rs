@@ -0,0 +1,164 @@
+//! Compressing against a tree trained ahead of time.
+//!
+//! Many small, similar files compress better against one shared tree than if
+//! each carries its own. A tree file is:
+//!
+//! ```text
+//! magic    4 bytes  "RFTR"
+//! version  u8
+//! tree     structural tree, as stored in a container member
+//! ```
+//!
+//! and a file compressed against it holds only:
+//!
+//! ```text
+//! bit count  u64   payload length in bits
+//! crc        u32   CRC-32 of the decoded text's UTF-8 bytes
+//! payload    ceil(bit count / 8) bytes, MSB first
+//! ```
+//!
+//! All integers are big-endian.
+
+use std::io::{self, Write};
+
+use bitvec::prelude::*;
+
+use crate::{
+    bits::BitWriter, calc_freq, calc_huff, crc::crc32, decode::DecodeTable, merge_freqs,
+    structural_bytes, Huffman, Node, NodeBytes, RuffmanError,
+};
+
+const MAGIC: [u8; 4] = *b"RFTR";
+const VERSION: u8 = 1;
+
+/// Size of the bit count and CRC ahead of a payload.
+const PAYLOAD_HEADER_LEN: usize = 8 + 4;
+
+/// Builds one tree over the combined symbol frequencies of `inputs`, or
+/// `None` if they contain no symbols at all.
+pub fn train<I: IntoIterator<Item = String>>(inputs: I) -> Option<Node> {
+    let freqs = inputs.into_iter().fold(Vec::new(), |freqs, input| {
+        merge_freqs(freqs, calc_freq(input))
+    });
+    if freqs.is_empty() {
+        return None;
+    }
+
+    Some(calc_huff(freqs))
+}
+
+/// Writes `tree` as a tree file.
+pub fn write_tree<W: Write>(tree: &Node, out: &mut W) -> io::Result<()> {
+    out.write_all(&MAGIC)?;
+    out.write_all(&[VERSION])?;
+    out.write_all(&structural_bytes(tree))
+}
+
+/// Parses a tree file written by [`write_tree`].
+pub fn read_tree(bytes: &[u8]) -> Result<Node, RuffmanError> {
+    if bytes.len() < MAGIC.len() + 1 {
+        return Err(RuffmanError::Truncated);
+    }
+
+    if bytes[0..4] != MAGIC {
+        return Err(RuffmanError::InvalidMagic);
+    }
+
+    if bytes[4] != VERSION {
+        return Err(RuffmanError::UnsupportedVersion(bytes[4]));
+    }
+
+    let tree = NodeBytes::try_from(bytes[5..].to_vec())?.node;
+    tree.validate()?;
+
+    Ok(tree)
+}
+
+/// A CRC-32 identifying `tree`, recorded in members written without it so
+/// that decoding against a different tree fails rather than producing
+/// garbage. It survives a round trip through [`write_tree`].
+pub fn tree_hash(tree: &Node) -> u32 {
+    crc32(&structural_bytes(tree))
+}
+
+/// Writes `bits`, the encoding of `huffman`'s input, with no tree. Returns
+/// the number of bytes written.
+pub fn write_payload<W: Write>(huffman: &Huffman, bits: &[u8], out: &mut W) -> io::Result<u64> {
+    out.write_all(&(bits.len() as u64).to_be_bytes())?;
+    out.write_all(&crc32(huffman.input.as_bytes()).to_be_bytes())?;
+
+    let mut payload = BitWriter::new(out);
+    payload.write_bits(bits)?;
+    let payload_len = payload.finish()?;
+
+    Ok(PAYLOAD_HEADER_LEN as u64 + payload_len)
+}
+
+/// Decodes a payload written by [`write_payload`] against the same `tree`.
+pub fn read_payload(tree: &Node, bytes: &[u8]) -> Result<String, RuffmanError> {
+    if bytes.len() < PAYLOAD_HEADER_LEN {
+        return Err(RuffmanError::Truncated);
+    }
+
+    let bit_count = u64::from_be_bytes(bytes[0..8].try_into().unwrap()) as usize;
+    let crc = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
+
+    let payload = &bytes[PAYLOAD_HEADER_LEN..];
+    if payload.len() < bit_count.div_ceil(8) {
+        return Err(RuffmanError::Truncated);
+    }
+
+    let output = DecodeTable::new(tree).decode(&payload.view_bits::<Msb0>()[..bit_count]);
+
+    let found = crc32(output.as_bytes());
+    if found != crc {
+        return Err(RuffmanError::ChecksumMismatch {
+            expected: crc,
+            found,
+        });
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn train_compress_decompress() {
+        let corpus = ["the cat sat on the mat", "a hat on a cat", "that rat"];
+        let tree = train(corpus.map(str::to_owned)).unwrap();
+
+        let mut tree_file = Vec::new();
+        write_tree(&tree, &mut tree_file).unwrap();
+        let tree = read_tree(&tree_file).unwrap();
+
+        let input = "the rat sat on a hat";
+        let mut huffman = Huffman::with_tree(tree.clone(), input.to_owned()).unwrap();
+        huffman.compress();
+
+        let mut out = Vec::new();
+        let written = write_payload(&huffman, &huffman.get_compressed(), &mut out).unwrap();
+
+        assert_eq!(written, out.len() as u64);
+        assert_eq!(read_payload(&tree, &out).unwrap(), input);
+
+        assert!(matches!(
+            Huffman::with_tree(tree, "the dog".to_owned()),
+            Err(RuffmanError::UnknownSymbol('d'))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_tree_versions() {
+        let mut tree_file = Vec::new();
+        write_tree(&train(["abc".to_owned()]).unwrap(), &mut tree_file).unwrap();
+        tree_file[4] = 2;
+
+        assert!(matches!(
+            read_tree(&tree_file),
+            Err(RuffmanError::UnsupportedVersion(2))
+        ));
+    }
+}
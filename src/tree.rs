@@ -0,0 +1,104 @@
+//! The bare tree, for sharing a code with another program.
+
+use crate::{structural_bytes, Node, NodeBytes, RuffmanError};
+
+/// A code tree on its own, without an input or payload.
+///
+/// [`Tree::to_bytes`] writes the same structural tree a container member
+/// holds, with no magic, version or payload around it:
+///
+/// ```text
+/// leaf count  u32
+/// nodes       pre-order; each edge below the root is a 0 byte, and each
+///             leaf is a 1 byte, then its symbol and weight as u32s
+/// ```
+///
+/// All integers are big-endian. Only the leaves' symbols and weights are
+/// read back: [`Tree::from_bytes`] rebuilds the tree from them the way
+/// [`crate::Huffman`] builds one from its input, so both sides end up with
+/// the same codes. This layout is stable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tree {
+    root: Node,
+}
+
+impl Tree {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        structural_bytes(&self.root)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RuffmanError> {
+        let root = NodeBytes::try_from(bytes.to_vec())?.node;
+        root.validate()?;
+
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    pub fn into_root(self) -> Node {
+        self.root
+    }
+}
+
+impl From<Node> for Tree {
+    fn from(root: Node) -> Self {
+        Self { root }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{container, container::Metadata, Huffman};
+
+    #[test]
+    fn tree_bytes_round_trip() {
+        for input in ["", "aaa", "Hello", "abracadabra, alakazam! ☃"] {
+            let tree = Tree::from(Huffman::from_input(input.to_owned()).tree().clone());
+            let bytes = tree.to_bytes();
+
+            assert_eq!(Tree::from_bytes(&bytes).unwrap(), tree);
+            assert!(matches!(
+                Tree::from_bytes(&bytes[..bytes.len() - 1]),
+                Err(RuffmanError::Truncated)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_weights_past_u32() {
+        let mut bytes = 2u32.to_be_bytes().to_vec();
+        for symb in ['a', 'b'] {
+            bytes.extend([0, 1]);
+            bytes.extend((symb as u32).to_be_bytes());
+            bytes.extend(u32::MAX.to_be_bytes());
+        }
+
+        assert!(matches!(
+            Tree::from_bytes(&bytes),
+            Err(RuffmanError::CorruptTree)
+        ));
+    }
+
+    #[test]
+    fn imported_tree_decodes_member() {
+        let mut huffman = Huffman::from_input("the rain in spain".to_owned());
+        huffman.compress();
+        let mut bytes = Vec::new();
+        container::write_member_without_tree(
+            &huffman,
+            &huffman.get_compressed(),
+            &Metadata::default(),
+            1,
+            &mut bytes,
+        )
+        .unwrap();
+
+        let tree = Tree::from_bytes(&Tree::from(huffman.tree().clone()).to_bytes()).unwrap();
+        let (text, _) = container::decompress_with_tree(&bytes, tree.root()).unwrap();
+        assert_eq!(text, b"the rain in spain");
+    }
+}
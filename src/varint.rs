@@ -0,0 +1,34 @@
+//! LEB128 variable-length integers: seven bits per byte, least significant
+//! group first, with the high bit set on every byte but the last.
+
+use crate::RuffmanError;
+
+pub(crate) fn write(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads a varint at `*pos`, advancing past it.
+pub(crate) fn read(bytes: &[u8], pos: &mut usize) -> Result<u64, RuffmanError> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or(RuffmanError::Truncated)?;
+        *pos += 1;
+
+        let group = (byte & 0x7F) as u64;
+        if shift == 63 && group > 1 {
+            return Err(RuffmanError::CorruptVarint);
+        }
+        value |= group << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(RuffmanError::CorruptVarint)
+}
@@ -0,0 +1,134 @@
+//! Runs the `huffman` binary on scratch files, for behaviour that lives in
+//! the CLI rather than the library.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ruffman-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_huffman"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn failed_compress_leaves_no_tree_file() {
+    let dir = test_dir("tree-out");
+    fs::write(dir.join("in.txt"), "abcdefghij".repeat(20)).unwrap();
+
+    // The ratio gate fails, and so does writing into a missing directory.
+    for args in [
+        &["in.txt", "out.huf", "--min-ratio", "0.01"][..],
+        &["in.txt", "missing/out.huf"],
+    ] {
+        let output = run(
+            &dir,
+            &[&["compress"], args, &["--tree-out", "tree.bin"]].concat(),
+        );
+
+        assert!(!output.status.success());
+        assert!(!dir.join("out.huf").exists());
+        assert!(!dir.join("tree.bin").exists());
+    }
+
+    let output = run(
+        &dir,
+        &["compress", "in.txt", "out.huf", "--tree-out", "tree.bin"],
+    );
+    assert!(output.status.success());
+    assert!(dir.join("tree.bin").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn decompress_stats_describe_the_written_file() {
+    let dir = test_dir("stats");
+    fs::write(dir.join("a.txt"), "the rain in spain ".repeat(10)).unwrap();
+    fs::write(dir.join("b.txt"), "stays mainly in the plain".repeat(10)).unwrap();
+    assert!(run(&dir, &["compress", "a.txt", "a.huf"]).status.success());
+    assert!(run(&dir, &["compress", "b.txt", "b.huf", "--no-crc"])
+        .status
+        .success());
+
+    // Only the second member of the concatenation lacks a checksum.
+    for (inputs, checksum) in [
+        (&["a.huf"][..], "verified"),
+        (&["a.huf", "b.huf"], "not verified"),
+    ] {
+        let compressed = inputs
+            .iter()
+            .flat_map(|name| fs::read(dir.join(name)).unwrap())
+            .collect::<Vec<_>>();
+        fs::write(dir.join("in.huf"), &compressed).unwrap();
+        let _ = fs::remove_file(dir.join("out.txt"));
+
+        let output = run(&dir, &["decompress", "in.huf", "out.txt", "--stats"]);
+        assert!(output.status.success());
+        let written = fs::metadata(dir.join("out.txt")).unwrap().len();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains(&format!("compressed:   {} bytes\n", compressed.len())));
+        assert!(stdout.contains(&format!("decompressed: {} bytes\n", written)));
+        assert!(stdout.contains(&format!("checksum:     {}\n", checksum)));
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compare_empty_file_has_no_ratio() {
+    let dir = test_dir("compare");
+    fs::write(dir.join("empty.txt"), "").unwrap();
+
+    let output = run(&dir, &["compare", "empty.txt"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("bytes (n/a)\n"), "{}", stdout);
+    assert!(!stdout.contains("NaN"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn piped_compressed_input_warns() {
+    let dir = test_dir("piped");
+    fs::write(dir.join("in.txt"), "hello hello world\n").unwrap();
+    assert!(run(&dir, &["compress", "in.txt", "in.huf"])
+        .status
+        .success());
+
+    // A pipe is always compressed a block at a time.
+    for (input, warns) in [("in.huf", true), ("in.txt", false)] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_huffman"))
+            .current_dir(&dir)
+            .args(["compress", "-", "-", "--verbose"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let bytes = fs::read(dir.join(input)).unwrap();
+        child.stdin.take().unwrap().write_all(&bytes).unwrap();
+
+        let output = child.wait_with_output().unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            stderr.contains("warning: input appears already compressed"),
+            warns
+        );
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
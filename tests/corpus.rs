@@ -0,0 +1,61 @@
+//! Round-trips every sample under `tests/corpus/` through the library.
+
+use std::{fs, path::Path};
+
+use huffman::{
+    container::{self, Metadata},
+    Huffman,
+};
+
+/// Compresses `data` the way the CLI does by default, as text if it is
+/// UTF-8 and in byte mode otherwise, then decodes it again.
+fn roundtrip(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut huffman = match std::str::from_utf8(data) {
+        Ok(text) => Huffman::from_input(text.to_owned()),
+        Err(_) => Huffman::from_bytes(data),
+    };
+    huffman.compress();
+
+    let mut bytes = Vec::new();
+    container::write_member_or_stored(
+        &huffman,
+        &huffman.get_compressed(),
+        &Metadata::default(),
+        1,
+        &mut bytes,
+    )
+    .map_err(|e| format!("compressing failed: {}", e))?;
+
+    container::decompress_bytes(&bytes).map_err(|e| format!("decompressing failed: {}", e))
+}
+
+#[test]
+fn corpus_round_trips() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut paths = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty(), "no samples in {}", dir.display());
+
+    let failures = paths
+        .iter()
+        .filter_map(|path| {
+            let data = fs::read(path).unwrap();
+            let name = path.file_name().unwrap().to_string_lossy();
+            match roundtrip(&data) {
+                Ok(output) if output == data => None,
+                Ok(output) => Some(format!(
+                    "{}: decoded {} bytes, expected {}",
+                    name,
+                    output.len(),
+                    data.len()
+                )),
+                Err(error) => Some(format!("{}: {}", name, error)),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}